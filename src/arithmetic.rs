@@ -0,0 +1,276 @@
+use super::object::Object;
+use std::fmt;
+use std::str::FromStr;
+
+// 整数演算が i64 に収まらないときの扱い。0 除算はどちらでもエラーになる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerMode {
+    // overflow を実行時エラーにする
+    #[default]
+    Checked,
+    // 2 の補数で折り返す
+    Wrapping,
+}
+
+impl FromStr for IntegerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(IntegerMode::Checked),
+            "wrapping" => Ok(IntegerMode::Wrapping),
+            _ => Err(format!(
+                "unknown integer mode: {} (expected checked or wrapping)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IntegerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegerMode::Checked => write!(f, "checked"),
+            IntegerMode::Wrapping => write!(f, "wrapping"),
+        }
+    }
+}
+
+// VM と evaluator で共通の整数の四則演算
+pub fn integer_infix(
+    mode: IntegerMode,
+    operator: &str,
+    left: i64,
+    right: i64,
+) -> Result<i64, String> {
+    if (operator == "/" || operator == "%") && right == 0 {
+        return Err(format!("division by zero: {} {} {}", left, operator, right));
+    }
+
+    let result = match (mode, operator) {
+        (IntegerMode::Checked, "+") => left.checked_add(right),
+        (IntegerMode::Checked, "-") => left.checked_sub(right),
+        (IntegerMode::Checked, "*") => left.checked_mul(right),
+        (IntegerMode::Checked, "/") => left.checked_div(right),
+        (IntegerMode::Checked, "%") => left.checked_rem(right),
+        (IntegerMode::Wrapping, "+") => Some(left.wrapping_add(right)),
+        (IntegerMode::Wrapping, "-") => Some(left.wrapping_sub(right)),
+        (IntegerMode::Wrapping, "*") => Some(left.wrapping_mul(right)),
+        (IntegerMode::Wrapping, "/") => Some(left.wrapping_div(right)),
+        (IntegerMode::Wrapping, "%") => Some(left.wrapping_rem(right)),
+        _ => return Err(format!("unknown integer operator: {}", operator)),
+    };
+    result.ok_or_else(|| format!("integer overflow: {} {} {}", left, operator, right))
+}
+
+pub fn integer_negate(mode: IntegerMode, value: i64) -> Result<i64, String> {
+    match mode {
+        IntegerMode::Checked => value
+            .checked_neg()
+            .ok_or_else(|| format!("integer overflow: -({})", value)),
+        IntegerMode::Wrapping => Ok(value.wrapping_neg()),
+    }
+}
+
+// VM と evaluator で共通の、演算できない値の組み合わせのエラー
+pub fn infix_error(operator: &str, left: &Object, right: &Object) -> String {
+    if left.type_name() != right.type_name() {
+        format!(
+            "type mismatch: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        )
+    } else {
+        format!(
+            "unknown operator: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        )
+    }
+}
+
+pub fn prefix_error(operator: &str, right: &Object) -> String {
+    format!("unknown operator: {}{}", operator, right.type_name())
+}
+
+// 浮動小数点数が混ざる演算では、整数も浮動小数点数にそろえる
+pub fn float_operands(left: &Object, right: &Object) -> Option<(f64, f64)> {
+    match (left, right) {
+        (Object::Float(l), Object::Float(r)) => Some((*l, *r)),
+        (Object::Integer(l), Object::Float(r)) => Some((*l as f64, *r)),
+        (Object::Float(l), Object::Integer(r)) => Some((*l, *r as f64)),
+        _ => None,
+    }
+}
+
+// 整数に合わせて 0 除算はエラーにする
+pub fn float_infix(operator: &str, left: f64, right: f64) -> Result<f64, String> {
+    match operator {
+        "+" => Ok(left + right),
+        "-" => Ok(left - right),
+        "*" => Ok(left * right),
+        "/" | "%" if right == 0.0 => Err(format!(
+            "division by zero: {:?} {} {:?}",
+            left, operator, right
+        )),
+        "/" => Ok(left / right),
+        "%" => Ok(left % right),
+        _ => Err(format!("unknown float operator: {}", operator)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::object::Object;
+    use super::{
+        float_infix, float_operands, infix_error, integer_infix, integer_negate, prefix_error,
+        IntegerMode,
+    };
+
+    #[test]
+    fn test_integer_infix() {
+        let tests = vec![
+            (IntegerMode::Checked, 1, "+", 2, Ok(3)),
+            (IntegerMode::Checked, 7, "/", -2, Ok(-3)),
+            (
+                IntegerMode::Checked,
+                i64::MAX,
+                "+",
+                1,
+                Err("integer overflow: 9223372036854775807 + 1"),
+            ),
+            (
+                IntegerMode::Checked,
+                i64::MIN,
+                "-",
+                1,
+                Err("integer overflow: -9223372036854775808 - 1"),
+            ),
+            (
+                IntegerMode::Checked,
+                i64::MAX,
+                "*",
+                2,
+                Err("integer overflow: 9223372036854775807 * 2"),
+            ),
+            (
+                IntegerMode::Checked,
+                i64::MIN,
+                "/",
+                -1,
+                Err("integer overflow: -9223372036854775808 / -1"),
+            ),
+            (
+                IntegerMode::Checked,
+                1,
+                "/",
+                0,
+                Err("division by zero: 1 / 0"),
+            ),
+            (IntegerMode::Checked, -7, "%", 3, Ok(-1)),
+            (
+                IntegerMode::Checked,
+                7,
+                "%",
+                0,
+                Err("division by zero: 7 % 0"),
+            ),
+            (
+                IntegerMode::Checked,
+                i64::MIN,
+                "%",
+                -1,
+                Err("integer overflow: -9223372036854775808 % -1"),
+            ),
+            (IntegerMode::Wrapping, i64::MAX, "+", 1, Ok(i64::MIN)),
+            (IntegerMode::Wrapping, i64::MIN, "%", -1, Ok(0)),
+            (IntegerMode::Wrapping, i64::MIN, "/", -1, Ok(i64::MIN)),
+            (
+                IntegerMode::Wrapping,
+                1,
+                "/",
+                0,
+                Err("division by zero: 1 / 0"),
+            ),
+        ];
+
+        for (mode, left, operator, right, expected) in tests {
+            assert_eq!(
+                integer_infix(mode, operator, left, right),
+                expected.map_err(|e| e.to_string()),
+                "{} {} {} ({})",
+                left,
+                operator,
+                right,
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn test_integer_negate() {
+        assert_eq!(integer_negate(IntegerMode::Checked, 5), Ok(-5));
+        assert_eq!(
+            integer_negate(IntegerMode::Checked, i64::MIN),
+            Err("integer overflow: -(-9223372036854775808)".to_string())
+        );
+        assert_eq!(
+            integer_negate(IntegerMode::Wrapping, i64::MIN),
+            Ok(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn test_float_operands() {
+        let tests = vec![
+            (Object::Float(1.5), Object::Float(2.0), Some((1.5, 2.0))),
+            (Object::Integer(1), Object::Float(2.5), Some((1.0, 2.5))),
+            (Object::Float(1.5), Object::Integer(2), Some((1.5, 2.0))),
+            (Object::Integer(1), Object::Integer(2), None),
+            (Object::Float(1.5), Object::Boolean(true), None),
+        ];
+
+        for (left, right, expected) in tests {
+            assert_eq!(
+                float_operands(&left, &right),
+                expected,
+                "{} {}",
+                left,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn test_float_infix() {
+        assert_eq!(float_infix("+", 0.5, 0.25), Ok(0.75));
+        assert_eq!(float_infix("/", 1.0, 4.0), Ok(0.25));
+        assert_eq!(float_infix("%", 5.5, 2.0), Ok(1.5));
+        assert_eq!(
+            float_infix("/", 1.0, 0.0),
+            Err("division by zero: 1.0 / 0.0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_operand_errors() {
+        assert_eq!(
+            infix_error("+", &Object::Integer(5), &Object::Boolean(true)),
+            "type mismatch: INTEGER + BOOLEAN"
+        );
+        assert_eq!(
+            infix_error(
+                "-",
+                &Object::String("a".to_string()),
+                &Object::String("b".to_string())
+            ),
+            "unknown operator: STRING - STRING"
+        );
+        assert_eq!(
+            prefix_error("-", &Object::Boolean(true)),
+            "unknown operator: -BOOLEAN"
+        );
+    }
+}
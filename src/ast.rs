@@ -1,10 +1,12 @@
 use super::enum_with_fmt;
+use super::token::Position;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
@@ -19,17 +21,37 @@ impl fmt::Display for Program {
 // Statement
 enum_with_fmt!(
     #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[allow(clippy::enum_variant_names)]
     pub enum Statement {
         LetStatement(LetStatement),
         ReturnStatement(ReturnStatement),
         ExpressionStatement(ExpressionStatement),
+        WhileStatement(WhileStatement),
+        ForStatement(ForStatement),
+        BreakStatement(BreakStatement),
+        ContinueStatement(ContinueStatement),
     }
 );
 
+impl Statement {
+    pub fn pos(&self) -> Position {
+        match self {
+            Statement::LetStatement(stmt) => stmt.pos,
+            Statement::ReturnStatement(stmt) => stmt.pos,
+            Statement::ExpressionStatement(stmt) => stmt.pos,
+            Statement::WhileStatement(stmt) => stmt.pos,
+            Statement::ForStatement(stmt) => stmt.pos,
+            Statement::BreakStatement(stmt) => stmt.pos,
+            Statement::ContinueStatement(stmt) => stmt.pos,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct LetStatement {
     pub name: Identifier,
     pub value: Expression,
+    pub pos: Position,
 }
 
 impl fmt::Display for LetStatement {
@@ -41,6 +63,7 @@ impl fmt::Display for LetStatement {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ReturnStatement {
     pub return_value: Expression,
+    pub pos: Position,
 }
 
 impl fmt::Display for ReturnStatement {
@@ -52,6 +75,7 @@ impl fmt::Display for ReturnStatement {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ExpressionStatement {
     pub expression: Expression,
+    pub pos: Position,
 }
 
 impl fmt::Display for ExpressionStatement {
@@ -60,6 +84,65 @@ impl fmt::Display for ExpressionStatement {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WhileStatement {
+    pub condition: Expression,
+    pub body: BlockStatement,
+    pub pos: Position,
+}
+
+impl fmt::Display for WhileStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "while{} {}", self.condition, self.body)
+    }
+}
+
+// for (x in xs) { } と for (k, v in xs) { }
+// value があるときは配列なら index と要素、ハッシュなら key と value を受け取る
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ForStatement {
+    pub key: Identifier,
+    pub value: Option<Identifier>,
+    pub iterable: Expression,
+    pub body: BlockStatement,
+    pub pos: Position,
+}
+
+impl fmt::Display for ForStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(
+                f,
+                "for({}, {} in {}) {}",
+                self.key, value, self.iterable, self.body
+            ),
+            None => write!(f, "for({} in {}) {}", self.key, self.iterable, self.body),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BreakStatement {
+    pub pos: Position,
+}
+
+impl fmt::Display for BreakStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "break;")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContinueStatement {
+    pub pos: Position,
+}
+
+impl fmt::Display for ContinueStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "continue;")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct BlockStatement {
     pub statements: Vec<Statement>,
@@ -77,6 +160,7 @@ enum_with_fmt!(
     pub enum Expression {
         Identifier(Identifier),
         IntegerLiteral(IntegerLiteral),
+        FloatLiteral(FloatLiteral),
         PrefixExpression(PrefixExpression),
         InfixExpression(InfixExpression),
         Boolean(Boolean),
@@ -84,16 +168,42 @@ enum_with_fmt!(
         FunctionLiteral(FunctionLiteral),
         CallExpression(CallExpression),
         StringLiteral(StringLiteral),
+        InterpolatedString(InterpolatedString),
         ArrayLiteral(ArrayLiteral),
         IndexExpression(IndexExpression),
         HashLiteral(HashLiteral),
         MacroLiteral(MacroLiteral),
+        AssignExpression(AssignExpression),
     }
 );
 
+impl Expression {
+    pub fn pos(&self) -> Position {
+        match self {
+            Expression::Identifier(exp) => exp.pos,
+            Expression::IntegerLiteral(exp) => exp.pos,
+            Expression::FloatLiteral(exp) => exp.pos,
+            Expression::PrefixExpression(exp) => exp.pos,
+            Expression::InfixExpression(exp) => exp.pos,
+            Expression::Boolean(exp) => exp.pos,
+            Expression::IfExpression(exp) => exp.pos,
+            Expression::FunctionLiteral(exp) => exp.pos,
+            Expression::CallExpression(exp) => exp.pos,
+            Expression::StringLiteral(exp) => exp.pos,
+            Expression::InterpolatedString(exp) => exp.pos,
+            Expression::ArrayLiteral(exp) => exp.pos,
+            Expression::IndexExpression(exp) => exp.pos,
+            Expression::HashLiteral(exp) => exp.pos,
+            Expression::MacroLiteral(exp) => exp.pos,
+            Expression::AssignExpression(exp) => exp.pos,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Identifier {
     pub value: String,
+    pub pos: Position,
 }
 
 impl fmt::Display for Identifier {
@@ -105,6 +215,7 @@ impl fmt::Display for Identifier {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct IntegerLiteral {
     pub value: i64,
+    pub pos: Position,
 }
 
 impl fmt::Display for IntegerLiteral {
@@ -113,10 +224,53 @@ impl fmt::Display for IntegerLiteral {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct FloatLiteral {
+    pub value: f64,
+    pub pos: Position,
+}
+
+// f64 は Eq や Hash を持たないので、bit 列で比べる
+impl PartialEq for FloatLiteral {
+    fn eq(&self, other: &Self) -> bool {
+        self.value.to_bits() == other.value.to_bits() && self.pos == other.pos
+    }
+}
+
+impl Eq for FloatLiteral {}
+
+impl Hash for FloatLiteral {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.to_bits().hash(state);
+        self.pos.hash(state);
+    }
+}
+
+impl PartialOrd for FloatLiteral {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloatLiteral {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .total_cmp(&other.value)
+            .then(self.pos.cmp(&other.pos))
+    }
+}
+
+impl fmt::Display for FloatLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.value)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PrefixExpression {
     pub operator: String,
     pub right: Box<Expression>,
+    pub pos: Position,
 }
 
 impl fmt::Display for PrefixExpression {
@@ -130,6 +284,7 @@ pub struct InfixExpression {
     pub left: Box<Expression>,
     pub operator: String,
     pub right: Box<Expression>,
+    pub pos: Position,
 }
 
 impl fmt::Display for InfixExpression {
@@ -141,6 +296,7 @@ impl fmt::Display for InfixExpression {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Boolean {
     pub value: bool,
+    pub pos: Position,
 }
 
 impl fmt::Display for Boolean {
@@ -149,11 +305,34 @@ impl fmt::Display for Boolean {
     }
 }
 
+// x = v, xs[i] = v, x += v など。target は識別子か、識別子から始まる index 式
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AssignExpression {
+    pub target: Box<Expression>,
+    pub operator: String,
+    pub value: Box<Expression>,
+    pub pos: Position,
+}
+
+impl AssignExpression {
+    // += なら + のように、代入の前に行う演算子
+    pub fn infix_operator(&self) -> Option<&str> {
+        self.operator.strip_suffix('=').filter(|op| !op.is_empty())
+    }
+}
+
+impl fmt::Display for AssignExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({} {} {})", self.target, self.operator, self.value)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct IfExpression {
     pub condition: Box<Expression>,
     pub consequence: Box<BlockStatement>,
     pub alternative: Option<Box<BlockStatement>>,
+    pub pos: Position,
 }
 
 impl fmt::Display for IfExpression {
@@ -174,6 +353,7 @@ impl fmt::Display for IfExpression {
 pub struct FunctionLiteral {
     pub parameters: Vec<Identifier>,
     pub body: Box<BlockStatement>,
+    pub pos: Position,
 }
 
 impl fmt::Display for FunctionLiteral {
@@ -195,6 +375,7 @@ impl fmt::Display for FunctionLiteral {
 pub struct CallExpression {
     pub function: Box<Expression>,
     pub arguments: Vec<Expression>,
+    pub pos: Position,
 }
 
 impl fmt::Display for CallExpression {
@@ -215,6 +396,7 @@ impl fmt::Display for CallExpression {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct StringLiteral {
     pub value: String,
+    pub pos: Position,
 }
 
 impl fmt::Display for StringLiteral {
@@ -223,9 +405,30 @@ impl fmt::Display for StringLiteral {
     }
 }
 
+// "a ${x} b" のように式を埋め込んだ文字列。文字列の断片と埋め込んだ式を順に持つ
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InterpolatedString {
+    pub parts: Vec<Expression>,
+    pub pos: Position,
+}
+
+impl fmt::Display for InterpolatedString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for part in &self.parts {
+            match part {
+                Expression::StringLiteral(text) => write!(f, "{}", text.value)?,
+                exp => write!(f, "${{{}}}", exp)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ArrayLiteral {
     pub elements: Vec<Expression>,
+    pub pos: Position,
 }
 
 impl fmt::Display for ArrayLiteral {
@@ -246,6 +449,7 @@ impl fmt::Display for ArrayLiteral {
 pub struct IndexExpression {
     pub left: Box<Expression>,
     pub index: Box<Expression>,
+    pub pos: Position,
 }
 
 impl fmt::Display for IndexExpression {
@@ -256,7 +460,9 @@ impl fmt::Display for IndexExpression {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct HashLiteral {
-    pub pairs: BTreeMap<Expression, Expression>,
+    // 書いた順に並べる
+    pub pairs: Vec<(Expression, Expression)>,
+    pub pos: Position,
 }
 
 impl fmt::Display for HashLiteral {
@@ -277,6 +483,7 @@ impl fmt::Display for HashLiteral {
 pub struct MacroLiteral {
     pub parameters: Vec<Identifier>,
     pub body: Box<BlockStatement>,
+    pub pos: Position,
 }
 
 impl fmt::Display for MacroLiteral {
@@ -306,7 +513,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
     match target {
         Node::Program(mut program) => {
             for i in 0..program.statements.len() {
-                let statement = program.statements.swap_remove(i);
+                let statement = program.statements.remove(i);
                 if let Node::Statement(stmt) =
                     modify(Node::Statement(statement), Rc::clone(&modifier))
                 {
@@ -317,7 +524,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
         }
         Node::BlockStatement(mut node) => {
             for i in 0..node.statements.len() {
-                let statement = node.statements.swap_remove(i);
+                let statement = node.statements.remove(i);
                 if let Node::Statement(stmt) =
                     modify(Node::Statement(statement), Rc::clone(&modifier))
                 {
@@ -331,7 +538,10 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                 modify(Node::Expression(node.expression), Rc::clone(&modifier))
             {
                 (&mut *modifier.borrow_mut())(Node::Statement(Statement::ExpressionStatement(
-                    ExpressionStatement { expression },
+                    ExpressionStatement {
+                        expression,
+                        pos: node.pos,
+                    },
                 )))
             } else {
                 unreachable!()
@@ -349,6 +559,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                             left: Box::new(left),
                             right: Box::new(right),
                             operator: node.operator,
+                            pos: node.pos,
                         },
                     )))
                 } else {
@@ -366,6 +577,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                     PrefixExpression {
                         right: Box::new(right),
                         operator: node.operator,
+                        pos: node.pos,
                     },
                 )))
             } else {
@@ -383,6 +595,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                         IndexExpression {
                             left: Box::new(left),
                             index: Box::new(index),
+                            pos: node.pos,
                         },
                     )))
                 } else {
@@ -410,6 +623,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                                     condition: Box::new(condition),
                                     consequence: Box::new(consequence),
                                     alternative: Some(Box::new(alternative)),
+                                    pos: node.pos,
                                 }),
                             ))
                         } else {
@@ -421,6 +635,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                                 condition: Box::new(condition),
                                 consequence: Box::new(consequence),
                                 alternative: None,
+                                pos: node.pos,
                             },
                         )))
                     }
@@ -433,7 +648,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
         }
         Node::Expression(Expression::FunctionLiteral(mut node)) => {
             for i in 0..node.parameters.len() {
-                let identifier = node.parameters.swap_remove(i);
+                let identifier = node.parameters.remove(i);
                 if let Node::Expression(Expression::Identifier(ident)) = modify(
                     Node::Expression(Expression::Identifier(identifier)),
                     Rc::clone(&modifier),
@@ -448,6 +663,30 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                     FunctionLiteral {
                         parameters: node.parameters,
                         body: Box::new(body),
+                        pos: node.pos,
+                    },
+                )))
+            } else {
+                unreachable!()
+            }
+        }
+        Node::Expression(Expression::CallExpression(mut node)) => {
+            if let Node::Expression(function) =
+                modify(Node::Expression(*node.function), Rc::clone(&modifier))
+            {
+                for i in 0..node.arguments.len() {
+                    let argument = node.arguments.remove(i);
+                    if let Node::Expression(arg) =
+                        modify(Node::Expression(argument), Rc::clone(&modifier))
+                    {
+                        node.arguments.insert(i, arg);
+                    }
+                }
+                (*modifier.borrow_mut())(Node::Expression(Expression::CallExpression(
+                    CallExpression {
+                        function: Box::new(function),
+                        arguments: node.arguments,
+                        pos: node.pos,
                     },
                 )))
             } else {
@@ -456,7 +695,7 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
         }
         Node::Expression(Expression::ArrayLiteral(mut node)) => {
             for i in 0..node.elements.len() {
-                let element = node.elements.swap_remove(i);
+                let element = node.elements.remove(i);
                 if let Node::Expression(elem) =
                     modify(Node::Expression(element), Rc::clone(&modifier))
                 {
@@ -465,19 +704,29 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
             }
             (&mut *modifier.borrow_mut())(Node::Expression(Expression::ArrayLiteral(node)))
         }
+        Node::Expression(Expression::InterpolatedString(mut node)) => {
+            for i in 0..node.parts.len() {
+                let part = node.parts.remove(i);
+                if let Node::Expression(p) = modify(Node::Expression(part), Rc::clone(&modifier)) {
+                    node.parts.insert(i, p);
+                }
+            }
+            (*modifier.borrow_mut())(Node::Expression(Expression::InterpolatedString(node)))
+        }
         Node::Expression(Expression::HashLiteral(node)) => {
-            let mut new_pairs = BTreeMap::new();
+            let mut new_pairs = vec![];
             for (key, value) in node.pairs {
                 if let Node::Expression(k) = modify(Node::Expression(key), Rc::clone(&modifier)) {
                     if let Node::Expression(v) =
                         modify(Node::Expression(value), Rc::clone(&modifier))
                     {
-                        new_pairs.insert(k, v);
+                        new_pairs.push((k, v));
                     }
                 }
             }
             (&mut *modifier.borrow_mut())(Node::Expression(Expression::HashLiteral(HashLiteral {
                 pairs: new_pairs,
+                pos: node.pos,
             })))
         }
         Node::Statement(Statement::ReturnStatement(node)) => {
@@ -485,7 +734,10 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                 modify(Node::Expression(node.return_value), Rc::clone(&modifier))
             {
                 (&mut *modifier.borrow_mut())(Node::Statement(Statement::ReturnStatement(
-                    ReturnStatement { return_value },
+                    ReturnStatement {
+                        return_value,
+                        pos: node.pos,
+                    },
                 )))
             } else {
                 unreachable!()
@@ -499,12 +751,79 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
                     LetStatement {
                         name: node.name,
                         value,
+                        pos: node.pos,
                     },
                 )))
             } else {
                 unreachable!()
             }
         }
+        Node::Expression(Expression::AssignExpression(node)) => {
+            if let Node::Expression(target) =
+                modify(Node::Expression(*node.target), Rc::clone(&modifier))
+            {
+                if let Node::Expression(value) =
+                    modify(Node::Expression(*node.value), Rc::clone(&modifier))
+                {
+                    (*modifier.borrow_mut())(Node::Expression(Expression::AssignExpression(
+                        AssignExpression {
+                            target: Box::new(target),
+                            operator: node.operator,
+                            value: Box::new(value),
+                            pos: node.pos,
+                        },
+                    )))
+                } else {
+                    unreachable!()
+                }
+            } else {
+                unreachable!()
+            }
+        }
+        Node::Statement(Statement::WhileStatement(node)) => {
+            if let Node::Expression(condition) =
+                modify(Node::Expression(node.condition), Rc::clone(&modifier))
+            {
+                if let Node::BlockStatement(body) =
+                    modify(Node::BlockStatement(node.body), Rc::clone(&modifier))
+                {
+                    (*modifier.borrow_mut())(Node::Statement(Statement::WhileStatement(
+                        WhileStatement {
+                            condition,
+                            body,
+                            pos: node.pos,
+                        },
+                    )))
+                } else {
+                    unreachable!()
+                }
+            } else {
+                unreachable!()
+            }
+        }
+        Node::Statement(Statement::ForStatement(node)) => {
+            if let Node::Expression(iterable) =
+                modify(Node::Expression(node.iterable), Rc::clone(&modifier))
+            {
+                if let Node::BlockStatement(body) =
+                    modify(Node::BlockStatement(node.body), Rc::clone(&modifier))
+                {
+                    (*modifier.borrow_mut())(Node::Statement(Statement::ForStatement(
+                        ForStatement {
+                            key: node.key,
+                            value: node.value,
+                            iterable,
+                            body,
+                            pos: node.pos,
+                        },
+                    )))
+                } else {
+                    unreachable!()
+                }
+            } else {
+                unreachable!()
+            }
+        }
         _ => (&mut *modifier.borrow_mut())(target),
     }
 }
@@ -512,9 +831,10 @@ pub fn modify<P: FnMut(Node) -> Node>(target: Node, modifier: Rc<RefCell<P>>) ->
 #[cfg(test)]
 mod tests {
     use super::{
-        modify, ArrayLiteral, BTreeMap, BlockStatement, Expression, ExpressionStatement,
-        FunctionLiteral, HashLiteral, Identifier, IfExpression, IndexExpression, InfixExpression,
-        IntegerLiteral, LetStatement, Node, PrefixExpression, Program, ReturnStatement, Statement,
+        modify, ArrayLiteral, AssignExpression, BlockStatement, CallExpression, Expression,
+        ExpressionStatement, ForStatement, FunctionLiteral, HashLiteral, Identifier, IfExpression,
+        IndexExpression, InfixExpression, IntegerLiteral, LetStatement, Node, Position,
+        PrefixExpression, Program, ReturnStatement, Statement, WhileStatement,
     };
     use std::cell::RefCell;
     use std::rc::Rc;
@@ -526,15 +846,20 @@ mod tests {
                 Statement::LetStatement(LetStatement {
                     name: Identifier {
                         value: "myVar".to_string(),
+                        pos: Position::default(),
                     },
                     value: Expression::Identifier(Identifier {
                         value: "anotherVar".to_string(),
+                        pos: Position::default(),
                     }),
+                    pos: Position::default(),
                 }),
                 Statement::ReturnStatement(ReturnStatement {
                     return_value: Expression::Identifier(Identifier {
                         value: "returnVar".to_string(),
+                        pos: Position::default(),
                     }),
+                    pos: Position::default(),
                 }),
             ],
         };
@@ -548,28 +873,33 @@ mod tests {
     #[test]
     fn test_modify() {
         fn one() -> Expression {
-            Expression::IntegerLiteral(IntegerLiteral { value: 1 })
+            Expression::IntegerLiteral(IntegerLiteral {
+                value: 1,
+                pos: Position::default(),
+            })
         }
         fn two() -> Expression {
-            Expression::IntegerLiteral(IntegerLiteral { value: 2 })
+            Expression::IntegerLiteral(IntegerLiteral {
+                value: 2,
+                pos: Position::default(),
+            })
         }
         let turn_one_into_two = |node: Node| -> Node {
             if let Node::Expression(Expression::IntegerLiteral(integer)) = &node {
                 if integer.value != 1 {
                     return node;
                 }
-                Node::Expression(Expression::IntegerLiteral(IntegerLiteral { value: 2 }))
+                Node::Expression(Expression::IntegerLiteral(IntegerLiteral {
+                    value: 2,
+                    pos: Position::default(),
+                }))
             } else {
                 node
             }
         };
 
-        let mut input_map = BTreeMap::new();
-        input_map.insert(one(), one());
-        input_map.insert(one(), one());
-        let mut expected_map = BTreeMap::new();
-        expected_map.insert(two(), two());
-        expected_map.insert(two(), two());
+        let input_map = vec![(one(), one()), (one(), one())];
+        let expected_map = vec![(two(), two()), (two(), two())];
 
         let mut tests = vec![
             (Node::Expression(one()), Node::Expression(two())),
@@ -577,11 +907,13 @@ mod tests {
                 Node::Program(Program {
                     statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                         expression: one(),
+                        pos: Position::default(),
                     })],
                 }),
                 Node::Program(Program {
                     statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                         expression: two(),
+                        pos: Position::default(),
                     })],
                 }),
             ),
@@ -590,11 +922,13 @@ mod tests {
                     left: Box::new(one()),
                     operator: "+".to_string(),
                     right: Box::new(two()),
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::InfixExpression(InfixExpression {
                     left: Box::new(two()),
                     operator: "+".to_string(),
                     right: Box::new(two()),
+                    pos: Position::default(),
                 })),
             ),
             (
@@ -602,31 +936,49 @@ mod tests {
                     left: Box::new(two()),
                     operator: "+".to_string(),
                     right: Box::new(one()),
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::InfixExpression(InfixExpression {
                     left: Box::new(two()),
                     operator: "+".to_string(),
                     right: Box::new(two()),
+                    pos: Position::default(),
                 })),
             ),
             (
                 Node::Expression(Expression::PrefixExpression(PrefixExpression {
                     operator: "-".to_string(),
                     right: Box::new(one()),
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::PrefixExpression(PrefixExpression {
                     operator: "-".to_string(),
                     right: Box::new(two()),
+                    pos: Position::default(),
                 })),
             ),
             (
                 Node::Expression(Expression::IndexExpression(IndexExpression {
                     left: Box::new(one()),
                     index: Box::new(one()),
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::IndexExpression(IndexExpression {
                     left: Box::new(two()),
                     index: Box::new(two()),
+                    pos: Position::default(),
+                })),
+            ),
+            (
+                Node::Expression(Expression::CallExpression(CallExpression {
+                    function: Box::new(one()),
+                    arguments: vec![one(), one()],
+                    pos: Position::default(),
+                })),
+                Node::Expression(Expression::CallExpression(CallExpression {
+                    function: Box::new(two()),
+                    arguments: vec![two(), two()],
+                    pos: Position::default(),
                 })),
             ),
             (
@@ -635,48 +987,60 @@ mod tests {
                     consequence: Box::new(BlockStatement {
                         statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                             expression: one(),
+                            pos: Position::default(),
                         })],
                     }),
                     alternative: Some(Box::new(BlockStatement {
                         statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                             expression: one(),
+                            pos: Position::default(),
                         })],
                     })),
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::IfExpression(IfExpression {
                     condition: Box::new(two()),
                     consequence: Box::new(BlockStatement {
                         statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                             expression: two(),
+                            pos: Position::default(),
                         })],
                     }),
                     alternative: Some(Box::new(BlockStatement {
                         statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                             expression: two(),
+                            pos: Position::default(),
                         })],
                     })),
+                    pos: Position::default(),
                 })),
             ),
             (
                 Node::Statement(Statement::ReturnStatement(ReturnStatement {
                     return_value: one(),
+                    pos: Position::default(),
                 })),
                 Node::Statement(Statement::ReturnStatement(ReturnStatement {
                     return_value: two(),
+                    pos: Position::default(),
                 })),
             ),
             (
                 Node::Statement(Statement::LetStatement(LetStatement {
                     name: Identifier {
                         value: "value".to_string(),
+                        pos: Position::default(),
                     },
                     value: one(),
+                    pos: Position::default(),
                 })),
                 Node::Statement(Statement::LetStatement(LetStatement {
                     name: Identifier {
                         value: "value".to_string(),
+                        pos: Position::default(),
                     },
                     value: two(),
+                    pos: Position::default(),
                 })),
             ),
             (
@@ -685,30 +1049,122 @@ mod tests {
                     body: Box::new(BlockStatement {
                         statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                             expression: one(),
+                            pos: Position::default(),
                         })],
                     }),
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::FunctionLiteral(FunctionLiteral {
                     parameters: vec![],
                     body: Box::new(BlockStatement {
                         statements: vec![Statement::ExpressionStatement(ExpressionStatement {
                             expression: two(),
+                            pos: Position::default(),
                         })],
                     }),
+                    pos: Position::default(),
+                })),
+            ),
+            (
+                Node::Expression(Expression::AssignExpression(AssignExpression {
+                    target: Box::new(Expression::IndexExpression(IndexExpression {
+                        left: Box::new(Expression::Identifier(Identifier {
+                            value: "x".to_string(),
+                            pos: Position::default(),
+                        })),
+                        index: Box::new(one()),
+                        pos: Position::default(),
+                    })),
+                    operator: "=".to_string(),
+                    value: Box::new(one()),
+                    pos: Position::default(),
+                })),
+                Node::Expression(Expression::AssignExpression(AssignExpression {
+                    target: Box::new(Expression::IndexExpression(IndexExpression {
+                        left: Box::new(Expression::Identifier(Identifier {
+                            value: "x".to_string(),
+                            pos: Position::default(),
+                        })),
+                        index: Box::new(two()),
+                        pos: Position::default(),
+                    })),
+                    operator: "=".to_string(),
+                    value: Box::new(two()),
+                    pos: Position::default(),
+                })),
+            ),
+            (
+                Node::Statement(Statement::WhileStatement(WhileStatement {
+                    condition: one(),
+                    body: BlockStatement {
+                        statements: vec![Statement::ExpressionStatement(ExpressionStatement {
+                            expression: one(),
+                            pos: Position::default(),
+                        })],
+                    },
+                    pos: Position::default(),
+                })),
+                Node::Statement(Statement::WhileStatement(WhileStatement {
+                    condition: two(),
+                    body: BlockStatement {
+                        statements: vec![Statement::ExpressionStatement(ExpressionStatement {
+                            expression: two(),
+                            pos: Position::default(),
+                        })],
+                    },
+                    pos: Position::default(),
+                })),
+            ),
+            (
+                Node::Statement(Statement::ForStatement(ForStatement {
+                    key: Identifier {
+                        value: "x".to_string(),
+                        pos: Position::default(),
+                    },
+                    value: None,
+                    iterable: one(),
+                    body: BlockStatement {
+                        statements: vec![Statement::ExpressionStatement(ExpressionStatement {
+                            expression: one(),
+                            pos: Position::default(),
+                        })],
+                    },
+                    pos: Position::default(),
+                })),
+                Node::Statement(Statement::ForStatement(ForStatement {
+                    key: Identifier {
+                        value: "x".to_string(),
+                        pos: Position::default(),
+                    },
+                    value: None,
+                    iterable: two(),
+                    body: BlockStatement {
+                        statements: vec![Statement::ExpressionStatement(ExpressionStatement {
+                            expression: two(),
+                            pos: Position::default(),
+                        })],
+                    },
+                    pos: Position::default(),
                 })),
             ),
             (
                 Node::Expression(Expression::ArrayLiteral(ArrayLiteral {
                     elements: vec![one(), one()],
+                    pos: Position::default(),
                 })),
                 Node::Expression(Expression::ArrayLiteral(ArrayLiteral {
                     elements: vec![two(), two()],
+                    pos: Position::default(),
                 })),
             ),
             (
-                Node::Expression(Expression::HashLiteral(HashLiteral { pairs: input_map })),
+                Node::Expression(Expression::HashLiteral(HashLiteral {
+                    pairs: input_map,
+                    pos: Position::default(),
+                })),
                 Node::Expression(Expression::HashLiteral(HashLiteral {
                     pairs: expected_map,
+                    pos: Position::default(),
                 })),
             ),
         ];
@@ -719,4 +1175,40 @@ mod tests {
             assert_eq!(modified, expected);
         }
     }
+
+    #[test]
+    fn test_modify_keeps_order() {
+        fn int(value: i64) -> Expression {
+            Expression::IntegerLiteral(IntegerLiteral {
+                value,
+                pos: Position::default(),
+            })
+        }
+        fn stmt(value: i64) -> Statement {
+            Statement::ExpressionStatement(ExpressionStatement {
+                expression: int(value),
+                pos: Position::default(),
+            })
+        }
+
+        let program = Node::Program(Program {
+            statements: vec![stmt(1), stmt(2), stmt(3), stmt(4)],
+        });
+        let array = Node::Expression(Expression::ArrayLiteral(ArrayLiteral {
+            elements: vec![int(1), int(2), int(3), int(4)],
+            pos: Position::default(),
+        }));
+
+        let identity = |node: Node| -> Node { node };
+        if let Node::Program(modified) = modify(program, Rc::new(RefCell::new(identity))) {
+            assert_eq!(format!("{}", modified), "1234");
+        } else {
+            assert!(false, "modified node is not Program")
+        }
+        if let Node::Expression(modified) = modify(array, Rc::new(RefCell::new(identity))) {
+            assert_eq!(format!("{}", modified), "[1, 2, 3, 4]");
+        } else {
+            assert!(false, "modified node is not Expression")
+        }
+    }
 }
@@ -0,0 +1,506 @@
+use super::code::{lookup, read_operands, Instructions, Opcode};
+use super::object::builtin::BUILTINS;
+use super::object::{CompiledFunction, Object};
+use super::token::Position;
+use std::convert::TryInto;
+use std::fmt;
+
+/*
+compile 済みプログラムのファイル形式 (数値はすべて big endian)
+
+```
+magic        "WBC\0"
+version      u16
+source_name  str
+main         function
+constants    u32 個の constant
+
+str          u32 の長さ + UTF-8
+function     num_locals u32, num_parameters u32,
+             instructions (u32 の長さ + bytes),
+             positions (u32 個の offset u32, line u32, column u32)
+constant     tag u8 + 値
+             0: Integer i64, 1: String str, 2: CompiledFunction function,
+             3: Float f64 (IEEE 754 の bit 列)
+```
+*/
+const MAGIC: &[u8; 4] = b"WBC\0";
+pub const VERSION: u16 = 1;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_COMPILED_FUNCTION: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+// OpGetLocal の番号は u8 なので、それより多い local は持てない
+const MAX_LOCALS: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub struct BytecodeFile {
+    pub source_name: String,
+    pub main: CompiledFunction,
+    pub constants: Vec<Object>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FormatError {
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+fn format_error<T>(message: String) -> Result<T, FormatError> {
+    Err(FormatError { message })
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+impl BytecodeFile {
+    pub fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        let mut w = Writer { buf: vec![] };
+        w.buf.extend_from_slice(MAGIC);
+        w.write_u16(VERSION);
+        w.write_str(&self.source_name);
+        w.write_function(&self.main);
+
+        w.write_u32(self.constants.len());
+        for constant in &self.constants {
+            match constant {
+                Object::Integer(value) => {
+                    w.write_u8(TAG_INTEGER);
+                    w.buf.extend_from_slice(&value.to_be_bytes());
+                }
+                Object::String(value) => {
+                    w.write_u8(TAG_STRING);
+                    w.write_str(value);
+                }
+                Object::CompiledFunction(func) => {
+                    w.write_u8(TAG_COMPILED_FUNCTION);
+                    w.write_function(func);
+                }
+                Object::Float(value) => {
+                    w.write_u8(TAG_FLOAT);
+                    w.buf.extend_from_slice(&value.to_bits().to_be_bytes());
+                }
+                other => return format_error(format!("cannot serialize constant: {}", other)),
+            }
+        }
+        Ok(w.buf)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<BytecodeFile, FormatError> {
+        if !is_bytecode(bytes) {
+            return format_error(String::from("not a wacir bytecode file"));
+        }
+        let mut r = Reader {
+            bytes,
+            offset: MAGIC.len(),
+        };
+
+        let version = r.read_u16()?;
+        if version != VERSION {
+            return format_error(format!(
+                "unsupported bytecode version: {} (expected {})",
+                version, VERSION
+            ));
+        }
+        let source_name = r.read_str()?;
+        let main = r.read_function()?;
+
+        let count = r.read_u32()?;
+        let mut constants = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let constant = match r.read_u8()? {
+                TAG_INTEGER => Object::Integer(i64::from_be_bytes(r.read_array()?)),
+                TAG_STRING => Object::String(r.read_str()?),
+                TAG_COMPILED_FUNCTION => Object::CompiledFunction(r.read_function()?),
+                TAG_FLOAT => Object::Float(f64::from_bits(u64::from_be_bytes(r.read_array()?))),
+                tag => return format_error(format!("unknown constant tag: {}", tag)),
+            };
+            constants.push(constant);
+        }
+        if r.offset != bytes.len() {
+            return format_error(format!("unexpected trailing bytes at offset {}", r.offset));
+        }
+
+        validate(&main, &constants)?;
+        for constant in &constants {
+            if let Object::CompiledFunction(func) = constant {
+                validate(func, &constants)?;
+            }
+        }
+
+        Ok(BytecodeFile {
+            source_name,
+            main,
+            constants,
+        })
+    }
+}
+
+// VM が panic しないよう、opcode と constant、builtin、local の参照先を確かめておく。
+// stack の深さは実行してみないと分からないので VM が確かめる
+fn validate(func: &CompiledFunction, constants: &[Object]) -> Result<(), FormatError> {
+    if func.num_locals > MAX_LOCALS || func.num_parameters > func.num_locals {
+        return format_error(format!(
+            "invalid number of locals: {} locals, {} parameters",
+            func.num_locals, func.num_parameters
+        ));
+    }
+    let ins = &func.instructions;
+    // 命令の途中へ飛ぶと別の命令として読まれるので、飛び先は命令の先頭か末尾に限る
+    let mut starts = vec![false; ins.0.len() + 1];
+    starts[ins.0.len()] = true;
+    let mut jumps = vec![];
+    let mut i = 0;
+    while i < ins.0.len() {
+        starts[i] = true;
+        let op = match Opcode::from_byte(ins.0[i]) {
+            Some(op) => op,
+            None => return format_error(format!("unknown opcode {} at {}", ins.0[i], i)),
+        };
+        let def = lookup(&op);
+        let width: usize = def.operand_width.iter().sum();
+        if i + 1 + width > ins.0.len() {
+            return format_error(format!("truncated operands for {} at {}", def.name, i));
+        }
+        let (operands, read) = read_operands(&def, &ins.0[i + 1..]);
+
+        match op {
+            Opcode::OpConstant if operands[0] >= constants.len() => {
+                return format_error(format!("constant {} out of range at {}", operands[0], i));
+            }
+            Opcode::OpClosure => match constants.get(operands[0]) {
+                Some(Object::CompiledFunction(_)) => {}
+                _ => return format_error(format!("not a function: constant {}", operands[0])),
+            },
+            Opcode::OpJump
+            | Opcode::OpJumpNotTruthy
+            | Opcode::OpJumpNotTruthyOrPop
+            | Opcode::OpJumpTruthyOrPop => jumps.push((i, operands[0])),
+            Opcode::OpGetBuiltin if operands[0] >= BUILTINS.len() => {
+                return format_error(format!("builtin {} out of range at {}", operands[0], i));
+            }
+            Opcode::OpGetLocal | Opcode::OpSetLocal if operands[0] >= func.num_locals => {
+                return format_error(format!("local {} out of range at {}", operands[0], i));
+            }
+            Opcode::OpSetIndex if operands[0] == 0 => {
+                return format_error(format!("no index to assign at {}", i));
+            }
+            // 0 はただの代入、それ以外は += などの演算
+            Opcode::OpSetIndex
+                if operands[1] != 0
+                    && ![
+                        Opcode::OpAdd,
+                        Opcode::OpSub,
+                        Opcode::OpMul,
+                        Opcode::OpDiv,
+                        Opcode::OpMod,
+                    ]
+                    .iter()
+                    .any(|op| op.byte() as usize == operands[1]) =>
+            {
+                return format_error(format!(
+                    "unknown assignment operator {} at {}",
+                    operands[1], i
+                ));
+            }
+            _ => {}
+        }
+        i += 1 + read;
+    }
+    for (i, target) in jumps {
+        if !starts.get(target).copied().unwrap_or(false) {
+            return format_error(format!("invalid jump target {} at {}", target, i));
+        }
+    }
+    Ok(())
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn write_u32(&mut self, value: usize) {
+        self.buf.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_u32(value.len());
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_function(&mut self, func: &CompiledFunction) {
+        self.write_u32(func.num_locals);
+        self.write_u32(func.num_parameters);
+        self.write_u32(func.instructions.0.len());
+        self.buf.extend_from_slice(&func.instructions.0);
+        self.write_u32(func.positions.len());
+        for (offset, pos) in &func.positions {
+            self.write_u32(*offset);
+            self.write_u32(pos.line);
+            self.write_u32(pos.column);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() - self.offset < len {
+            return format_error(format!("unexpected end of file at offset {}", self.offset));
+        }
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<usize, FormatError> {
+        Ok(u32::from_be_bytes(self.read_array()?) as usize)
+    }
+
+    fn read_str(&mut self) -> Result<String, FormatError> {
+        let len = self.read_u32()?;
+        match String::from_utf8(self.read_bytes(len)?.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => format_error(format!("invalid UTF-8 string at offset {}", self.offset)),
+        }
+    }
+
+    fn read_function(&mut self) -> Result<CompiledFunction, FormatError> {
+        let num_locals = self.read_u32()?;
+        let num_parameters = self.read_u32()?;
+        let len = self.read_u32()?;
+        let instructions = Instructions(self.read_bytes(len)?.to_vec());
+
+        let count = self.read_u32()?;
+        let mut positions = Vec::with_capacity(count.min(self.bytes.len()));
+        for _ in 0..count {
+            let offset = self.read_u32()?;
+            let line = self.read_u32()?;
+            let column = self.read_u32()?;
+            positions.push((offset, Position::new(line, column)));
+        }
+
+        Ok(CompiledFunction {
+            instructions,
+            num_locals,
+            num_parameters,
+            positions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::code::make_with_operands;
+    use super::*;
+
+    fn sample() -> BytecodeFile {
+        let func = CompiledFunction {
+            instructions: Instructions(
+                vec![
+                    make_with_operands(Opcode::OpGetLocal, &[0]).0,
+                    make_with_operands(Opcode::OpConstant, &[1]).0,
+                    vec![Opcode::OpAdd.byte(), Opcode::OpReturnValue.byte()],
+                ]
+                .concat(),
+            ),
+            num_locals: 1,
+            num_parameters: 1,
+            positions: vec![(0, Position::new(1, 16)), (3, Position::new(1, 18))],
+        };
+        BytecodeFile {
+            source_name: "add.monkey".to_string(),
+            main: CompiledFunction {
+                instructions: Instructions(
+                    vec![
+                        make_with_operands(Opcode::OpClosure, &[0, 0]).0,
+                        make_with_operands(Opcode::OpConstant, &[2]).0,
+                        make_with_operands(Opcode::OpCall, &[1]).0,
+                        vec![Opcode::OpPop.byte()],
+                    ]
+                    .concat(),
+                ),
+                num_locals: 0,
+                num_parameters: 0,
+                positions: vec![(0, Position::new(1, 1))],
+            },
+            constants: vec![
+                Object::CompiledFunction(func),
+                Object::Integer(-2),
+                Object::Float(0.1),
+                Object::String("héllo".to_string()),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = sample();
+        let bytes = file.encode().unwrap();
+
+        assert!(is_bytecode(&bytes));
+        assert_eq!(&bytes[4..6], &VERSION.to_be_bytes());
+        assert_eq!(BytecodeFile::decode(&bytes), Ok(file));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = sample().encode().unwrap();
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[5] = 99;
+        let mut unknown_tag = bytes.clone();
+        let tag_offset = bytes.len() - (1 + 4 + "héllo".len());
+        unknown_tag[tag_offset] = 9;
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+
+        let tests = vec![
+            (b"puts(1)".to_vec(), "not a wacir bytecode file"),
+            (
+                wrong_version,
+                "unsupported bytecode version: 99 (expected 1)",
+            ),
+            (
+                bytes[..bytes.len() - 3].to_vec(),
+                "unexpected end of file at offset",
+            ),
+            (unknown_tag, "unknown constant tag: 9"),
+            (trailing, "unexpected trailing bytes"),
+        ];
+
+        for (input, expected) in tests {
+            match BytecodeFile::decode(&input) {
+                Ok(_) => assert!(false, "expected error: {}", expected),
+                Err(err) => assert!(err.message.starts_with(expected), "{}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_instructions() {
+        let equal = Opcode::OpEqual.byte() as usize;
+        let tests = vec![
+            (
+                make_with_operands(Opcode::OpConstant, &[4]),
+                "constant 4 out of range at 0".to_string(),
+            ),
+            (
+                Instructions(vec![255]),
+                "unknown opcode 255 at 0".to_string(),
+            ),
+            (
+                make_with_operands(Opcode::OpGetBuiltin, &[200]),
+                "builtin 200 out of range at 0".to_string(),
+            ),
+            // main には local がない
+            (
+                make_with_operands(Opcode::OpGetLocal, &[0]),
+                "local 0 out of range at 0".to_string(),
+            ),
+            (
+                Instructions(
+                    vec![
+                        make_with_operands(Opcode::OpJump, &[1]).0,
+                        vec![Opcode::OpNull.byte()],
+                    ]
+                    .concat(),
+                ),
+                "invalid jump target 1 at 0".to_string(),
+            ),
+            (
+                make_with_operands(Opcode::OpJump, &[9]),
+                "invalid jump target 9 at 0".to_string(),
+            ),
+            (
+                make_with_operands(Opcode::OpSetIndex, &[0, 0]),
+                "no index to assign at 0".to_string(),
+            ),
+            (
+                make_with_operands(Opcode::OpSetIndex, &[1, equal]),
+                format!("unknown assignment operator {} at 0", equal),
+            ),
+        ];
+
+        for (instructions, expected) in tests {
+            let mut file = sample();
+            file.main.instructions = instructions;
+            let bytes = file.encode().unwrap();
+            assert_eq!(
+                BytecodeFile::decode(&bytes),
+                Err(FormatError { message: expected })
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_locals() {
+        let mut file = sample();
+        file.main.num_locals = 100_000;
+        let bytes = file.encode().unwrap();
+        assert_eq!(
+            BytecodeFile::decode(&bytes),
+            Err(FormatError {
+                message: "invalid number of locals: 100000 locals, 0 parameters".to_string()
+            })
+        );
+
+        let mut file = sample();
+        if let Object::CompiledFunction(func) = &mut file.constants[0] {
+            func.num_parameters = 2;
+        }
+        let bytes = file.encode().unwrap();
+        assert_eq!(
+            BytecodeFile::decode(&bytes),
+            Err(FormatError {
+                message: "invalid number of locals: 1 locals, 2 parameters".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_encode_unsupported_constant() {
+        let mut file = sample();
+        file.constants.push(Object::Null);
+        assert_eq!(
+            file.encode(),
+            Err(FormatError {
+                message: "cannot serialize constant: null".to_string()
+            })
+        );
+    }
+}
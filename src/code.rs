@@ -39,7 +39,7 @@ impl std::fmt::Debug for Instructions {
 }
 
 impl Instructions {
-    fn fmt_instruction(&self, def: Definition, operands: Vec<usize>) -> String {
+    pub fn fmt_instruction(&self, def: Definition, operands: Vec<usize>) -> String {
         let operand_count = def.operand_width.len();
 
         if operands.len() != operand_count {
@@ -50,11 +50,9 @@ impl Instructions {
             );
         }
 
-        match operand_count {
-            0 => def.name,
-            1 => format!("{} {}", def.name, operands[0]),
-            _ => format!("ERROR: unhundled operand_count for {}", def.name),
-        }
+        operands
+            .iter()
+            .fold(def.name, |out, operand| format!("{} {}", out, operand))
     }
 }
 
@@ -77,14 +75,21 @@ pub struct Definition {
 //     }
 //
 //     pub fn from(byte: u8) -> Opcode {
+//         match Opcode::from_byte(byte) {
+//             Some(op) => op,
+//             None => panic!("No such opcode {}", byte),
+//         }
+//     }
+//
+//     pub fn from_byte(byte: u8) -> Option<Opcode> {
 //         if byte == Opcode::OpConstant.byte() {
-//             return Opcode::OpConstant;
+//             return Some(Opcode::OpConstant);
 //         }
 //         if byte == Opcode::OpAdd.byte() {
-//             return Opcode::OpAdd;
+//             return Some(Opcode::OpAdd);
 //         }
 //         ...
-//         unreachable!("No such opcode {}", byte)
+//         None
 //     }
 // }
 //
@@ -115,12 +120,19 @@ macro_rules! opcode_enum {
             }
 
             pub fn from(byte: u8) -> $opcode {
+                match $opcode::from_byte(byte) {
+                    Some(op) => op,
+                    None => panic!("No such opcode {}", byte),
+                }
+            }
+
+            pub fn from_byte(byte: u8) -> Option<$opcode> {
                 $(
                     if byte == $opcode::$var.byte() {
-                        return $opcode::$var;
+                        return Some($opcode::$var);
                     }
                 )+
-                panic!("No such opcode {}", byte)
+                None
             }
         }
 
@@ -165,7 +177,24 @@ opcode_enum!(
         OpReturnValue: [],
         OpReturn: [],
         OpGetLocal: [1],
-        OpSetLocal: [1]
+        OpSetLocal: [1],
+        OpGetBuiltin: [1],
+        OpClosure: [2, 1],
+        OpGetFree: [1],
+        OpCurrentClosure: [],
+        // compile 済みファイルの互換のため、新しい opcode は末尾に足す
+        OpGreaterThanOrEqual: [],
+        OpMod: [],
+        // for-in で回す要素の配列に置き換える。operand が 1 なら [key, value] の組にする
+        OpIterItems: [1],
+        // operand は index の数と、+= などで使う演算の opcode (= なら 0)
+        OpSetIndex: [1, 1],
+        // 左辺から順に評価できるよう、< と <= も専用の opcode にする
+        OpLessThan: [],
+        OpLessThanOrEqual: [],
+        // && と || の左辺の値で飛ぶ。飛ぶときは値を残し、飛ばないときは捨てる
+        OpJumpNotTruthyOrPop: [2],
+        OpJumpTruthyOrPop: [2]
     ]
 );
 
@@ -252,6 +281,11 @@ mod tests {
                     vec![255],
                     Instructions(vec![Opcode::OpGetLocal.byte(), 255]),
                 ),
+                (
+                    Opcode::OpClosure,
+                    vec![65534, 255],
+                    Instructions(vec![Opcode::OpClosure.byte(), 255, 254, 255]),
+                ),
             ];
 
             for (op, operands, expected) in tests {
@@ -277,12 +311,14 @@ mod tests {
             make_with_operands(Opcode::OpGetLocal, &[1]),
             make_with_operands(Opcode::OpConstant, &[2]),
             make_with_operands(Opcode::OpConstant, &[65535]),
+            make_with_operands(Opcode::OpClosure, &[65535, 255]),
         ];
 
         let expected = r"0000 OpAdd
 0001 OpGetLocal 1
 0003 OpConstant 2
 0006 OpConstant 65535
+0009 OpClosure 65535 255
 ";
         let concatted = instructions.concat();
 
@@ -294,6 +330,7 @@ mod tests {
         let tests = vec![
             (Opcode::OpConstant, vec![65535], 2),
             (Opcode::OpGetLocal, vec![255], 1),
+            (Opcode::OpClosure, vec![65535, 255], 3),
         ];
 
         for (op, operands, bytes_read) in tests {
@@ -3,14 +3,138 @@ mod symbol_table;
 use super::ast::*;
 use super::code::*;
 use super::object;
+use super::object::builtin::BUILTINS;
 use super::object::Object;
-pub use symbol_table::new_symbol_table_stack;
+use super::token::Position;
+use std::fmt;
 use symbol_table::*;
 
+pub use symbol_table::SymbolTableStack;
+
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    UndefinedVariable {
+        name: String,
+        suggestions: Vec<String>,
+        pos: Position,
+    },
+    UnknownOperator {
+        operator: String,
+        pos: Position,
+    },
+    MacroLiteral {
+        pos: Position,
+    },
+    OutsideLoop {
+        keyword: String,
+        pos: Position,
+    },
+    InvalidAssignment {
+        target: String,
+        pos: Position,
+    },
+    // 命令の operand に収まらない数
+    OperandTooLarge {
+        operand: String,
+        value: usize,
+        max: usize,
+        pos: Position,
+    },
+}
+
+impl CompileError {
+    pub fn pos(&self) -> Position {
+        match self {
+            CompileError::UndefinedVariable { pos, .. } => *pos,
+            CompileError::UnknownOperator { pos, .. } => *pos,
+            CompileError::MacroLiteral { pos } => *pos,
+            CompileError::OutsideLoop { pos, .. } => *pos,
+            CompileError::InvalidAssignment { pos, .. } => *pos,
+            CompileError::OperandTooLarge { pos, .. } => *pos,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::UndefinedVariable {
+                name, suggestions, ..
+            } => {
+                write!(f, "undefined variable: {}", name)?;
+                match suggestions.len() {
+                    0 => Ok(()),
+                    1 => write!(f, " (did you mean `{}`?)", suggestions[0]),
+                    _ => write!(
+                        f,
+                        " (did you mean one of {}?)",
+                        suggestions
+                            .iter()
+                            .map(|s| format!("`{}`", s))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }
+            }
+            CompileError::UnknownOperator { operator, .. } => {
+                write!(f, "unknown operator {}", operator)
+            }
+            CompileError::MacroLiteral { .. } => write!(
+                f,
+                "macro literal is only allowed in a top-level let statement"
+            ),
+            CompileError::OutsideLoop { keyword, .. } => {
+                write!(f, "{} outside of loop", keyword)
+            }
+            CompileError::InvalidAssignment { target, .. } => {
+                write!(f, "cannot assign to {}", target)
+            }
+            CompileError::OperandTooLarge {
+                operand,
+                value,
+                max,
+                ..
+            } => write!(f, "{} {} is too large (max {})", operand, value, max),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// local の番号は u8 の operand に入れる
+const MAX_LOCALS: usize = 256;
+
+// エラーの文言で operand が何の数かを示す
+fn operand_name(op: Opcode, i: usize) -> &'static str {
+    match (op, i) {
+        (Opcode::OpConstant, _) | (Opcode::OpClosure, 0) => "constant index",
+        (Opcode::OpClosure, _) => "number of free variables",
+        (Opcode::OpGetLocal, _) | (Opcode::OpSetLocal, _) => "local index",
+        (Opcode::OpGetGlobal, _) | (Opcode::OpSetGlobal, _) => "global index",
+        (Opcode::OpGetFree, _) => "free variable index",
+        (Opcode::OpCall, _) => "number of arguments",
+        (Opcode::OpArray, _) | (Opcode::OpHash, _) => "number of elements",
+        (Opcode::OpSetIndex, 0) => "number of indices",
+        (Opcode::OpJump, _)
+        | (Opcode::OpJumpNotTruthy, _)
+        | (Opcode::OpJumpNotTruthyOrPop, _)
+        | (Opcode::OpJumpTruthyOrPop, _) => "jump target",
+        _ => "operand",
+    }
+}
+
 pub fn new_constants() -> Vec<Object> {
     vec![]
 }
 
+pub fn new_symbol_table_stack() -> SymbolTableStack {
+    let mut stack = symbol_table::new_symbol_table_stack();
+    for (i, builtin) in BUILTINS.iter().enumerate() {
+        stack.define_builtin(i, builtin.name);
+    }
+    stack
+}
+
 struct EmittedInstruction {
     opcode: Opcode,
     position: usize,
@@ -20,6 +144,17 @@ struct CompilationScope {
     instructions: Instructions,
     last_instruction: Option<EmittedInstruction>,
     previous_instruction: Option<EmittedInstruction>,
+    // 命令の開始位置と、それを生成したソース上の位置
+    positions: Vec<(usize, Position)>,
+    // compile 中のループ。関数の中からは外側のループに break できない
+    loops: Vec<LoopContext>,
+}
+
+// 後で飛び先を埋める break と continue の jump の位置
+#[derive(Default)]
+struct LoopContext {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
 }
 
 pub struct Compiler<'a> {
@@ -27,6 +162,10 @@ pub struct Compiler<'a> {
     symbol_table_stack: &'a mut SymbolTableStack,
     scopes: Vec<CompilationScope>,
     scope_index: usize,
+    // コンパイル中のノードの位置
+    pos: Position,
+    // emit の中からは返せないので、operand に収まらなかった最初の値を覚えておく
+    overflow: Option<CompileError>,
 }
 
 impl<'a> Compiler<'a> {
@@ -38,23 +177,43 @@ impl<'a> Compiler<'a> {
             instructions: Instructions(vec![]),
             last_instruction: None,
             previous_instruction: None,
+            positions: vec![],
+            loops: vec![],
         };
         Compiler {
             constants: constants,
             symbol_table_stack: s,
             scopes: vec![main_scope],
             scope_index: 0,
+            pos: Position::default(),
+            overflow: None,
         }
     }
 
-    pub fn compile(&mut self, program: Program) -> Result<(), String> {
-        program.compile(self)
+    pub fn compile(&mut self, program: Program) -> Result<(), CompileError> {
+        // 失敗した入力で定義した名前や constant は残さない
+        let symbol_tables = self.symbol_table_stack.stack.clone();
+        let num_constants = self.constants.len();
+        let mut result = program.compile(self);
+        if let (Ok(()), Some(err)) = (&result, self.overflow.take()) {
+            result = Err(err);
+        }
+        if result.is_err() {
+            while self.scope_index > 0 {
+                self.leave_scope();
+            }
+            self.symbol_table_stack.stack = symbol_tables;
+            self.constants.truncate(num_constants);
+        }
+        result
     }
 
     pub fn bytecode(mut self) -> ByteCode<'a> {
+        let main_scope = self.scopes.pop().unwrap();
         ByteCode {
-            instructions: self.scopes.pop().unwrap().instructions,
+            instructions: main_scope.instructions,
             constants: self.constants,
+            positions: main_scope.positions,
         }
     }
 
@@ -64,12 +223,14 @@ impl<'a> Compiler<'a> {
     }
 
     pub fn emit_with_operands(&mut self, op: Opcode, operands: &[usize]) -> usize {
+        self.check_operands(op, operands);
         let ins = make_with_operands(op, operands);
         self.emit_ins(op, ins)
     }
 
     fn emit_ins(&mut self, op: Opcode, ins: Instructions) -> usize {
         let pos = self.add_instruction(ins);
+        self.add_position(pos);
         self.set_last_instruction(op, pos);
         pos
     }
@@ -84,6 +245,14 @@ impl<'a> Compiler<'a> {
         pos_new_instruction
     }
 
+    fn add_position(&mut self, offset: usize) {
+        let pos = self.pos;
+        let positions = &mut self.scopes[self.scope_index].positions;
+        if positions.last().map(|(_, last)| *last) != Some(pos) {
+            positions.push((offset, pos));
+        }
+    }
+
     fn set_last_instruction(&mut self, op: Opcode, pos: usize) {
         let last = Some(EmittedInstruction {
             opcode: op,
@@ -113,12 +282,24 @@ impl<'a> Compiler<'a> {
             .unwrap()
             .position;
         self.current_instructions().0.truncate(position);
+        self.scopes[self.scope_index]
+            .positions
+            .retain(|(offset, _)| *offset < position);
         self.scopes[self.scope_index].last_instruction = std::mem::replace(
             &mut self.scopes[self.scope_index].previous_instruction,
             None,
         );
     }
 
+    // block の最後の式の値を stack に残す。式で終わらない block は null にする
+    fn leave_block_value(&mut self) {
+        if self.last_instruction_is(Opcode::OpPop) {
+            self.remove_last_pop();
+        } else {
+            self.emit(Opcode::OpNull);
+        }
+    }
+
     pub fn replace_instruction(&mut self, pos: usize, new_instuction: Instructions) {
         for (i, b) in new_instuction.0.iter().enumerate() {
             self.current_instructions().0[pos + i] = *b;
@@ -127,15 +308,156 @@ impl<'a> Compiler<'a> {
 
     pub fn change_operand(&mut self, op_pos: usize, operand: usize) {
         let op = Opcode::from(self.current_instructions().0[op_pos]);
+        self.check_operands(op, &[operand]);
         let new_instuction = make_with_operands(op, &[operand]);
         self.replace_instruction(op_pos, new_instuction);
     }
 
+    fn check_operands(&mut self, op: Opcode, operands: &[usize]) {
+        let def = lookup(&op);
+        for (i, (value, width)) in operands.iter().zip(def.operand_width.iter()).enumerate() {
+            let max = (1 << (8 * width)) - 1;
+            if *value > max {
+                self.set_overflow(operand_name(op, i), *value, max);
+            }
+        }
+    }
+
+    fn set_overflow(&mut self, operand: &str, value: usize, max: usize) {
+        if self.overflow.is_none() {
+            self.overflow = Some(CompileError::OperandTooLarge {
+                operand: operand.to_string(),
+                value,
+                max,
+                pos: self.pos,
+            });
+        }
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global => self.emit_with_operands(Opcode::OpGetGlobal, &[symbol.index]),
+            SymbolScope::Local => self.emit_with_operands(Opcode::OpGetLocal, &[symbol.index]),
+            SymbolScope::Builtin => self.emit_with_operands(Opcode::OpGetBuiltin, &[symbol.index]),
+            SymbolScope::Free => self.emit_with_operands(Opcode::OpGetFree, &[symbol.index]),
+            SymbolScope::Function => self.emit(Opcode::OpCurrentClosure),
+        };
+    }
+
+    fn set_symbol(&mut self, symbol: &Symbol) {
+        let op = if symbol.is_global() {
+            Opcode::OpSetGlobal
+        } else {
+            Opcode::OpSetLocal
+        };
+        self.emit_with_operands(op, &[symbol.index]);
+    }
+
+    // 関数が取り込んだ外側の変数は複製なので、書き換えても外側には届かない。代入はエラーにする
+    fn assignable_symbol(&mut self, ident: &Identifier) -> Result<Symbol, CompileError> {
+        let symbol = match self.symbol_table_stack.resolve(&ident.value) {
+            Some(symbol) => symbol,
+            None => {
+                return Err(CompileError::UndefinedVariable {
+                    name: ident.value.clone(),
+                    suggestions: self.symbol_table_stack.similar_names(&ident.value),
+                    pos: ident.pos,
+                })
+            }
+        };
+        let target = match symbol.scope {
+            SymbolScope::Global | SymbolScope::Local => return Ok(symbol),
+            SymbolScope::Free | SymbolScope::Function => "captured variable",
+            SymbolScope::Builtin => "builtin",
+        };
+        Err(CompileError::InvalidAssignment {
+            target: format!("{} `{}`", target, ident.value),
+            pos: ident.pos,
+        })
+    }
+
+    // ループの中でだけ使う、ソースからは書けない名前の変数
+    fn define_hidden(&mut self, name: &str) -> Symbol {
+        let depth = self.scopes[self.scope_index].loops.len();
+        let name = format!("{}@{}", name, depth);
+        self.symbol_table_stack.define(&name).clone()
+    }
+
+    // body を compile して、その中の break と continue の飛び先を埋める
+    fn compile_loop_body(
+        &mut self,
+        body: &BlockStatement,
+        continue_pos: impl FnOnce(&mut Self) -> usize,
+    ) -> Result<LoopContext, CompileError> {
+        self.scopes[self.scope_index]
+            .loops
+            .push(LoopContext::default());
+        body.compile(self)?;
+        let context = self.scopes[self.scope_index].loops.pop().unwrap();
+
+        let continue_pos = continue_pos(self);
+        for pos in &context.continues {
+            self.change_operand(*pos, continue_pos);
+        }
+        Ok(context)
+    }
+
+    fn patch_breaks(&mut self, context: LoopContext) {
+        let after_loop_pos = self.current_instructions().0.len();
+        for pos in context.breaks {
+            self.change_operand(pos, after_loop_pos);
+        }
+    }
+
+    fn compile_function(
+        &mut self,
+        func: &FunctionLiteral,
+        name: Option<&str>,
+    ) -> Result<(), CompileError> {
+        self.enter_scope();
+        if let Some(name) = name {
+            self.symbol_table_stack.define_function_name(name);
+        }
+        for p in &func.parameters {
+            self.symbol_table_stack.define(&p.value);
+        }
+        func.body.compile(self)?;
+        if self.last_instruction_is(Opcode::OpPop) {
+            self.replace_last_pop_with_return();
+        }
+        if !self.last_instruction_is(Opcode::OpReturnValue) {
+            self.emit(Opcode::OpReturn);
+        }
+        let free_symbols = self.symbol_table_stack.last().free_symbols.clone();
+        let num_locals = self.symbol_table_stack.last().num_definitions;
+        if num_locals > MAX_LOCALS {
+            self.set_overflow("number of locals", num_locals, MAX_LOCALS);
+        }
+        let positions = std::mem::take(&mut self.scopes[self.scope_index].positions);
+        let instructions = self.leave_scope();
+
+        for s in &free_symbols {
+            self.load_symbol(s);
+        }
+
+        let compiled_fn = Object::CompiledFunction(object::CompiledFunction {
+            instructions,
+            num_locals,
+            num_parameters: func.parameters.len(),
+            positions,
+        });
+        let operand = self.add_constant(compiled_fn);
+        self.emit_with_operands(Opcode::OpClosure, &[operand, free_symbols.len()]);
+        Ok(())
+    }
+
     pub fn enter_scope(&mut self) {
         let scope = CompilationScope {
             instructions: Instructions(vec![]),
             last_instruction: None,
             previous_instruction: None,
+            positions: vec![],
+            loops: vec![],
         };
         self.scopes.push(scope);
         self.scope_index += 1;
@@ -165,13 +487,13 @@ impl<'a> Compiler<'a> {
 }
 
 trait Compile {
-    fn compile(&self, compiler: &mut Compiler) -> Result<(), String>;
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), CompileError>;
 }
 
 macro_rules! impl_compile {
     ($ty:ty => ($self:ident, $compiler:ident) $block:block) => {
         impl Compile for $ty {
-            fn compile(&$self, $compiler: &mut Compiler) -> Result<(), String> {
+            fn compile(&$self, $compiler: &mut Compiler) -> Result<(), CompileError> {
                 $block
             }
         }
@@ -179,6 +501,14 @@ macro_rules! impl_compile {
 }
 
 impl_compile!(Program => (self, compiler) {
+    // top-level の関数は先に名前を定義しておき、相互再帰できるようにする
+    for s in &self.statements {
+        if let Statement::LetStatement(stmt) = s {
+            if let Expression::FunctionLiteral(_) = stmt.value {
+                compiler.symbol_table_stack.define(&stmt.name.value);
+            }
+        }
+    }
     for s in &self.statements {
         s.compile(compiler)?
     }
@@ -186,6 +516,7 @@ impl_compile!(Program => (self, compiler) {
 });
 
 impl_compile!(Statement => (self, compiler) {
+    compiler.pos = self.pos();
     match self {
         Statement::ExpressionStatement(stmt) => {
             stmt.expression.compile(compiler)?;
@@ -200,46 +531,189 @@ impl_compile!(Statement => (self, compiler) {
             compiler.emit(Opcode::OpReturnValue);
             Ok(())
         }
+        Statement::WhileStatement(stmt) => stmt.compile(compiler),
+        Statement::ForStatement(stmt) => stmt.compile(compiler),
+        Statement::BreakStatement(stmt) => {
+            let jump_pos = compiler.emit_with_operands(Opcode::OpJump, &[9999]);
+            match compiler.scopes[compiler.scope_index].loops.last_mut() {
+                Some(context) => {
+                    context.breaks.push(jump_pos);
+                    Ok(())
+                }
+                None => Err(CompileError::OutsideLoop {
+                    keyword: "break".to_string(),
+                    pos: stmt.pos,
+                }),
+            }
+        }
+        Statement::ContinueStatement(stmt) => {
+            let jump_pos = compiler.emit_with_operands(Opcode::OpJump, &[9999]);
+            match compiler.scopes[compiler.scope_index].loops.last_mut() {
+                Some(context) => {
+                    context.continues.push(jump_pos);
+                    Ok(())
+                }
+                None => Err(CompileError::OutsideLoop {
+                    keyword: "continue".to_string(),
+                    pos: stmt.pos,
+                }),
+            }
+        }
+    }
+});
+
+/*
+ * while (cond) { body }
+ *
+ *   cond:     <cond>
+ *             OpJumpNotTruthy end
+ *             <body>             ; continue は cond へ
+ *             OpJump cond
+ *   end:                         ; break は end へ
+ */
+impl_compile!(WhileStatement => (self, compiler) {
+    let condition_pos = compiler.current_instructions().0.len();
+    self.condition.compile(compiler)?;
+    let jump_not_truthy_pos = compiler.emit_with_operands(Opcode::OpJumpNotTruthy, &[9999]);
+
+    let context = compiler.compile_loop_body(&self.body, |_| condition_pos)?;
+    compiler.emit_with_operands(Opcode::OpJump, &[condition_pos]);
+
+    let after_body_pos = compiler.current_instructions().0.len();
+    compiler.change_operand(jump_not_truthy_pos, after_body_pos);
+    compiler.patch_breaks(context);
+    Ok(())
+});
+
+/*
+ * for (key, value in iterable) { body }
+ *
+ *             <iterable>
+ *             OpIterItems 1      ; value がなければ 0
+ *             set items
+ *             len(items)         ; 組み込みの len を直接呼ぶ
+ *             set length
+ *             set index = 0
+ *   cond:     length > index
+ *             OpJumpNotTruthy end
+ *             key = items[index][0], value = items[index][1]
+ *             <body>
+ *   continue: index = index + 1
+ *             OpJump cond
+ *   end:
+ */
+impl_compile!(ForStatement => (self, compiler) {
+    self.iterable.compile(compiler)?;
+    compiler.emit_with_operands(Opcode::OpIterItems, &[self.value.is_some() as usize]);
+    let items = compiler.define_hidden("items");
+    compiler.set_symbol(&items);
+
+    let len_index = BUILTINS.iter().position(|b| b.name == "len").unwrap();
+    compiler.emit_with_operands(Opcode::OpGetBuiltin, &[len_index]);
+    compiler.load_symbol(&items);
+    compiler.emit_with_operands(Opcode::OpCall, &[1]);
+    let length = compiler.define_hidden("length");
+    compiler.set_symbol(&length);
+
+    let zero = compiler.add_constant(Object::Integer(0));
+    let one = compiler.add_constant(Object::Integer(1));
+    compiler.emit_with_operands(Opcode::OpConstant, &[zero]);
+    let index = compiler.define_hidden("index");
+    compiler.set_symbol(&index);
+
+    let condition_pos = compiler.current_instructions().0.len();
+    compiler.load_symbol(&length);
+    compiler.load_symbol(&index);
+    compiler.emit(Opcode::OpGreaterThan);
+    let jump_not_truthy_pos = compiler.emit_with_operands(Opcode::OpJumpNotTruthy, &[9999]);
+
+    let key = compiler.symbol_table_stack.define(&self.key.value).clone();
+    let value = self
+        .value
+        .as_ref()
+        .map(|value| compiler.symbol_table_stack.define(&value.value).clone());
+    compiler.load_symbol(&items);
+    compiler.load_symbol(&index);
+    compiler.emit(Opcode::OpIndex);
+    match &value {
+        Some(value) => {
+            // [key, value] の組を分ける
+            compiler.emit_with_operands(Opcode::OpConstant, &[zero]);
+            compiler.emit(Opcode::OpIndex);
+            compiler.set_symbol(&key);
+            compiler.load_symbol(&items);
+            compiler.load_symbol(&index);
+            compiler.emit(Opcode::OpIndex);
+            compiler.emit_with_operands(Opcode::OpConstant, &[one]);
+            compiler.emit(Opcode::OpIndex);
+            compiler.set_symbol(value);
+        }
+        None => compiler.set_symbol(&key),
     }
+
+    let context = compiler.compile_loop_body(&self.body, |compiler| {
+        let continue_pos = compiler.current_instructions().0.len();
+        compiler.load_symbol(&index);
+        compiler.emit_with_operands(Opcode::OpConstant, &[one]);
+        compiler.emit(Opcode::OpAdd);
+        compiler.set_symbol(&index);
+        continue_pos
+    })?;
+    compiler.emit_with_operands(Opcode::OpJump, &[condition_pos]);
+
+    let after_body_pos = compiler.current_instructions().0.len();
+    compiler.change_operand(jump_not_truthy_pos, after_body_pos);
+    compiler.patch_breaks(context);
+    Ok(())
 });
 
 impl_compile!(LetStatement => (self, compiler) {
-    self.value.compile(compiler)?;
-    let symbol = compiler.symbol_table_stack.define(&self.name.value);
+    let symbol = if let Expression::FunctionLiteral(func) = &self.value {
+        // 関数本体から自分自身を参照できるよう、先に名前を定義する
+        let symbol = compiler.symbol_table_stack.define(&self.name.value).clone();
+        compiler.compile_function(func, Some(&self.name.value))?;
+        symbol
+    } else {
+        self.value.compile(compiler)?;
+        compiler.symbol_table_stack.define(&self.name.value).clone()
+    };
     let op = if symbol.is_global() {
         Opcode::OpSetGlobal
     } else {
         Opcode::OpSetLocal
     };
-    let index = symbol.index.clone();
-    compiler.emit_with_operands(op, &[index]);
+    compiler.emit_with_operands(op, &[symbol.index]);
     Ok(())
 });
 
 impl_compile!(Expression => (self, compiler) {
-    match self {
+    let outer = std::mem::replace(&mut compiler.pos, self.pos());
+    let result = match self {
         Expression::InfixExpression(exp) => exp.compile(compiler),
         Expression::IntegerLiteral(exp) => exp.compile(compiler),
+        Expression::FloatLiteral(exp) => exp.compile(compiler),
         Expression::Boolean(exp) => exp.compile(compiler),
         Expression::PrefixExpression(exp) => exp.compile(compiler),
         Expression::IfExpression(exp) => exp.compile(compiler),
         Expression::Identifier(exp) => exp.compile(compiler),
         Expression::StringLiteral(exp) => exp.compile(compiler),
+        Expression::InterpolatedString(exp) => exp.compile(compiler),
         Expression::ArrayLiteral(exp) => exp.compile(compiler),
         Expression::HashLiteral(exp) => exp.compile(compiler),
         Expression::IndexExpression(exp) => exp.compile(compiler),
         Expression::FunctionLiteral(exp) => exp.compile(compiler),
         Expression::CallExpression(exp) => exp.compile(compiler),
-        _ => todo!("other expressions: {:?}", self),
-    }
+        Expression::AssignExpression(exp) => exp.compile(compiler),
+        // macro は compile 前に展開して取り除いておく
+        Expression::MacroLiteral(exp) => Err(CompileError::MacroLiteral { pos: exp.pos }),
+    };
+    compiler.pos = outer;
+    result
 });
 
 impl_compile!(InfixExpression => (self, compiler) {
-    if &*self.operator == "<" {
-        self.right.compile(compiler)?;
-        self.left.compile(compiler)?;
-        compiler.emit(Opcode::OpGreaterThan);
-        return Ok(())
+    if self.operator == "&&" || self.operator == "||" {
+        return compile_logical_expression(self, compiler);
     }
 
     self.left.compile(compiler)?;
@@ -258,26 +732,149 @@ impl_compile!(InfixExpression => (self, compiler) {
         "/" => {
             compiler.emit(Opcode::OpDiv);
         }
+        "%" => {
+            compiler.emit(Opcode::OpMod);
+        }
         ">" => {
             compiler.emit(Opcode::OpGreaterThan);
         }
+        ">=" => {
+            compiler.emit(Opcode::OpGreaterThanOrEqual);
+        }
+        "<" => {
+            compiler.emit(Opcode::OpLessThan);
+        }
+        "<=" => {
+            compiler.emit(Opcode::OpLessThanOrEqual);
+        }
         "==" => {
             compiler.emit(Opcode::OpEqual);
         }
         "!=" => {
             compiler.emit(Opcode::OpNotEqual);
         }
-        other => return Err(format!("unknown operator {}", other))
+        other => return Err(CompileError::UnknownOperator {
+            operator: other.to_string(),
+            pos: self.pos,
+        })
+    }
+    Ok(())
+});
+
+fn arithmetic_opcode(operator: &str) -> Option<Opcode> {
+    match operator {
+        "+" => Some(Opcode::OpAdd),
+        "-" => Some(Opcode::OpSub),
+        "*" => Some(Opcode::OpMul),
+        "/" => Some(Opcode::OpDiv),
+        "%" => Some(Opcode::OpMod),
+        _ => None,
+    }
+}
+
+/*
+配列とハッシュは値なので、xs[i][j] = v は書き換えた xs を xs に入れ直す
+
+```
+xs = v                        xs[i][j] += v
+  v                             xs
+  OpSetGlobal xs                i
+  OpGetGlobal xs                j
+                                v
+                                OpSetIndex 2 OpAdd   ; 代入した値と新しい xs を積む
+                                OpSetGlobal xs
+```
+*/
+impl_compile!(AssignExpression => (self, compiler) {
+    let operation = match self.infix_operator() {
+        Some(operator) => match arithmetic_opcode(operator) {
+            Some(op) => Some(op),
+            None => return Err(CompileError::UnknownOperator {
+                operator: self.operator.clone(),
+                pos: self.pos,
+            }),
+        },
+        None => None,
+    };
+
+    let mut indices = vec![];
+    let mut base = &*self.target;
+    while let Expression::IndexExpression(exp) = base {
+        indices.push(&*exp.index);
+        base = &exp.left;
+    }
+    indices.reverse();
+    let ident = match base {
+        Expression::Identifier(ident) => ident,
+        _ => return Err(CompileError::InvalidAssignment {
+            target: format!("{}", self.target),
+            pos: self.pos,
+        }),
+    };
+    let symbol = compiler.assignable_symbol(ident)?;
+
+    if indices.is_empty() {
+        if let Some(op) = operation {
+            compiler.load_symbol(&symbol);
+            self.value.compile(compiler)?;
+            compiler.emit(op);
+        } else {
+            self.value.compile(compiler)?;
+        }
+        compiler.set_symbol(&symbol);
+        compiler.load_symbol(&symbol);
+    } else {
+        compiler.load_symbol(&symbol);
+        for index in &indices {
+            index.compile(compiler)?;
+        }
+        self.value.compile(compiler)?;
+        let op = operation.map_or(0, |op| op.byte() as usize);
+        compiler.emit_with_operands(Opcode::OpSetIndex, &[indices.len(), op]);
+        compiler.set_symbol(&symbol);
     }
     Ok(())
 });
 
+/*
+右辺は左辺の結果しだいで評価する。右辺を評価しないときは左辺の値になる
+
+```
+a && b                        a || b
+  a                             a
+  OpJumpNotTruthyOrPop L0       OpJumpTruthyOrPop L0
+  b                             b
+L0:                           L0:
+```
+*/
+fn compile_logical_expression(
+    exp: &InfixExpression,
+    compiler: &mut Compiler,
+) -> Result<(), CompileError> {
+    exp.left.compile(compiler)?;
+    let op = if exp.operator == "&&" {
+        Opcode::OpJumpNotTruthyOrPop
+    } else {
+        Opcode::OpJumpTruthyOrPop
+    };
+    let jump_pos = compiler.emit_with_operands(op, &[9999]);
+
+    exp.right.compile(compiler)?;
+
+    let after_right_pos = compiler.current_instructions().0.len();
+    compiler.change_operand(jump_pos, after_right_pos);
+    Ok(())
+}
+
 impl_compile!(PrefixExpression => (self, compiler) {
     self.right.compile(compiler)?;
     match &*self.operator {
         "!" => compiler.emit(Opcode::OpBang),
         "-" => compiler.emit(Opcode::OpMinus),
-        other => return Err(format!("unknown operator {}", other))
+        other => return Err(CompileError::UnknownOperator {
+            operator: other.to_string(),
+            pos: self.pos,
+        })
     };
     Ok(())
 });
@@ -289,6 +886,13 @@ impl_compile!(IntegerLiteral => (self, compiler) {
     Ok(())
 });
 
+impl_compile!(FloatLiteral => (self, compiler) {
+    let float = Object::Float(self.value);
+    let constant = compiler.add_constant(float);
+    compiler.emit_with_operands(Opcode::OpConstant, &[constant]);
+    Ok(())
+});
+
 impl_compile!(Boolean => (self, compiler) {
     if self.value {
         compiler.emit(Opcode::OpTrue);
@@ -304,10 +908,7 @@ impl_compile!(IfExpression => (self, compiler) {
     let jump_not_truthy_pos = compiler.emit_with_operands(Opcode::OpJumpNotTruthy, &[9999]);
 
     self.consequence.compile(compiler)?;
-
-    if compiler.last_instruction_is(Opcode::OpPop) {
-        compiler.remove_last_pop()
-    }
+    compiler.leave_block_value();
 
     let jump_pos = compiler.emit_with_operands(Opcode::OpJump, &[9999]);
 
@@ -316,10 +917,7 @@ impl_compile!(IfExpression => (self, compiler) {
 
     if let Some(alternative) = &self.alternative {
         alternative.compile(compiler)?;
-
-        if compiler.last_instruction_is(Opcode::OpPop) {
-            compiler.remove_last_pop();
-        }
+        compiler.leave_block_value();
     } else {
         compiler.emit(Opcode::OpNull);
     }
@@ -338,16 +936,15 @@ impl_compile!(BlockStatement => (self, compiler) {
 });
 
 impl_compile!(Identifier => (self, compiler) {
-    let symbol = compiler.symbol_table_stack.resolve(&self.value).expect(
-        &format!("undefined variable: {}", self.value)
-    );
-    let op = if symbol.is_global() {
-        Opcode::OpGetGlobal
-    } else {
-        Opcode::OpGetLocal
+    let symbol = match compiler.symbol_table_stack.resolve(&self.value) {
+        Some(symbol) => symbol,
+        None => return Err(CompileError::UndefinedVariable {
+            name: self.value.clone(),
+            suggestions: compiler.symbol_table_stack.similar_names(&self.value),
+            pos: self.pos,
+        }),
     };
-    let index = symbol.index.clone();
-    compiler.emit_with_operands(op, &[index]);
+    compiler.load_symbol(&symbol);
     Ok(())
 });
 
@@ -358,6 +955,29 @@ impl_compile!(StringLiteral => (self, compiler) {
     Ok(())
 });
 
+// 埋め込んだ式は組み込みの str を直接呼んで文字列にし、断片を順に + でつなぐ
+impl_compile!(InterpolatedString => (self, compiler) {
+    if self.parts.is_empty() {
+        let constant = compiler.add_constant(Object::String(String::new()));
+        compiler.emit_with_operands(Opcode::OpConstant, &[constant]);
+        return Ok(());
+    }
+    let str_index = BUILTINS.iter().position(|b| b.name == "str").unwrap();
+    for (i, part) in self.parts.iter().enumerate() {
+        if let Expression::StringLiteral(text) = part {
+            text.compile(compiler)?;
+        } else {
+            compiler.emit_with_operands(Opcode::OpGetBuiltin, &[str_index]);
+            part.compile(compiler)?;
+            compiler.emit_with_operands(Opcode::OpCall, &[1]);
+        }
+        if i > 0 {
+            compiler.emit(Opcode::OpAdd);
+        }
+    }
+    Ok(())
+});
+
 impl_compile!(ArrayLiteral => (self, compiler) {
     for el in &self.elements {
         el.compile(compiler)?;
@@ -383,23 +1003,7 @@ impl_compile!(IndexExpression => (self, compiler) {
 });
 
 impl_compile!(FunctionLiteral => (self, compiler) {
-    compiler.enter_scope();
-    for p in &self.parameters {
-        compiler.symbol_table_stack.define(&p.value);
-    }
-    self.body.compile(compiler)?;
-    if compiler.last_instruction_is(Opcode::OpPop) {
-        compiler.replace_last_pop_with_return();
-    }
-    if !compiler.last_instruction_is(Opcode::OpReturnValue) {
-        compiler.emit(Opcode::OpReturn);
-    }
-    let num_locals = compiler.symbol_table_stack.last().num_definitions;
-    let instructions = compiler.leave_scope();
-    let compiled_fn = Object::CompiledFunction(object::CompiledFunction{instructions, num_locals});
-    let operand = compiler.add_constant(compiled_fn);
-    compiler.emit_with_operands(Opcode::OpConstant, &[operand]);
-    Ok(())
+    compiler.compile_function(self, None)
 });
 
 impl_compile!(CallExpression => (self, compiler) {
@@ -414,6 +1018,7 @@ impl_compile!(CallExpression => (self, compiler) {
 pub struct ByteCode<'a> {
     pub instructions: Instructions,
     pub constants: &'a mut Vec<Object>,
+    pub positions: Vec<(usize, Position)>,
 }
 
 #[cfg(test)]
@@ -438,6 +1043,16 @@ mod tests {
                     make(Opcode::OpPop),
                 ],
             ),
+            (
+                "5 % 2",
+                vec![5, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &vec![0]),
+                    make_with_operands(Opcode::OpConstant, &vec![1]),
+                    make(Opcode::OpMod),
+                    make(Opcode::OpPop),
+                ],
+            ),
             (
                 "1; 2",
                 vec![1, 2],
@@ -517,11 +1132,11 @@ mod tests {
             ),
             (
                 "1 < 2",
-                vec![2, 1],
+                vec![1, 2],
                 vec![
                     make_with_operands(Opcode::OpConstant, &vec![0]),
                     make_with_operands(Opcode::OpConstant, &vec![1]),
-                    make(Opcode::OpGreaterThan),
+                    make(Opcode::OpLessThan),
                     make(Opcode::OpPop),
                 ],
             ),
@@ -574,6 +1189,62 @@ mod tests {
                     make(Opcode::OpPop),
                 ],
             ),
+            (
+                "1 >= 2",
+                vec![1, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &vec![0]),
+                    make_with_operands(Opcode::OpConstant, &vec![1]),
+                    make(Opcode::OpGreaterThanOrEqual),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                "1 <= 2",
+                vec![1, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &vec![0]),
+                    make_with_operands(Opcode::OpConstant, &vec![1]),
+                    make(Opcode::OpLessThanOrEqual),
+                    make(Opcode::OpPop),
+                ],
+            ),
+        ];
+
+        run_compile_tests(tests);
+    }
+
+    #[test]
+    fn test_logical_expressions() {
+        let tests = vec![
+            (
+                "true && false",
+                vec![],
+                vec![
+                    // 0000
+                    make(Opcode::OpTrue),
+                    // 0001
+                    make_with_operands(Opcode::OpJumpNotTruthyOrPop, &[5]),
+                    // 0004
+                    make(Opcode::OpFalse),
+                    // 0005
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                "1 || 2",
+                vec![1, 2],
+                vec![
+                    // 0000
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    // 0003
+                    make_with_operands(Opcode::OpJumpTruthyOrPop, &[9]),
+                    // 0006
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    // 0009
+                    make(Opcode::OpPop),
+                ],
+            ),
         ];
 
         run_compile_tests(tests);
@@ -632,41 +1303,298 @@ mod tests {
     }
 
     #[test]
-    fn test_global_let_statements() {
+    fn test_loops() {
         let tests = vec![
             (
-                r"
-                let one = 1;
-                let two = 2;
-                ",
-                vec![1, 2],
-                vec![
-                    make_with_operands(Opcode::OpConstant, &[0]),
-                    make_with_operands(Opcode::OpSetGlobal, &[0]),
-                    make_with_operands(Opcode::OpConstant, &[1]),
-                    make_with_operands(Opcode::OpSetGlobal, &[1]),
-                ],
-            ),
-            (
-                r"
-                let one = 1;
-                one;
-                ",
-                vec![1],
+                "while (true) { break; continue; }",
+                vec![],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[0]),
-                    make_with_operands(Opcode::OpSetGlobal, &[0]),
-                    make_with_operands(Opcode::OpGetGlobal, &[0]),
-                    make(Opcode::OpPop),
+                    // 0000
+                    make(Opcode::OpTrue),
+                    // 0001
+                    make_with_operands(Opcode::OpJumpNotTruthy, &[13]),
+                    // 0004
+                    make_with_operands(Opcode::OpJump, &[13]),
+                    // 0007
+                    make_with_operands(Opcode::OpJump, &[0]),
+                    // 0010
+                    make_with_operands(Opcode::OpJump, &[0]),
                 ],
             ),
             (
-                r"
-                let one = 1;
-                let two = one;
-                two;
-                ",
-                vec![1],
+                "for (x in [1]) { x }",
+                vec![1, 0, 1],
+                vec![
+                    // 0000
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    // 0003
+                    make_with_operands(Opcode::OpArray, &[1]),
+                    // 0006
+                    make_with_operands(Opcode::OpIterItems, &[0]),
+                    // 0008
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    // 0011
+                    make_with_operands(Opcode::OpGetBuiltin, &[0]),
+                    // 0013
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    // 0016
+                    make_with_operands(Opcode::OpCall, &[1]),
+                    // 0018
+                    make_with_operands(Opcode::OpSetGlobal, &[1]),
+                    // 0021
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    // 0024
+                    make_with_operands(Opcode::OpSetGlobal, &[2]),
+                    // 0027
+                    make_with_operands(Opcode::OpGetGlobal, &[1]),
+                    // 0030
+                    make_with_operands(Opcode::OpGetGlobal, &[2]),
+                    // 0033
+                    make(Opcode::OpGreaterThan),
+                    // 0034
+                    make_with_operands(Opcode::OpJumpNotTruthy, &[64]),
+                    // 0037
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    // 0040
+                    make_with_operands(Opcode::OpGetGlobal, &[2]),
+                    // 0043
+                    make(Opcode::OpIndex),
+                    // 0044
+                    make_with_operands(Opcode::OpSetGlobal, &[3]),
+                    // 0047
+                    make_with_operands(Opcode::OpGetGlobal, &[3]),
+                    // 0050
+                    make(Opcode::OpPop),
+                    // 0051
+                    make_with_operands(Opcode::OpGetGlobal, &[2]),
+                    // 0054
+                    make_with_operands(Opcode::OpConstant, &[2]),
+                    // 0057
+                    make(Opcode::OpAdd),
+                    // 0058
+                    make_with_operands(Opcode::OpSetGlobal, &[2]),
+                    // 0061
+                    make_with_operands(Opcode::OpJump, &[27]),
+                ],
+            ),
+        ];
+
+        run_compile_tests(tests);
+    }
+
+    #[test]
+    fn test_assignments() {
+        let tests = vec![
+            (
+                "let x = 1; x += 2;",
+                vec![1, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make(Opcode::OpAdd),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                "let x = [1]; x[0] = 2;",
+                vec![1, 0, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpArray, &[1]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpSetIndex, &[1, 0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                "let x = [[1]]; x[0][0] *= 2;",
+                vec![1, 0, 0, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpArray, &[1]),
+                    make_with_operands(Opcode::OpArray, &[1]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpConstant, &[3]),
+                    make_with_operands(Opcode::OpSetIndex, &[2, Opcode::OpMul.byte() as usize]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+        ];
+
+        run_compile_tests(tests);
+    }
+
+    #[test]
+    fn test_invalid_assignments() {
+        let tests = vec![
+            ("len = 1", "builtin `len`", (1, 1)),
+            ("fn(a) { fn() { a = 1 } }", "captured variable `a`", (1, 16)),
+            (
+                "let f = fn() { let g = fn() { g += 1 } }",
+                "captured variable `g`",
+                (1, 31),
+            ),
+        ];
+
+        for (input, expected_target, (line, column)) in tests {
+            let program = parse(input.to_string());
+
+            let mut symbol_table_stack = new_symbol_table_stack();
+            let mut constants = new_constants();
+            let mut compiler = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+            match compiler.compile(program) {
+                Ok(_) => assert!(false, "expected compile error for {}", input),
+                Err(err) => assert_eq!(
+                    err,
+                    CompileError::InvalidAssignment {
+                        target: expected_target.to_string(),
+                        pos: Position::new(line, column),
+                    }
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_loop_control_outside_of_loop() {
+        let tests = vec![
+            ("break;", "break", (1, 1)),
+            ("if (true) { continue; }", "continue", (1, 13)),
+            // 関数の中から外側のループには抜けられない
+            ("while (true) { fn() { break; } }", "break", (1, 23)),
+        ];
+
+        for (input, expected_keyword, (line, column)) in tests {
+            let program = parse(input.to_string());
+
+            let mut symbol_table_stack = new_symbol_table_stack();
+            let mut constants = new_constants();
+            let mut compiler = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+            match compiler.compile(program) {
+                Ok(_) => assert!(false, "expected compile error for {}", input),
+                Err(err) => assert_eq!(
+                    err,
+                    CompileError::OutsideLoop {
+                        keyword: expected_keyword.to_string(),
+                        pos: Position::new(line, column),
+                    }
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_operands_too_large() {
+        let names = |n: usize, prefix: &str| {
+            (0..n)
+                .map(|i| format!("{}{}", prefix, i))
+                .collect::<Vec<_>>()
+        };
+        let tests = vec![
+            (
+                format!(
+                    "fn() {{ {} v43 }}",
+                    names(300, "let v")
+                        .iter()
+                        .map(|l| format!("{} = 0;", l))
+                        .collect::<String>()
+                ),
+                "local index",
+                256,
+                255,
+            ),
+            (
+                format!("fn() {{}}({})", vec!["0"; 300].join(", ")),
+                "number of arguments",
+                300,
+                255,
+            ),
+            (
+                format!("fn({}) {{}}", names(300, "p").join(", ")),
+                "number of locals",
+                300,
+                256,
+            ),
+            (names(65537, "").join("; "), "constant index", 65536, 65535),
+            (
+                format!("[{}]", vec!["true"; 65536].join(", ")),
+                "number of elements",
+                65536,
+                65535,
+            ),
+        ];
+
+        for (input, expected_operand, expected_value, expected_max) in tests {
+            let program = parse(input.to_string());
+
+            let mut symbol_table_stack = new_symbol_table_stack();
+            let mut constants = new_constants();
+            let mut compiler = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+            match compiler.compile(program) {
+                Ok(_) => assert!(false, "expected compile error for {}", expected_operand),
+                Err(CompileError::OperandTooLarge {
+                    operand,
+                    value,
+                    max,
+                    ..
+                }) => {
+                    assert_eq!(operand, expected_operand);
+                    assert_eq!(value, expected_value);
+                    assert_eq!(max, expected_max);
+                }
+                Err(err) => assert!(false, "unexpected compile error. {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_global_let_statements() {
+        let tests = vec![
+            (
+                r"
+                let one = 1;
+                let two = 2;
+                ",
+                vec![1, 2],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpSetGlobal, &[1]),
+                ],
+            ),
+            (
+                r"
+                let one = 1;
+                one;
+                ",
+                vec![1],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                r"
+                let one = 1;
+                let two = one;
+                two;
+                ",
+                vec![1],
                 vec![
                     make_with_operands(Opcode::OpConstant, &[0]),
                     make_with_operands(Opcode::OpSetGlobal, &[0]),
@@ -701,6 +1629,20 @@ mod tests {
                     make(Opcode::OpPop),
                 ],
             ),
+            (
+                r#""a${"b" + "c"}""#,
+                vec!["a", "b", "c"],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpGetBuiltin, &[8]),
+                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make(Opcode::OpAdd),
+                    make_with_operands(Opcode::OpCall, &[1]),
+                    make(Opcode::OpAdd),
+                    make(Opcode::OpPop),
+                ],
+            ),
         ];
 
         run_compile_tests(tests)
@@ -846,7 +1788,7 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpClosure, &[2, 0]),
                     make(Opcode::OpPop),
                 ],
             ),
@@ -863,7 +1805,7 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpClosure, &[2, 0]),
                     make(Opcode::OpPop),
                 ],
             ),
@@ -880,7 +1822,7 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpClosure, &[2, 0]),
                     make(Opcode::OpPop),
                 ],
             ),
@@ -894,7 +1836,7 @@ mod tests {
             "fn() { }",
             vec![Expect::Instructions(vec![make(Opcode::OpReturn)])],
             vec![
-                make_with_operands(Opcode::OpConstant, &[0]),
+                make_with_operands(Opcode::OpClosure, &[0, 0]),
                 make(Opcode::OpPop),
             ],
         )];
@@ -948,7 +1890,7 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
                     make_with_operands(Opcode::OpCall, &[0]),
                     make(Opcode::OpPop),
                 ],
@@ -966,7 +1908,7 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
                     make_with_operands(Opcode::OpSetGlobal, &[0]),
                     make_with_operands(Opcode::OpGetGlobal, &[0]),
                     make_with_operands(Opcode::OpCall, &[0]),
@@ -986,7 +1928,7 @@ mod tests {
                     Expect::Integer(24),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpClosure, &[0, 0]),
                     make_with_operands(Opcode::OpSetGlobal, &[0]),
                     make_with_operands(Opcode::OpGetGlobal, &[0]),
                     make_with_operands(Opcode::OpConstant, &[1]),
@@ -1013,7 +1955,7 @@ mod tests {
                     Expect::Integer(26),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpClosure, &[0, 0]),
                     make_with_operands(Opcode::OpSetGlobal, &[0]),
                     make_with_operands(Opcode::OpGetGlobal, &[0]),
                     make_with_operands(Opcode::OpConstant, &[1]),
@@ -1045,7 +1987,7 @@ mod tests {
                 vec![
                     make_with_operands(Opcode::OpConstant, &[0]),
                     make_with_operands(Opcode::OpSetGlobal, &[0]),
-                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
                     make(Opcode::OpPop),
                 ],
             ),
@@ -1066,7 +2008,7 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[1]),
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
                     make(Opcode::OpPop),
                 ],
             ),
@@ -1093,15 +2035,379 @@ mod tests {
                     ]),
                 ],
                 vec![
-                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpClosure, &[2, 0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+        ];
+
+        run_compile_tests(tests);
+    }
+
+    #[test]
+    fn test_builtins() {
+        let tests = vec![(
+            r#"
+                len([]);
+                push([], 1);
+                "#,
+            vec![1],
+            vec![
+                make_with_operands(Opcode::OpGetBuiltin, &[0]),
+                make_with_operands(Opcode::OpArray, &[0]),
+                make_with_operands(Opcode::OpCall, &[1]),
+                make(Opcode::OpPop),
+                make_with_operands(Opcode::OpGetBuiltin, &[5]),
+                make_with_operands(Opcode::OpArray, &[0]),
+                make_with_operands(Opcode::OpConstant, &[0]),
+                make_with_operands(Opcode::OpCall, &[2]),
+                make(Opcode::OpPop),
+            ],
+        )];
+        run_compile_tests(tests);
+
+        let tests = vec![(
+            "fn() { len([]) }",
+            vec![Expect::Instructions(vec![
+                make_with_operands(Opcode::OpGetBuiltin, &[0]),
+                make_with_operands(Opcode::OpArray, &[0]),
+                make_with_operands(Opcode::OpCall, &[1]),
+                make(Opcode::OpReturnValue),
+            ])],
+            vec![
+                make_with_operands(Opcode::OpClosure, &[0, 0]),
+                make(Opcode::OpPop),
+            ],
+        )];
+        run_compile_tests(tests);
+    }
+
+    #[test]
+    fn test_closures() {
+        let tests = vec![
+            (
+                r#"
+                fn(a) {
+                    fn(b) {
+                        a + b
+                    }
+                }
+                "#,
+                vec![
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetFree, &[0]),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make(Opcode::OpAdd),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpClosure, &[0, 1]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                ],
+                vec![
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                r#"
+                fn(a) {
+                    fn(b) {
+                        fn(c) {
+                            a + b + c
+                        }
+                    }
+                };
+                "#,
+                vec![
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetFree, &[0]),
+                        make_with_operands(Opcode::OpGetFree, &[1]),
+                        make(Opcode::OpAdd),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make(Opcode::OpAdd),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetFree, &[0]),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpClosure, &[0, 2]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpClosure, &[1, 1]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                ],
+                vec![
+                    make_with_operands(Opcode::OpClosure, &[2, 0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                r#"
+                let global = 55;
+
+                fn() {
+                    let a = 66;
+
+                    fn() {
+                        let b = 77;
+
+                        fn() {
+                            let c = 88;
+
+                            global + a + b + c;
+                        }
+                    }
+                }
+                "#,
+                vec![
+                    Expect::Integer(55),
+                    Expect::Integer(66),
+                    Expect::Integer(77),
+                    Expect::Integer(88),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpConstant, &[3]),
+                        make_with_operands(Opcode::OpSetLocal, &[0]),
+                        make_with_operands(Opcode::OpGetGlobal, &[0]),
+                        make_with_operands(Opcode::OpGetFree, &[0]),
+                        make(Opcode::OpAdd),
+                        make_with_operands(Opcode::OpGetFree, &[1]),
+                        make(Opcode::OpAdd),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make(Opcode::OpAdd),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpConstant, &[2]),
+                        make_with_operands(Opcode::OpSetLocal, &[0]),
+                        make_with_operands(Opcode::OpGetFree, &[0]),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpClosure, &[4, 2]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpConstant, &[1]),
+                        make_with_operands(Opcode::OpSetLocal, &[0]),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpClosure, &[5, 1]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                ],
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpClosure, &[6, 0]),
                     make(Opcode::OpPop),
                 ],
             ),
         ];
+        run_compile_tests(tests);
+    }
 
+    #[test]
+    fn test_recursive_functions() {
+        let tests = vec![
+            (
+                r#"
+                let countDown = fn(x) { countDown(x - 1); };
+                countDown(1);
+                "#,
+                vec![
+                    Expect::Integer(1),
+                    Expect::Instructions(vec![
+                        make(Opcode::OpCurrentClosure),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpConstant, &[0]),
+                        make(Opcode::OpSub),
+                        make_with_operands(Opcode::OpCall, &[1]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Integer(1),
+                ],
+                vec![
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make_with_operands(Opcode::OpConstant, &[2]),
+                    make_with_operands(Opcode::OpCall, &[1]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                r#"
+                let wrapper = fn() {
+                    let countDown = fn(x) { countDown(x - 1); };
+                    countDown(1);
+                };
+                wrapper();
+                "#,
+                vec![
+                    Expect::Integer(1),
+                    Expect::Instructions(vec![
+                        make(Opcode::OpCurrentClosure),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpConstant, &[0]),
+                        make(Opcode::OpSub),
+                        make_with_operands(Opcode::OpCall, &[1]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Integer(1),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpClosure, &[1, 0]),
+                        make_with_operands(Opcode::OpSetLocal, &[0]),
+                        make_with_operands(Opcode::OpGetLocal, &[0]),
+                        make_with_operands(Opcode::OpConstant, &[2]),
+                        make_with_operands(Opcode::OpCall, &[1]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                ],
+                vec![
+                    make_with_operands(Opcode::OpClosure, &[3, 0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpGetGlobal, &[0]),
+                    make_with_operands(Opcode::OpCall, &[0]),
+                    make(Opcode::OpPop),
+                ],
+            ),
+            (
+                r#"
+                let a = fn() { b() };
+                let b = fn() { a() };
+                "#,
+                vec![
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetGlobal, &[1]),
+                        make_with_operands(Opcode::OpCall, &[0]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                    Expect::Instructions(vec![
+                        make_with_operands(Opcode::OpGetGlobal, &[0]),
+                        make_with_operands(Opcode::OpCall, &[0]),
+                        make(Opcode::OpReturnValue),
+                    ]),
+                ],
+                vec![
+                    make_with_operands(Opcode::OpClosure, &[0, 0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[0]),
+                    make_with_operands(Opcode::OpClosure, &[1, 0]),
+                    make_with_operands(Opcode::OpSetGlobal, &[1]),
+                ],
+            ),
+        ];
         run_compile_tests(tests);
     }
 
+    #[test]
+    fn test_undefined_variables() {
+        let tests = vec![
+            ("foobar", "foobar", vec![], (1, 1)),
+            ("let foobar = 1; fooba", "fooba", vec!["foobar"], (1, 17)),
+            ("fn(value) {\n  valeu\n}", "valeu", vec!["value"], (2, 3)),
+            ("lenn([1, 2])", "lenn", vec!["len"], (1, 1)),
+            (
+                "let abc = 1; let abd = 2; fn() { abx }",
+                "abx",
+                vec!["abc", "abd"],
+                (1, 34),
+            ),
+        ];
+
+        for (input, expected_name, expected_suggestions, (line, column)) in tests {
+            let program = parse(input.to_string());
+
+            let mut symbol_table_stack = new_symbol_table_stack();
+            let mut constants = new_constants();
+            let mut compiler = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+            match compiler.compile(program) {
+                Ok(_) => assert!(false, "expected compile error for {}", input),
+                Err(err) => assert_eq!(
+                    err,
+                    CompileError::UndefinedVariable {
+                        name: expected_name.to_string(),
+                        suggestions: expected_suggestions.iter().map(|s| s.to_string()).collect(),
+                        pos: Position::new(line, column),
+                    }
+                ),
+            }
+            // 失敗しても次の入力を compile できる状態に戻っている
+            assert_eq!(compiler.scope_index, 0);
+            assert_eq!(compiler.symbol_table_stack.stack.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_unexpanded_macro_literal() {
+        let program = parse("fn() { macro(x) { x } }".to_string());
+
+        let mut symbol_table_stack = new_symbol_table_stack();
+        let mut constants = new_constants();
+        let mut compiler = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+        match compiler.compile(program) {
+            Ok(_) => assert!(false, "expected compile error"),
+            Err(err) => assert_eq!(
+                err,
+                CompileError::MacroLiteral {
+                    pos: Position::new(1, 8)
+                }
+            ),
+        }
+    }
+
+    #[test]
+    fn test_compile_error_messages() {
+        let tests = vec![
+            (
+                CompileError::UndefinedVariable {
+                    name: "x".to_string(),
+                    suggestions: vec![],
+                    pos: Position::default(),
+                },
+                "undefined variable: x",
+            ),
+            (
+                CompileError::UndefinedVariable {
+                    name: "fo".to_string(),
+                    suggestions: vec!["foo".to_string()],
+                    pos: Position::default(),
+                },
+                "undefined variable: fo (did you mean `foo`?)",
+            ),
+            (
+                CompileError::UndefinedVariable {
+                    name: "ab".to_string(),
+                    suggestions: vec!["abc".to_string(), "abd".to_string()],
+                    pos: Position::default(),
+                },
+                "undefined variable: ab (did you mean one of `abc`, `abd`?)",
+            ),
+            (
+                CompileError::OutsideLoop {
+                    keyword: "break".to_string(),
+                    pos: Position::default(),
+                },
+                "break outside of loop",
+            ),
+            (
+                CompileError::OperandTooLarge {
+                    operand: "number of arguments".to_string(),
+                    value: 300,
+                    max: 255,
+                    pos: Position::default(),
+                },
+                "number of arguments 300 is too large (max 255)",
+            ),
+        ];
+
+        for (err, expected) in tests {
+            assert_eq!(format!("{}", err), expected);
+        }
+    }
+
     fn run_compile_tests<T: Expectable>(tests: Vec<(&str, Vec<T>, Vec<Instructions>)>) {
         for (input, expected_constants, expected_instructions) in tests {
             let program = parse(input.to_string());
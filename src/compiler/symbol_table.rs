@@ -1,15 +1,18 @@
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
-enum SymbolScope {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolScope {
     Global,
     Local,
+    Builtin,
+    Free,
+    Function,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Symbol {
     name: String,
-    scope: SymbolScope,
+    pub scope: SymbolScope,
     pub index: usize,
 }
 
@@ -19,9 +22,11 @@ impl Symbol {
     }
 }
 
+#[derive(Clone)]
 pub struct SymbolTable {
     store: HashMap<String, Symbol>,
     pub num_definitions: usize,
+    pub free_symbols: Vec<Symbol>,
 }
 
 pub struct SymbolTableStack {
@@ -33,6 +38,7 @@ impl SymbolTableStack {
         self.stack.push(SymbolTable {
             store: HashMap::new(),
             num_definitions: 0,
+            free_symbols: vec![],
         })
     }
 
@@ -52,6 +58,15 @@ impl SymbolTableStack {
         };
         let symbol_table = self.stack.last_mut().expect("There are no symbol_table");
 
+        // 同じ scope で定義済みの名前は同じ slot を使い回す
+        let defined = match symbol_table.store.get(name) {
+            Some(symbol) => symbol.scope == scope,
+            None => false,
+        };
+        if defined {
+            return symbol_table.store.get(name).unwrap();
+        }
+
         let symbol = Symbol {
             name: name.to_string(),
             scope,
@@ -62,15 +77,114 @@ impl SymbolTableStack {
         symbol_table.store.get(name).unwrap()
     }
 
-    pub fn resolve(&self, name: &str) -> Option<&Symbol> {
-        for symbol_table in self.stack.iter().rev() {
-            let result = symbol_table.store.get(name);
-            if result.is_some() {
-                return result;
+    pub fn define_function_name(&mut self, name: &str) -> &Symbol {
+        let symbol_table = self.stack.last_mut().expect("There are no symbol_table");
+
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope: SymbolScope::Function,
+            index: 0,
+        };
+        symbol_table.store.insert(name.to_string(), symbol);
+        symbol_table.store.get(name).unwrap()
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> &Symbol {
+        let symbol_table = self.stack.first_mut().expect("There are no symbol_table");
+
+        let symbol = Symbol {
+            name: name.to_string(),
+            scope: SymbolScope::Builtin,
+            index,
+        };
+        symbol_table.store.insert(name.to_string(), symbol);
+        symbol_table.store.get(name).unwrap()
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        let level = self.stack.len() - 1;
+        self.resolve_at(level, name)
+    }
+
+    fn resolve_at(&mut self, level: usize, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.stack[level].store.get(name) {
+            return Some(symbol.clone());
+        }
+        if level == 0 {
+            return None;
+        }
+
+        let symbol = self.resolve_at(level - 1, name)?;
+        match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => Some(symbol),
+            // 外側の関数の local は free variable として取り込む
+            _ => Some(self.define_free(level, symbol)),
+        }
+    }
+
+    fn define_free(&mut self, level: usize, original: Symbol) -> Symbol {
+        let symbol_table = &mut self.stack[level];
+
+        let symbol = Symbol {
+            name: original.name.clone(),
+            scope: SymbolScope::Free,
+            index: symbol_table.free_symbols.len(),
+        };
+        symbol_table.free_symbols.push(original);
+        symbol_table
+            .store
+            .insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    // resolve できなかった名前に近い名前を、近い順に最大 3 つ返す
+    pub fn similar_names(&self, name: &str) -> Vec<String> {
+        let max_distance = std::cmp::max(1, name.chars().count() / 3);
+
+        let mut candidates: Vec<(usize, &String)> = self
+            .stack
+            .iter()
+            .flat_map(|symbol_table| symbol_table.store.keys())
+            // compiler が内部で使う名前は候補にしない
+            .filter(|candidate| !candidate.contains('@'))
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        candidates.sort();
+        candidates.dedup_by(|a, b| a.1 == b.1);
+
+        candidates
+            .into_iter()
+            .take(3)
+            .map(|(_, candidate)| candidate.clone())
+            .collect()
+    }
+}
+
+// 隣接文字の入れ替えも 1 とする編集距離 (optimal string alignment)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
             }
         }
-        None
     }
+    d[a.len()][b.len()]
 }
 
 pub fn new_symbol_table_stack() -> SymbolTableStack {
@@ -160,7 +274,7 @@ mod tests {
 
         for sym in &expected {
             if let Some(result) = stack.resolve(&sym.name) {
-                assert_eq!(sym, result);
+                assert_eq!(sym, &result);
             } else {
                 assert!(false, format!("name {} not resolvable", sym.name));
             }
@@ -205,13 +319,57 @@ mod tests {
 
         for sym in &expected {
             if let Some(result) = stack.resolve(&sym.name) {
-                assert_eq!(sym, result);
+                assert_eq!(sym, &result);
             } else {
                 assert!(false, format!("name {} not resulved", sym.name))
             }
         }
     }
 
+    #[test]
+    fn test_define_resolve_builtins() {
+        let mut stack = new_symbol_table_stack();
+
+        let expected = [
+            Symbol {
+                name: "a".to_string(),
+                scope: SymbolScope::Builtin,
+                index: 0,
+            },
+            Symbol {
+                name: "c".to_string(),
+                scope: SymbolScope::Builtin,
+                index: 1,
+            },
+            Symbol {
+                name: "e".to_string(),
+                scope: SymbolScope::Builtin,
+                index: 2,
+            },
+            Symbol {
+                name: "f".to_string(),
+                scope: SymbolScope::Builtin,
+                index: 3,
+            },
+        ];
+
+        for (i, sym) in expected.iter().enumerate() {
+            stack.define_builtin(i, &sym.name);
+        }
+
+        // builtins are visible from every nested scope
+        for _ in 0..3 {
+            for sym in &expected {
+                if let Some(result) = stack.resolve(&sym.name) {
+                    assert_eq!(sym, &result);
+                } else {
+                    assert!(false, "name {} not resolvable", sym.name)
+                }
+            }
+            stack.push();
+        }
+    }
+
     #[test]
     fn test_resolve_nested_local() {
         let mut stack = new_symbol_table_stack();
@@ -256,7 +414,7 @@ mod tests {
             ];
             for sym in &tests {
                 if let Some(result) = stack.resolve(&sym.name) {
-                    assert_eq!(sym, result);
+                    assert_eq!(sym, &result);
                 } else {
                     assert!(false, format!("name {} not resulved", sym.name))
                 }
@@ -290,11 +448,203 @@ mod tests {
             ];
             for sym in &tests {
                 if let Some(result) = stack.resolve(&sym.name) {
-                    assert_eq!(sym, result);
+                    assert_eq!(sym, &result);
                 } else {
                     assert!(false, format!("name {} not resulved", sym.name))
                 }
             }
         }
     }
+
+    #[test]
+    fn test_resolve_free() {
+        let mut stack = new_symbol_table_stack();
+
+        // global
+        stack.define("a");
+        stack.define("b");
+
+        // first local
+        stack.push();
+        stack.define("c");
+        stack.define("d");
+
+        // second local
+        stack.push();
+        stack.define("e");
+        stack.define("f");
+
+        let symbol = |name: &str, scope, index| Symbol {
+            name: name.to_string(),
+            scope,
+            index,
+        };
+
+        // test second local
+        {
+            let tests = [
+                symbol("a", SymbolScope::Global, 0),
+                symbol("b", SymbolScope::Global, 1),
+                symbol("c", SymbolScope::Free, 0),
+                symbol("d", SymbolScope::Free, 1),
+                symbol("e", SymbolScope::Local, 0),
+                symbol("f", SymbolScope::Local, 1),
+            ];
+            for sym in &tests {
+                if let Some(result) = stack.resolve(&sym.name) {
+                    assert_eq!(sym, &result);
+                } else {
+                    assert!(false, "name {} not resolvable", sym.name)
+                }
+            }
+            assert_eq!(
+                stack.last().free_symbols,
+                vec![
+                    symbol("c", SymbolScope::Local, 0),
+                    symbol("d", SymbolScope::Local, 1),
+                ]
+            );
+        }
+
+        // test first local
+        stack.pop();
+        {
+            let tests = [
+                symbol("a", SymbolScope::Global, 0),
+                symbol("b", SymbolScope::Global, 1),
+                symbol("c", SymbolScope::Local, 0),
+                symbol("d", SymbolScope::Local, 1),
+            ];
+            for sym in &tests {
+                if let Some(result) = stack.resolve(&sym.name) {
+                    assert_eq!(sym, &result);
+                } else {
+                    assert!(false, "name {} not resolvable", sym.name)
+                }
+            }
+            assert!(stack.last().free_symbols.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_resolve_unresolvable_free() {
+        let mut stack = new_symbol_table_stack();
+
+        // global
+        stack.define("a");
+
+        // first local
+        stack.push();
+        stack.define("c");
+
+        // second local
+        stack.push();
+        stack.define("e");
+        stack.define("f");
+
+        let expected = [
+            Symbol {
+                name: "a".to_string(),
+                scope: SymbolScope::Global,
+                index: 0,
+            },
+            Symbol {
+                name: "c".to_string(),
+                scope: SymbolScope::Free,
+                index: 0,
+            },
+            Symbol {
+                name: "e".to_string(),
+                scope: SymbolScope::Local,
+                index: 0,
+            },
+            Symbol {
+                name: "f".to_string(),
+                scope: SymbolScope::Local,
+                index: 1,
+            },
+        ];
+
+        for sym in &expected {
+            if let Some(result) = stack.resolve(&sym.name) {
+                assert_eq!(sym, &result);
+            } else {
+                assert!(false, "name {} not resolvable", sym.name)
+            }
+        }
+
+        for name in &["b", "d"] {
+            assert_eq!(stack.resolve(name), None, "name {} resolved", name);
+        }
+    }
+
+    #[test]
+    fn test_redefine() {
+        let mut stack = new_symbol_table_stack();
+
+        stack.define("a");
+        stack.define("b");
+        assert_eq!(stack.define("a").index, 0);
+        assert_eq!(stack.last().num_definitions, 2);
+
+        stack.push();
+        stack.define("a");
+        stack.define("c");
+        assert_eq!(stack.define("c").index, 1);
+        assert_eq!(stack.last().num_definitions, 2);
+    }
+
+    #[test]
+    fn test_define_and_resolve_function_name() {
+        let mut stack = new_symbol_table_stack();
+        stack.push();
+        stack.define_function_name("a");
+
+        let expected = Symbol {
+            name: "a".to_string(),
+            scope: SymbolScope::Function,
+            index: 0,
+        };
+
+        assert_eq!(stack.resolve("a"), Some(expected));
+    }
+
+    #[test]
+    fn test_shadowing_function_name() {
+        let mut stack = new_symbol_table_stack();
+        stack.push();
+        stack.define_function_name("a");
+        stack.define("a");
+
+        let expected = Symbol {
+            name: "a".to_string(),
+            scope: SymbolScope::Local,
+            index: 0,
+        };
+
+        assert_eq!(stack.resolve("a"), Some(expected));
+    }
+
+    #[test]
+    fn test_similar_names() {
+        let mut stack = new_symbol_table_stack();
+        stack.define("counter");
+        stack.define("count");
+        stack.define("total");
+
+        stack.push();
+        stack.define("value");
+
+        assert_eq!(stack.similar_names("countr"), vec!["count", "counter"]);
+        assert_eq!(stack.similar_names("vaule"), vec!["value"]);
+        assert_eq!(stack.similar_names("x"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+        assert_eq!(edit_distance("ab", "ba"), 1);
+    }
 }
@@ -0,0 +1,58 @@
+use super::token::Position;
+
+/*
+エラーを位置付きで表示する
+
+```
+script.monkey:2:9: undefined variable: foo
+let x = foo;
+        ^
+```
+*/
+pub fn render(file: &str, source: &str, pos: Position, message: &str) -> String {
+    let mut out = format!("{}:{}: {}", file, pos, message);
+
+    if let Some(line) = source.lines().nth(pos.line.wrapping_sub(1)) {
+        // tab はそのまま残して caret の位置を揃える
+        let indent: String = line
+            .chars()
+            .take(pos.column.saturating_sub(1))
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!("\n{}\n{}^", line, indent));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Position};
+
+    #[test]
+    fn test_render() {
+        let tests = vec![
+            (
+                "let a = 1;\nlet x = foo;",
+                Position::new(2, 9),
+                "script.monkey:2:9: undefined variable: foo\nlet x = foo;\n        ^",
+            ),
+            (
+                "\tlet x = foo;",
+                Position::new(1, 10),
+                "script.monkey:1:10: undefined variable: foo\n\tlet x = foo;\n\t        ^",
+            ),
+            (
+                "let x = foo;",
+                Position::new(3, 1),
+                "script.monkey:3:1: undefined variable: foo",
+            ),
+        ];
+
+        for (source, pos, expected) in tests {
+            assert_eq!(
+                render("script.monkey", source, pos, "undefined variable: foo"),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use super::code::{lookup, read_operands, Instructions, Opcode};
+use super::object::builtin::BUILTINS;
+use super::object::{CompiledFunction, Object};
+use std::collections::{BTreeMap, HashSet};
+
+/*
+main と constants にある関数をすべて一覧にする
+
+```
+== main ==
+0000 OpClosure 1 0            ; fn#1
+0004 OpSetGlobal 0
+...
+
+== fn#1 (1 parameters, 1 locals) ==
+0000 OpGetLocal 0
+0002 OpJumpNotTruthy 9        ; L0
+0005 OpConstant 0             ; 1
+0008 OpReturnValue
+L0:
+0009 OpNull
+...
+```
+
+関数は main から参照される順にたどり、どこからも参照されないものは最後に並べる
+*/
+pub fn disassemble(main: &CompiledFunction, constants: &[Object]) -> String {
+    let mut order = vec![];
+    let mut visited = HashSet::new();
+    collect_functions(&main.instructions, constants, &mut visited, &mut order);
+    for (index, constant) in constants.iter().enumerate() {
+        if let Object::CompiledFunction(func) = constant {
+            if visited.insert(index) {
+                order.push(index);
+                collect_functions(&func.instructions, constants, &mut visited, &mut order);
+            }
+        }
+    }
+
+    let mut out = String::from("== main ==\n");
+    out.push_str(&disassemble_instructions(&main.instructions, constants));
+    for index in order {
+        if let Object::CompiledFunction(func) = &constants[index] {
+            out.push_str(&format!(
+                "\n== fn#{} ({} parameters, {} locals) ==\n",
+                index, func.num_parameters, func.num_locals
+            ));
+            out.push_str(&disassemble_instructions(&func.instructions, constants));
+        }
+    }
+    out
+}
+
+// 深さ優先で、参照された関数の constant index を順に集める
+fn collect_functions(
+    ins: &Instructions,
+    constants: &[Object],
+    visited: &mut HashSet<usize>,
+    order: &mut Vec<usize>,
+) {
+    for (_, op, operands) in decode(ins) {
+        if let (Some(Opcode::OpClosure | Opcode::OpConstant), Some(&index)) = (op, operands.first())
+        {
+            if let Some(Object::CompiledFunction(func)) = constants.get(index) {
+                if visited.insert(index) {
+                    order.push(index);
+                    collect_functions(&func.instructions, constants, visited, order);
+                }
+            }
+        }
+    }
+}
+
+fn disassemble_instructions(ins: &Instructions, constants: &[Object]) -> String {
+    let decoded = decode(ins);
+
+    // jump 先にはオフセット順にラベルを振る
+    let mut labels = BTreeMap::new();
+    for (_, op, operands) in &decoded {
+        if let Some(
+            Opcode::OpJump
+            | Opcode::OpJumpNotTruthy
+            | Opcode::OpJumpNotTruthyOrPop
+            | Opcode::OpJumpTruthyOrPop,
+        ) = op
+        {
+            labels.insert(operands[0], 0);
+        }
+    }
+    for (i, label) in labels.values_mut().enumerate() {
+        *label = i;
+    }
+
+    let mut out = String::new();
+    for (offset, op, operands) in decoded {
+        if let Some(label) = labels.get(&offset) {
+            out.push_str(&format!("L{}:\n", label));
+        }
+        let op = match op {
+            Some(op) => op,
+            None => {
+                out.push_str(&format!(
+                    "{:04} ERROR: unknown opcode {}\n",
+                    offset, ins.0[offset]
+                ));
+                break;
+            }
+        };
+
+        let text = ins.fmt_instruction(lookup(&op), operands.clone());
+        match annotation(op, &operands, constants, &labels) {
+            Some(note) => out.push_str(&format!("{:04} {:<24} ; {}\n", offset, text, note)),
+            None => out.push_str(&format!("{:04} {}\n", offset, text)),
+        }
+    }
+    // 関数の末尾への jump
+    if let Some(label) = labels.get(&ins.0.len()) {
+        out.push_str(&format!("L{}:\n", label));
+    }
+    out
+}
+
+// (offset, opcode, operands) の列。不明な opcode や途中で切れた命令があればそこで止める
+fn decode(ins: &Instructions) -> Vec<(usize, Option<Opcode>, Vec<usize>)> {
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < ins.0.len() {
+        let op = match Opcode::from_byte(ins.0[i]) {
+            Some(op) => op,
+            None => {
+                decoded.push((i, None, vec![]));
+                break;
+            }
+        };
+        let def = lookup(&op);
+        let width: usize = def.operand_width.iter().sum();
+        if i + 1 + width > ins.0.len() {
+            decoded.push((i, None, vec![]));
+            break;
+        }
+        let (operands, read) = read_operands(&def, &ins.0[i + 1..]);
+        decoded.push((i, Some(op), operands));
+        i += 1 + read;
+    }
+    decoded
+}
+
+fn annotation(
+    op: Opcode,
+    operands: &[usize],
+    constants: &[Object],
+    labels: &BTreeMap<usize, usize>,
+) -> Option<String> {
+    match op {
+        Opcode::OpConstant | Opcode::OpClosure => {
+            constants.get(operands[0]).map(|constant| match constant {
+                Object::CompiledFunction(_) => format!("fn#{}", operands[0]),
+                Object::String(s) => format!("{:?}", s),
+                other => format!("{}", other),
+            })
+        }
+        Opcode::OpJump
+        | Opcode::OpJumpNotTruthy
+        | Opcode::OpJumpNotTruthyOrPop
+        | Opcode::OpJumpTruthyOrPop => labels.get(&operands[0]).map(|label| format!("L{}", label)),
+        Opcode::OpGetBuiltin => BUILTINS
+            .get(operands[0])
+            .map(|builtin| builtin.name.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::session::{Engine, Session};
+    use super::*;
+
+    fn disassemble_input(input: &str) -> String {
+        let file = Session::new(Engine::Vm).compile("test", input).unwrap();
+        disassemble(&file.main, &file.constants)
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let input = r#"
+        let f = fn(x) {
+            let g = fn() { x };
+            if (x) { g() } else { len("ab") }
+        };
+        f(1);
+        "#;
+        let expected = r#"== main ==
+0000 OpClosure 2 0            ; fn#2
+0004 OpSetGlobal 0
+0007 OpGetGlobal 0
+0010 OpConstant 3             ; 1
+0013 OpCall 1
+0015 OpPop
+
+== fn#2 (1 parameters, 2 locals) ==
+0000 OpGetLocal 0
+0002 OpClosure 0 1            ; fn#0
+0006 OpSetLocal 1
+0008 OpGetLocal 0
+0010 OpJumpNotTruthy 20       ; L0
+0013 OpGetLocal 1
+0015 OpCall 0
+0017 OpJump 27                ; L1
+L0:
+0020 OpGetBuiltin 0           ; len
+0022 OpConstant 1             ; "ab"
+0025 OpCall 1
+L1:
+0027 OpReturnValue
+
+== fn#0 (0 parameters, 0 locals) ==
+0000 OpGetFree 0
+0002 OpReturnValue
+"#;
+        assert_eq!(disassemble_input(input), expected);
+    }
+
+    #[test]
+    fn test_disassemble_unknown_opcode() {
+        let main = CompiledFunction {
+            instructions: Instructions(vec![Opcode::OpTrue.byte(), 255]),
+            num_locals: 0,
+            num_parameters: 0,
+            positions: vec![],
+        };
+        assert_eq!(
+            disassemble(&main, &[]),
+            "== main ==\n0000 OpTrue\n0001 ERROR: unknown opcode 255\n"
+        );
+    }
+}
@@ -1,40 +1,65 @@
+use super::arithmetic::{self, IntegerMode};
 use super::ast::{
-    modify, ArrayLiteral, BlockStatement, Boolean, CallExpression, Expression, FunctionLiteral,
-    HashLiteral, Identifier, IfExpression, IndexExpression, InfixExpression, IntegerLiteral, Node,
-    PrefixExpression, Program, Statement, StringLiteral,
+    modify, ArrayLiteral, AssignExpression, BlockStatement, Boolean, CallExpression, Expression,
+    FloatLiteral, ForStatement, FunctionLiteral, HashLiteral, Identifier, IfExpression,
+    IndexExpression, InfixExpression, IntegerLiteral, InterpolatedString, Node, PrefixExpression,
+    Program, Statement, StringLiteral, WhileStatement,
 };
+use super::object::builtin::{get_builtin_by_name, string, Caller};
 use super::object::hash::hash_key_of;
-use super::object::{Array, Builtin, Function, Hash, HashPair, Macro, Object, Quote};
-use std::cell::RefCell;
+use super::object::{
+    iteration_items, update_index, Array, Function, Hash, HashPair, Macro, Object, Quote,
+};
+use super::token::Position;
+use super::vm::MAX_FRAMES;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
 const TRUE: Object = Object::Boolean(true);
 const FALSE: Object = Object::Boolean(false);
 const NULL: Object = Object::Null;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+thread_local! {
+    // 評価中の関数呼び出しの深さ。main を 1 つ目の frame とする VM と数え方をそろえる
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Environment {
     store: HashMap<String, Object>,
     outer: Option<Rc<RefCell<Environment>>>,
+    // 外側の環境から引き継ぐ
+    integer_mode: IntegerMode,
 }
 
 impl Environment {
     pub fn new() -> Rc<RefCell<Environment>> {
         let store = HashMap::new();
-        let env = Environment { store, outer: None };
+        let env = Environment {
+            store,
+            outer: None,
+            integer_mode: IntegerMode::default(),
+        };
         Rc::new(RefCell::new(env))
     }
 
     pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
         let store = HashMap::new();
+        let integer_mode = outer.borrow().integer_mode;
         let env = Environment {
             store,
             outer: Some(outer),
+            integer_mode,
         };
         Rc::new(RefCell::new(env))
     }
 
+    pub fn set_integer_mode(&mut self, mode: IntegerMode) {
+        self.integer_mode = mode;
+    }
+
     fn get(&self, name: &str) -> Option<Object> {
         if let Some(o) = self.store.get(name) {
             Some(o.clone())
@@ -48,6 +73,66 @@ impl Environment {
     fn set(&mut self, name: &str, val: &Object) {
         self.store.insert(name.to_string(), val.clone());
     }
+
+    // 関数を作るときの環境。VM と同じく、外側の local は今の値を写し取り、
+    // 一番外側 (global) の環境だけを共有する
+    fn capture(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut store = HashMap::new();
+        let mut current = Rc::clone(env);
+        loop {
+            let outer = match &current.borrow().outer {
+                Some(outer) => Rc::clone(outer),
+                None => break,
+            };
+            for (name, val) in &current.borrow().store {
+                store.entry(name.clone()).or_insert_with(|| val.clone());
+            }
+            current = outer;
+        }
+        if Rc::ptr_eq(&current, env) {
+            return current;
+        }
+        let integer_mode = env.borrow().integer_mode;
+        Rc::new(RefCell::new(Environment {
+            store,
+            outer: Some(current),
+            integer_mode,
+        }))
+    }
+
+    // VM に合わせて、代入できるのは今の環境と一番外側の環境の変数だけにする
+    // 途中の環境にあるのは関数が取り込んだ変数なので、書き換えられない
+    fn assign(&mut self, name: &str, val: &Object) -> Result<(), String> {
+        if self.store.contains_key(name) {
+            self.set(name, val);
+            return Ok(());
+        }
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().assign_outer(name, val),
+            None => Err(not_found_message(name)),
+        }
+    }
+
+    fn assign_outer(&mut self, name: &str, val: &Object) -> Result<(), String> {
+        match &self.outer {
+            None if self.store.contains_key(name) => {
+                self.set(name, val);
+                Ok(())
+            }
+            Some(_) if self.store.contains_key(name) => {
+                Err(format!("cannot assign to captured variable `{}`", name))
+            }
+            Some(outer) => outer.borrow_mut().assign_outer(name, val),
+            None => Err(not_found_message(name)),
+        }
+    }
+}
+
+fn not_found_message(name: &str) -> String {
+    match get_builtin_by_name(name) {
+        Some(_) => format!("cannot assign to builtin `{}`", name),
+        None => format!("identifier not found: {}", name),
+    }
 }
 
 pub trait Eval {
@@ -72,6 +157,7 @@ impl_eval!(Program => (self, env) {
         match result {
             Object::ReturnValue(return_value) => return *return_value,
             Object::Error(_) => return result,
+            Object::Break | Object::Continue => return outside_of_loop(&result),
             _ => (),
         }
     }
@@ -95,25 +181,84 @@ impl_eval!(Statement => (self, env) {
         Statement::ExpressionStatement(stmt) => stmt.expression.eval(env),
         Statement::ReturnStatement(stmt) => {
             let val = stmt.return_value.eval(env);
-            if is_error(&val) {
+            if is_error(&val) || is_loop_signal(&val) {
                 return val;
             }
             Object::ReturnValue(Box::new(val))
         }
         Statement::LetStatement(stmt) => {
             let val = stmt.value.eval(env);
-            if is_error(&val) {
+            if is_error(&val) || is_loop_signal(&val) {
                 return val;
             }
+            // 写し取った環境にはまだ自分がいないので、本体から自分自身を呼べるように加える
+            if let (Expression::FunctionLiteral(_), Object::Function(function)) = (&stmt.value, &val) {
+                if !Rc::ptr_eq(&function.env, env) {
+                    function.env.borrow_mut().set(&stmt.name.value, &val);
+                }
+            }
             env.borrow_mut().set(&stmt.name.value, &val);
-            val
+            // VM と同じく、let で終わる block の値は null
+            NULL
+        }
+        Statement::WhileStatement(stmt) => stmt.eval(env),
+        Statement::ForStatement(stmt) => stmt.eval(env),
+        Statement::BreakStatement(_) => Object::Break,
+        Statement::ContinueStatement(_) => Object::Continue,
+    }
+});
+
+// ループは値を持たないので null になる
+impl_eval!(WhileStatement => (self, env) {
+    loop {
+        let condition = self.condition.eval(env);
+        if is_error(&condition) || is_loop_signal(&condition) {
+            return condition;
+        }
+        if !is_truthy(condition) {
+            return NULL;
+        }
+
+        match self.body.eval(env) {
+            Object::Break => return NULL,
+            result @ (Object::ReturnValue(_) | Object::Error(_)) => return result,
+            _ => (),
         }
     }
 });
 
+impl_eval!(ForStatement => (self, env) {
+    let iterable = self.iterable.eval(env);
+    if is_error(&iterable) || is_loop_signal(&iterable) {
+        return iterable;
+    }
+    let items = match iteration_items(&iterable, self.value.is_some()) {
+        Ok(items) => items,
+        Err(message) => return new_error(message),
+    };
+
+    for item in items {
+        match (&self.value, item) {
+            (Some(value), Object::Array(pair)) => {
+                env.borrow_mut().set(&self.key.value, &pair.elements[0]);
+                env.borrow_mut().set(&value.value, &pair.elements[1]);
+            }
+            (_, item) => env.borrow_mut().set(&self.key.value, &item),
+        }
+
+        match self.body.eval(env) {
+            Object::Break => return NULL,
+            result @ (Object::ReturnValue(_) | Object::Error(_)) => return result,
+            _ => (),
+        }
+    }
+    NULL
+});
+
 impl_eval!(Expression => (self, env) {
     match self {
         Expression::IntegerLiteral(exp) => exp.eval(env),
+        Expression::FloatLiteral(exp) => exp.eval(env),
         Expression::Boolean(exp) => exp.eval(env),
         Expression::PrefixExpression(exp) => exp.eval(env),
         Expression::InfixExpression(exp) => exp.eval(env),
@@ -122,15 +267,21 @@ impl_eval!(Expression => (self, env) {
         Expression::FunctionLiteral(exp) => exp.eval(env),
         Expression::CallExpression(exp) => exp.eval(env),
         Expression::StringLiteral(exp) => exp.eval(env),
+        Expression::InterpolatedString(exp) => exp.eval(env),
         Expression::ArrayLiteral(exp) => exp.eval(env),
         Expression::IndexExpression(exp) => exp.eval(env),
         Expression::HashLiteral(exp) => exp.eval(env),
-        _ => unimplemented!()
+        Expression::AssignExpression(exp) => exp.eval(env),
+        Expression::MacroLiteral(_) => new_error(String::from(
+            "macro literal is only allowed in a top-level let statement",
+        )),
     }
 });
 
 impl_eval!(IntegerLiteral => (self, _env) { Object::Integer(self.value) });
 
+impl_eval!(FloatLiteral => (self, _env) { Object::Float(self.value) });
+
 impl_eval!(Boolean => (self, _env) {
     native_bool_to_boolean_object(self.value)
 });
@@ -145,17 +296,18 @@ fn native_bool_to_boolean_object(input: bool) -> Object {
 
 impl_eval!(PrefixExpression => (self, env) {
     let right = self.right.eval(env);
-    if is_error(&right) {
+    if is_error(&right) || is_loop_signal(&right) {
         return right;
     }
-    eval_prefix_expression(&self.operator, right)
+    let mode = env.borrow().integer_mode;
+    eval_prefix_expression(&self.operator, right, mode)
 });
 
-fn eval_prefix_expression(operator: &str, right: Object) -> Object {
+fn eval_prefix_expression(operator: &str, right: Object, mode: IntegerMode) -> Object {
     match operator {
         "!" => eval_bang_operator_expression(right),
-        "-" => eval_minus_prefix_operator_expression(right),
-        _ => new_error(format!("unknown operator: {}{:?}", operator, right)),
+        "-" => eval_minus_prefix_operator_expression(right, mode),
+        _ => new_error(arithmetic::prefix_error(operator, &right)),
     }
 }
 
@@ -168,77 +320,159 @@ fn eval_bang_operator_expression(right: Object) -> Object {
     }
 }
 
-fn eval_minus_prefix_operator_expression(right: Object) -> Object {
-    if let Object::Integer(value) = right {
-        Object::Integer(-value)
-    } else {
-        new_error(format!("unknown operator: -{:?}", right))
+fn eval_minus_prefix_operator_expression(right: Object, mode: IntegerMode) -> Object {
+    match right {
+        Object::Integer(value) => match arithmetic::integer_negate(mode, value) {
+            Ok(result) => Object::Integer(result),
+            Err(message) => new_error(message),
+        },
+        Object::Float(value) => Object::Float(-value),
+        _ => new_error(arithmetic::prefix_error("-", &right)),
     }
 }
 
 impl_eval!(InfixExpression => (self, env) {
     let left = self.left.eval(env);
-    if is_error(&left) {
+    if is_error(&left) || is_loop_signal(&left) {
         return left;
     }
+    // 右辺を評価しないときは左辺の値になる
+    if self.operator == "&&" || self.operator == "||" {
+        return match (&*self.operator, is_truthy(left.clone())) {
+            ("&&", false) | ("||", true) => left,
+            _ => self.right.eval(env),
+        };
+    }
     let right = self.right.eval(env);
-    if is_error(&right) {
+    if is_error(&right) || is_loop_signal(&right) {
         return right;
     }
-    eval_infix_expression(&self.operator, left, right)
+    let mode = env.borrow().integer_mode;
+    eval_infix_expression(&self.operator, left, right, mode)
+});
+
+impl_eval!(AssignExpression => (self, env) {
+    let mut indices = vec![];
+    let mut base = &*self.target;
+    while let Expression::IndexExpression(exp) = base {
+        indices.push(&*exp.index);
+        base = &exp.left;
+    }
+    indices.reverse();
+    let ident = match base {
+        Expression::Identifier(ident) => ident,
+        _ => return new_error(format!("cannot assign to {}", self.target)),
+    };
+    let mode = env.borrow().integer_mode;
+
+    // VM と同じく、代入先、index、右辺の順に評価する
+    let (target_value, value) = if indices.is_empty() {
+        let value = match self.infix_operator() {
+            Some(operator) => {
+                let current = ident.eval(env);
+                if is_error(&current) {
+                    return current;
+                }
+                let right = self.value.eval(env);
+                if is_error(&right) || is_loop_signal(&right) {
+                    return right;
+                }
+                eval_infix_expression(operator, current, right, mode)
+            }
+            None => self.value.eval(env),
+        };
+        if is_error(&value) || is_loop_signal(&value) {
+            return value;
+        }
+        (value.clone(), value)
+    } else {
+        let container = ident.eval(env);
+        if is_error(&container) {
+            return container;
+        }
+        let mut index_values = vec![];
+        for index in indices {
+            let index_value = index.eval(env);
+            if is_error(&index_value) || is_loop_signal(&index_value) {
+                return index_value;
+            }
+            index_values.push(index_value);
+        }
+        let right = self.value.eval(env);
+        if is_error(&right) || is_loop_signal(&right) {
+            return right;
+        }
+        let updated = update_index(container, &index_values, |current| {
+            match self.infix_operator() {
+                Some(operator) => match eval_infix_expression(operator, current, right, mode) {
+                    Object::Error(message) => Err(message),
+                    value => Ok(value),
+                },
+                None => Ok(right),
+            }
+        });
+        match updated {
+            Ok(updated) => updated,
+            Err(message) => return new_error(message),
+        }
+    };
+
+    match env.borrow_mut().assign(&ident.value, &target_value) {
+        Ok(()) => value,
+        Err(message) => new_error(message),
+    }
 });
 
-fn eval_infix_expression(operator: &str, left: Object, right: Object) -> Object {
+fn eval_infix_expression(operator: &str, left: Object, right: Object, mode: IntegerMode) -> Object {
+    // 1 == 1.0 のように数値として比べる
+    if let Some((l, r)) = arithmetic::float_operands(&left, &right) {
+        return eval_float_infix_expression(operator, l, r);
+    }
     if operator == "==" {
         return native_bool_to_boolean_object(left == right);
     }
     if operator == "!=" {
         return native_bool_to_boolean_object(left != right);
     }
-    if let Object::Integer(l) = left {
-        if let Object::Integer(r) = right {
-            return eval_integer_infix_expression(operator, l, r);
+    match (&left, &right) {
+        (Object::Integer(l), Object::Integer(r)) => {
+            eval_integer_infix_expression(operator, *l, *r, mode)
         }
-        return new_error(format!(
-            "type mismatch: {:?} {} {:?}",
-            left, operator, right
-        ));
-    }
-    if let Object::String(l) = &left {
-        if let Object::String(r) = &right {
-            return eval_string_infix_expression(operator, l, r);
-        }
-        return new_error(format!(
-            "type mismatch: {:?} {} {:?}",
-            left, operator, right
-        ));
+        (Object::String(l), Object::String(r)) => eval_string_infix_expression(operator, l, r),
+        _ => new_error(arithmetic::infix_error(operator, &left, &right)),
     }
-    new_error(format!(
-        "unknown operator: {:?} {} {:?}",
-        left, operator, right
-    ))
 }
 
-fn eval_integer_infix_expression(operator: &str, left_val: i64, right_val: i64) -> Object {
+fn eval_integer_infix_expression(
+    operator: &str,
+    left_val: i64,
+    right_val: i64,
+    mode: IntegerMode,
+) -> Object {
     match operator {
-        "+" => Object::Integer(left_val + right_val),
-        "-" => Object::Integer(left_val - right_val),
-        "*" => Object::Integer(left_val * right_val),
-        "/" => Object::Integer(left_val / right_val),
+        "+" | "-" | "*" | "/" | "%" => {
+            match arithmetic::integer_infix(mode, operator, left_val, right_val) {
+                Ok(result) => Object::Integer(result),
+                Err(message) => new_error(message),
+            }
+        }
         "<" => native_bool_to_boolean_object(left_val < right_val),
         ">" => native_bool_to_boolean_object(left_val > right_val),
+        "<=" => native_bool_to_boolean_object(left_val <= right_val),
+        ">=" => native_bool_to_boolean_object(left_val >= right_val),
         "==" => native_bool_to_boolean_object(left_val == right_val),
         "!=" => native_bool_to_boolean_object(left_val != right_val),
-        _ => new_error(format!(
-            "unknown operator: {:?} {} {:?}",
-            left_val, operator, right_val
+        _ => new_error(arithmetic::infix_error(
+            operator,
+            &Object::Integer(left_val),
+            &Object::Integer(right_val),
         )),
     }
 }
 
 impl_eval!(IfExpression => (self, env) {
     let condition = self.condition.eval(env);
-    if is_error(&condition) {
+    if is_error(&condition) || is_loop_signal(&condition) {
         return condition;
     }
 
@@ -259,6 +493,7 @@ impl_eval!(BlockStatement => (self, env) {
         match result {
             Object::ReturnValue(_) => return result,
             Object::Error(_) => return result,
+            Object::Break | Object::Continue => return result,
             _ => (),
         }
     }
@@ -269,14 +504,9 @@ impl_eval!(Identifier => (self, env) {
     if let Some(val) = env.borrow().get(&self.value) {
         return val;
     }
-    match self.value.as_str() {
-        "len" => Object::Builtin(Builtin{func: builtin::len}),
-        "first" => Object::Builtin(Builtin{func: builtin::first}),
-        "last" => Object::Builtin(Builtin{func: builtin::last}),
-        "rest" => Object::Builtin(Builtin{func: builtin::rest}),
-        "push" => Object::Builtin(Builtin{func: builtin::push}),
-        "puts" => Object::Builtin(Builtin{func: builtin::puts}),
-        _ => new_error(format!("identifier not found: {}", self.value))
+    match get_builtin_by_name(&self.value) {
+        Some(builtin) => Object::Builtin(builtin),
+        None => new_error(format!("identifier not found: {}", self.value))
     }
 });
 
@@ -284,7 +514,7 @@ impl_eval!(FunctionLiteral => (self, env) {
     Object::Function(Function {
         parameters: self.parameters.clone(),
         body: *self.body.clone(),
-        env: Rc::clone(&env),
+        env: Environment::capture(env),
     })
 });
 
@@ -295,13 +525,13 @@ impl_eval!(CallExpression => (self, env) {
         }
     }
     let function = self.function.eval(env);
-    if is_error(&function) {
+    if is_error(&function) || is_loop_signal(&function) {
         return function;
     }
     let mut args: Vec<Object> = vec![];
     for argument in &self.arguments {
         let evaluated = argument.eval(env);
-        if is_error(&evaluated) {
+        if is_error(&evaluated) || is_loop_signal(&evaluated) {
             return evaluated;
         }
         args.push(evaluated);
@@ -312,18 +542,44 @@ impl_eval!(CallExpression => (self, env) {
 fn apply_function(func: Object, args: Vec<Object>) -> Object {
     match func {
         Object::Function(function) => {
+            // VM の frame の数に合わせて、Rust の stack を使い切る前に止める
+            let depth = CALL_DEPTH.with(|depth| depth.get());
+            if depth + 1 >= MAX_FRAMES {
+                return new_error("stack overflow".to_string());
+            }
+            if args.len() != function.parameters.len() {
+                return new_error(format!(
+                    "wrong number of arguments: want={}, got={}",
+                    function.parameters.len(),
+                    args.len()
+                ));
+            }
             let mut extended_env = Environment::new_enclosed(function.env);
             for (i, param) in function.parameters.iter().enumerate() {
                 extended_env.borrow_mut().set(&param.value, &args[i]);
             }
+            CALL_DEPTH.with(|cell| cell.set(depth + 1));
             let evaluated = function.body.eval(&mut extended_env);
+            CALL_DEPTH.with(|cell| cell.set(depth));
+            if is_loop_signal(&evaluated) {
+                return outside_of_loop(&evaluated);
+            }
             unwrap_return_value(evaluated)
         }
-        Object::Builtin(builtin) => {
-            let builtin_function = builtin.func;
-            builtin_function(args)
+        Object::Builtin(builtin) => (builtin.func)(&mut FunctionCaller, args),
+        _ => new_error(format!("not a function: {}", func.type_name())),
+    }
+}
+
+// map などの builtin から Monkey の関数を呼ぶ
+struct FunctionCaller;
+
+impl Caller for FunctionCaller {
+    fn call(&mut self, func: Object, args: Vec<Object>) -> Result<Object, String> {
+        match apply_function(func, args) {
+            Object::Error(message) => Err(message),
+            obj => Ok(obj),
         }
-        _ => new_error(format!("not a function: {:?}", func)),
     }
 }
 
@@ -338,11 +594,50 @@ impl_eval!(StringLiteral => (self, _env) {
     Object::String(self.value.clone())
 });
 
+impl_eval!(InterpolatedString => (self, env) {
+    // 埋め込んだ式は、同じ名前の変数があっても組み込みの str で文字列にする
+    let str_builtin = Object::Builtin(get_builtin_by_name("str").unwrap());
+    let mut result = String::new();
+    for part in &self.parts {
+        let evaluated = part.eval(env);
+        if is_error(&evaluated) || is_loop_signal(&evaluated) {
+            return evaluated;
+        }
+        match apply_function(str_builtin.clone(), vec![evaluated]) {
+            Object::String(string) => result.push_str(&string),
+            obj => return obj,
+        }
+    }
+    Object::String(result)
+});
+
+fn eval_float_infix_expression(operator: &str, left_val: f64, right_val: f64) -> Object {
+    match operator {
+        "+" | "-" | "*" | "/" | "%" => match arithmetic::float_infix(operator, left_val, right_val)
+        {
+            Ok(result) => Object::Float(result),
+            Err(message) => new_error(message),
+        },
+        "<" => native_bool_to_boolean_object(left_val < right_val),
+        ">" => native_bool_to_boolean_object(left_val > right_val),
+        "<=" => native_bool_to_boolean_object(left_val <= right_val),
+        ">=" => native_bool_to_boolean_object(left_val >= right_val),
+        "==" => native_bool_to_boolean_object(left_val == right_val),
+        "!=" => native_bool_to_boolean_object(left_val != right_val),
+        _ => new_error(arithmetic::infix_error(
+            operator,
+            &Object::Float(left_val),
+            &Object::Float(right_val),
+        )),
+    }
+}
+
 fn eval_string_infix_expression(operator: &str, left: &str, right: &str) -> Object {
     if operator != "+" {
-        return new_error(format!(
-            "unknown operator: {:?} {} {:?}",
-            left, operator, right
+        return new_error(arithmetic::infix_error(
+            operator,
+            &Object::String(left.to_string()),
+            &Object::String(right.to_string()),
         ));
     }
     Object::String([left, right].join(""))
@@ -352,7 +647,7 @@ impl_eval!(ArrayLiteral => (self, env) {
     let mut elements: Vec<Object> = vec![];
     for element in &self.elements {
         let evaluated = element.eval(env);
-        if is_error(&evaluated) {
+        if is_error(&evaluated) || is_loop_signal(&evaluated) {
             return evaluated;
         }
         elements.push(evaluated);
@@ -362,11 +657,11 @@ impl_eval!(ArrayLiteral => (self, env) {
 
 impl_eval!(IndexExpression => (self, env) {
     let left = self.left.eval(env);
-    if is_error(&left) {
+    if is_error(&left) || is_loop_signal(&left) {
         return left
     }
     let index = self.index.eval(env);
-    if is_error(&index) {
+    if is_error(&index) || is_loop_signal(&index) {
         return index
     }
     eval_index_expression(left, index)
@@ -378,27 +673,33 @@ fn eval_index_expression(left: Object, index: Object) -> Object {
             return eval_array_index_expression(&array.elements, idx);
         }
     }
+    if let Object::String(string) = &left {
+        if let Object::Integer(idx) = index {
+            return string::char_at(string, idx);
+        }
+    }
     if let Object::Hash(hash) = &left {
         return eval_hash_index_expression(hash, index);
     }
-    new_error(format!("index operator not supported: {:?}", left))
+    new_error(format!(
+        "index operator not supported: {}",
+        left.type_name()
+    ))
 }
 
 fn eval_array_index_expression(elements: &[Object], idx: i64) -> Object {
-    let max = elements.len() - 1;
-
-    if idx < 0 || idx as usize > max {
+    if idx < 0 {
         return NULL;
     }
-    elements[idx as usize].clone()
+    elements.get(idx as usize).cloned().unwrap_or(NULL)
 }
 
 impl_eval!(HashLiteral => (self, env) {
-    let mut pairs = HashMap::new();
+    let mut hash = Hash::default();
 
     for (key_node, value_node) in &self.pairs {
         let key = key_node.eval(env);
-        if is_error(&key) {
+        if is_error(&key) || is_loop_signal(&key) {
             return key;
         }
 
@@ -408,11 +709,14 @@ impl_eval!(HashLiteral => (self, env) {
         };
 
         let value = value_node.eval(env);
+        if is_error(&value) || is_loop_signal(&value) {
+            return value;
+        }
 
-        pairs.insert(hashed, HashPair{key, value});
+        hash.insert(hashed, HashPair{key, value});
     }
 
-    Object::Hash(Hash{pairs})
+    Object::Hash(hash)
 });
 
 fn eval_hash_index_expression(hash_object: &Hash, index: Object) -> Object {
@@ -421,7 +725,7 @@ fn eval_hash_index_expression(hash_object: &Hash, index: Object) -> Object {
         Err(message) => return new_error(message),
     };
 
-    if let Some(pair) = hash_object.pairs.get(&key) {
+    if let Some(pair) = hash_object.get(&key) {
         pair.value.clone()
     } else {
         NULL
@@ -441,6 +745,15 @@ fn new_error(message: String) -> Object {
     Object::Error(message)
 }
 
+fn is_loop_signal(obj: &Object) -> bool {
+    matches!(obj, Object::Break | Object::Continue)
+}
+
+// ループの外まで届いた break と continue
+fn outside_of_loop(signal: &Object) -> Object {
+    new_error(format!("{} outside of loop", signal))
+}
+
 fn is_error(obj: &Object) -> bool {
     if let Object::Error(_) = obj {
         true
@@ -450,15 +763,22 @@ fn is_error(obj: &Object) -> bool {
 }
 
 fn quote(node: &Expression, env: &mut Rc<RefCell<Environment>>) -> Object {
-    let n = eval_unquote_calls(node, env);
-    Object::Quote(Quote { node: n })
+    match eval_unquote_calls(node, env) {
+        Ok(n) => Object::Quote(Quote { node: n }),
+        Err(message) => Object::Error(message),
+    }
 }
 
-fn eval_unquote_calls(quoted: &Expression, env: &mut Rc<RefCell<Environment>>) -> Expression {
-    if let Node::Expression(exp) = modify(
+fn eval_unquote_calls(
+    quoted: &Expression,
+    env: &mut Rc<RefCell<Environment>>,
+) -> Result<Expression, String> {
+    // modify の中からは返せないので、最初のエラーを覚えておく
+    let mut error = None;
+    let modified = modify(
         Node::Expression(quoted.clone()),
         Rc::new(RefCell::new(|node: Node| -> Node {
-            if !is_unquote_call(&node) {
+            if error.is_some() || !is_unquote_call(&node) {
                 return node;
             }
 
@@ -467,25 +787,74 @@ fn eval_unquote_calls(quoted: &Expression, env: &mut Rc<RefCell<Environment>>) -
                     return node;
                 }
                 let unquoted = call.arguments[0].eval(env);
-                Node::Expression(convert_object_to_ast_node(unquoted))
+                match convert_object_to_ast_node(unquoted, call.pos) {
+                    Ok(exp) => Node::Expression(exp),
+                    Err(message) => {
+                        error = Some(message);
+                        node
+                    }
+                }
             } else {
                 node
             }
         })),
-    ) {
-        exp
+    );
+    if let Some(message) = error {
+        return Err(message);
+    }
+    if let Node::Expression(exp) = modified {
+        Ok(exp)
     } else {
         unreachable!()
     }
 }
 
-fn convert_object_to_ast_node(obj: Object) -> Expression {
-    match obj {
-        Object::Integer(int) => Expression::IntegerLiteral(IntegerLiteral { value: int }),
-        Object::Boolean(boolean) => Expression::Boolean(Boolean { value: boolean }),
+fn convert_object_to_ast_node(obj: Object, pos: Position) -> Result<Expression, String> {
+    let exp = match obj {
+        Object::Integer(int) => Expression::IntegerLiteral(IntegerLiteral { value: int, pos }),
+        Object::Float(float) => Expression::FloatLiteral(FloatLiteral { value: float, pos }),
+        Object::Boolean(boolean) => Expression::Boolean(Boolean {
+            value: boolean,
+            pos,
+        }),
+        Object::String(value) => Expression::StringLiteral(StringLiteral { value, pos }),
+        Object::Array(array) => Expression::ArrayLiteral(ArrayLiteral {
+            elements: array
+                .elements
+                .into_iter()
+                .map(|element| convert_object_to_ast_node(element, pos))
+                .collect::<Result<_, _>>()?,
+            pos,
+        }),
+        Object::Hash(hash) => Expression::HashLiteral(HashLiteral {
+            pairs: hash
+                .pairs()
+                .map(|pair| {
+                    Ok((
+                        convert_object_to_ast_node(pair.key.clone(), pos)?,
+                        convert_object_to_ast_node(pair.value.clone(), pos)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+            pos,
+        }),
+        // null のリテラルは無いので、評価すると null になる式に置き換える
+        Object::Null => Expression::IfExpression(IfExpression {
+            condition: Box::new(Expression::Boolean(Boolean { value: false, pos })),
+            consequence: Box::new(BlockStatement { statements: vec![] }),
+            alternative: None,
+            pos,
+        }),
         Object::Quote(quote) => quote.node,
-        _ => unimplemented!(),
-    }
+        Object::Error(message) => return Err(message),
+        obj => {
+            return Err(format!(
+                "cannot convert {} to AST node in unquote",
+                obj.type_name()
+            ))
+        }
+    };
+    Ok(exp)
 }
 
 fn is_unquote_call(node: &Node) -> bool {
@@ -516,11 +885,7 @@ pub fn define_macros(program: &mut Program, env: Rc<RefCell<Environment>>) {
 
 fn is_macro_definition(node: &Statement) -> bool {
     if let Statement::LetStatement(let_statement) = node {
-        if let Expression::MacroLiteral(_) = let_statement.value {
-            true
-        } else {
-            false
-        }
+        matches!(let_statement.value, Expression::MacroLiteral(_))
     } else {
         false
     }
@@ -543,22 +908,40 @@ fn add_macro(stmt: &Statement, env: Rc<RefCell<Environment>>) {
     }
 }
 
-pub fn expand_macros(program: Program, env: &mut Rc<RefCell<Environment>>) -> Program {
-    if let Node::Program(p) = modify(
+#[derive(Debug, PartialEq)]
+pub struct MacroError {
+    pub message: String,
+    pub pos: Position,
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for MacroError {}
+
+pub fn expand_macros(
+    program: Program,
+    env: &mut Rc<RefCell<Environment>>,
+) -> Result<Program, MacroError> {
+    // modify の中からは返せないので、最初のエラーを覚えておく
+    let mut error = None;
+    let expanded = modify(
         Node::Program(program),
         Rc::new(RefCell::new(|node: Node| -> Node {
+            if error.is_some() {
+                return node;
+            }
             if let Node::Expression(Expression::CallExpression(call_expression)) = &node {
                 if let Some(macro_o) = is_macro_call(call_expression, env) {
-                    let args = quote_args(call_expression);
-                    let mut eval_env = Environment::new_enclosed(macro_o.env);
-                    for (i, param) in macro_o.parameters.iter().enumerate() {
-                        eval_env.borrow_mut().set(&param.value, &args[i]);
-                    }
-                    let evaluated = macro_o.body.eval(&mut eval_env);
-                    if let Object::Quote(quote) = evaluated {
-                        Node::Expression(quote.node)
-                    } else {
-                        panic!("we only support returning AST-nodes from macros");
+                    match expand_macro_call(call_expression, macro_o) {
+                        Ok(expanded) => Node::Expression(expanded),
+                        Err(err) => {
+                            error = Some(err);
+                            node
+                        }
                     }
                 } else {
                     node
@@ -567,21 +950,51 @@ pub fn expand_macros(program: Program, env: &mut Rc<RefCell<Environment>>) -> Pr
                 node
             }
         })),
-    ) {
-        p
+    );
+    if let Some(err) = error {
+        return Err(err);
+    }
+    if let Node::Program(p) = expanded {
+        Ok(p)
     } else {
         unreachable!()
     }
 }
 
+fn expand_macro_call(call: &CallExpression, macro_o: Macro) -> Result<Expression, MacroError> {
+    if call.arguments.len() != macro_o.parameters.len() {
+        return Err(MacroError {
+            message: format!(
+                "wrong number of macro arguments: want={}, got={}",
+                macro_o.parameters.len(),
+                call.arguments.len()
+            ),
+            pos: call.pos,
+        });
+    }
+
+    let args = quote_args(call);
+    let mut eval_env = Environment::new_enclosed(macro_o.env);
+    for (param, arg) in macro_o.parameters.iter().zip(args.iter()) {
+        eval_env.borrow_mut().set(&param.value, arg);
+    }
+    match macro_o.body.eval(&mut eval_env) {
+        Object::Quote(quote) => Ok(quote.node),
+        Object::Error(message) => Err(MacroError {
+            message,
+            pos: call.pos,
+        }),
+        _ => Err(MacroError {
+            message: String::from("we only support returning AST-nodes from macros"),
+            pos: call.pos,
+        }),
+    }
+}
+
 fn is_macro_call(exp: &CallExpression, env: &Rc<RefCell<Environment>>) -> Option<Macro> {
     if let Expression::Identifier(identifier) = &*exp.function {
-        if let Some(obj) = env.borrow().get(&identifier.value) {
-            if let Object::Macro(macro_o) = obj {
-                Some(macro_o)
-            } else {
-                None
-            }
+        if let Some(Object::Macro(macro_o)) = env.borrow().get(&identifier.value) {
+            Some(macro_o)
         } else {
             None
         }
@@ -599,119 +1012,6 @@ fn quote_args(exp: &CallExpression) -> Vec<Object> {
     args
 }
 
-mod builtin {
-    use super::new_error;
-    use super::{Array, Object, NULL};
-
-    pub fn len(args: Vec<Object>) -> Object {
-        if args.len() != 1 {
-            return new_error(format!(
-                "wrong number of arguments. got={}, want=1",
-                args.len()
-            ));
-        }
-        match &args[0] {
-            Object::String(string) => Object::Integer(string.len() as i64),
-            Object::Array(array) => Object::Integer(array.elements.len() as i64),
-            _ => new_error(format!(
-                "argument to `len` not supported, got {:?}",
-                args[0]
-            )),
-        }
-    }
-
-    pub fn first(args: Vec<Object>) -> Object {
-        if args.len() != 1 {
-            return new_error(format!(
-                "wrong number of arguments. got={}, want=1",
-                args.len()
-            ));
-        }
-        if let Object::Array(array) = &args[0] {
-            if !array.elements.is_empty() {
-                array.elements.first().unwrap().clone()
-            } else {
-                NULL
-            }
-        } else {
-            new_error(format!(
-                "argument to `first` must be ARRAY, got {:?}",
-                args[0]
-            ))
-        }
-    }
-
-    pub fn last(args: Vec<Object>) -> Object {
-        if args.len() != 1 {
-            return new_error(format!(
-                "wrong number of arguments. got={}, want=1",
-                args.len()
-            ));
-        }
-        if let Object::Array(array) = &args[0] {
-            if !array.elements.is_empty() {
-                array.elements.last().unwrap().clone()
-            } else {
-                NULL
-            }
-        } else {
-            new_error(format!(
-                "argument to `last` must be ARRAY, got {:?}",
-                args[0]
-            ))
-        }
-    }
-
-    pub fn rest(args: Vec<Object>) -> Object {
-        if args.len() != 1 {
-            return new_error(format!(
-                "wrong number of arguments. got={}, want=1",
-                args.len()
-            ));
-        }
-        if let Object::Array(array) = &args[0] {
-            if !array.elements.is_empty() {
-                Object::Array(Array {
-                    elements: array.elements[1..].to_vec(),
-                })
-            } else {
-                NULL
-            }
-        } else {
-            new_error(format!(
-                "argument to `rest` must be ARRAY, got {:?}",
-                args[0]
-            ))
-        }
-    }
-
-    pub fn push(args: Vec<Object>) -> Object {
-        if args.len() != 2 {
-            return new_error(format!(
-                "wrong number of arguments. got={}, want=2",
-                args.len()
-            ));
-        }
-        if let Object::Array(array) = &args[0] {
-            let mut elements = array.elements[..].to_vec();
-            elements.push(args[1].clone());
-            Object::Array(Array { elements })
-        } else {
-            new_error(format!(
-                "argument to `push` must be ARRAY, got {:?}",
-                args[0]
-            ))
-        }
-    }
-
-    pub fn puts(args: Vec<Object>) -> Object {
-        for arg in args {
-            println!("{}", arg);
-        }
-        NULL
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::super::ast::Program;
@@ -719,7 +1019,7 @@ mod tests {
     use super::super::object::hash::Hashable;
     use super::super::object::Object;
     use super::super::parser::Parser;
-    use super::{define_macros, expand_macros, Environment, Eval, NULL};
+    use super::{define_macros, expand_macros, Environment, Eval, Position, NULL};
     use std::collections::HashMap;
     use std::rc::Rc;
 
@@ -741,6 +1041,10 @@ mod tests {
             ("3 * 3 * 3 + 10", 37),
             ("3 * (3 * 3) + 10", 37),
             ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("7 % 3", 1),
+            ("-7 % 3", -1),
+            ("1 && 5", 5),
+            ("false || 7", 7),
         ];
 
         for (input, expected) in tests.iter() {
@@ -749,6 +1053,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_eval_float_expression() {
+        let tests = [
+            ("1.5", Object::Float(1.5)),
+            ("-1.5", Object::Float(-1.5)),
+            ("1 + 0.5", Object::Float(1.5)),
+            ("3.0 / 2", Object::Float(1.5)),
+            ("2 * 0.25 - 1", Object::Float(-0.5)),
+            ("1 < 1.5", Object::Boolean(true)),
+            ("1 == 1.0", Object::Boolean(true)),
+            ("2.5 != 2.5", Object::Boolean(false)),
+            ("int(3.7)", Object::Integer(3)),
+            ("float(2)", Object::Float(2.0)),
+            (
+                "1.0 / 0",
+                Object::Error("division by zero: 1.0 / 0.0".to_string()),
+            ),
+            (
+                "int(\"1.5\")",
+                Object::Error("could not parse \"1.5\" as integer".to_string()),
+            ),
+            (
+                "int(float(\"1e300\"))",
+                Object::Error("float out of integer range: 1e300".to_string()),
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(&test_eval(input), expected, "{}", input);
+        }
+    }
+
     #[test]
     fn test_eval_boolean_expression() {
         let tests = [
@@ -762,6 +1098,15 @@ mod tests {
             ("1 != 1", false),
             ("1 == 2", false),
             ("1 != 2", true),
+            ("1 <= 2", true),
+            ("2 <= 2", true),
+            ("2 >= 3", false),
+            ("2.5 >= 2", true),
+            ("true && false", false),
+            ("false || true", true),
+            ("1 < 2 && 2 < 3", true),
+            ("true || 1 / 0", true),
+            ("false && 1 / 0", false),
         ];
 
         for (input, expected) in tests.iter() {
@@ -869,20 +1214,14 @@ mod tests {
     #[test]
     fn test_error_handling() {
         let tests = [
-            ("5 + true;", "type mismatch: Integer(5) + Boolean(true)"),
-            ("5 + true; 5;", "type mismatch: Integer(5) + Boolean(true)"),
-            ("-true", "unknown operator: -Boolean(true)"),
-            (
-                "true + false;",
-                "unknown operator: Boolean(true) + Boolean(false)",
-            ),
-            (
-                "5; true + false; 5",
-                "unknown operator: Boolean(true) + Boolean(false)",
-            ),
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+            ("5 + true; 5;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "unknown operator: -BOOLEAN"),
+            ("true + false;", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("5; true + false; 5", "unknown operator: BOOLEAN + BOOLEAN"),
             (
                 "if (10 > 1) { true + false; }",
-                "unknown operator: Boolean(true) + Boolean(false)",
+                "unknown operator: BOOLEAN + BOOLEAN",
             ),
             (
                 r#"
@@ -892,16 +1231,22 @@ mod tests {
                     }
                 }
                 "#,
-                "unknown operator: Boolean(true) + Boolean(false)",
+                "unknown operator: BOOLEAN + BOOLEAN",
             ),
             ("foobar", "identifier not found: foobar"),
+            (r#""Hello" - "World""#, "unknown operator: STRING - STRING"),
             (
-                r#""Hello" - "World""#,
-                r#"unknown operator: "Hello" - "World""#,
+                r#"{"name": "Monkey"}[fn(x) { x }];"#,
+                "unusable as hash key: FUNCTION",
             ),
+            ("10 / (5 - 5)", "division by zero: 10 / 0"),
             (
-                r#"{"name": "Monkey"}[fn(x) { x }];"#,
-                "unusable as hash key: fn(x) {x}",
+                "9223372036854775807 * 2",
+                "integer overflow: 9223372036854775807 * 2",
+            ),
+            (
+                "-(-9223372036854775807 - 1)",
+                "integer overflow: -(-9223372036854775808)",
             ),
         ];
 
@@ -930,6 +1275,98 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_loops() {
+        let tests = [
+            (
+                "let s = 0; for (x in [1, 2, 3, 4, 5]) { if (x == 2) { continue; } if (x == 4) { break; } let s = s + x; }; s",
+                4,
+            ),
+            (
+                "let s = 0; for (i, x in [10, 20]) { let s = s + i * x; }; s",
+                20,
+            ),
+            (
+                r#"let s = 0; for (k, v in {"b": 2, "a": 1}) { let s = s * 10 + v; }; s"#,
+                21,
+            ),
+            (r#"let n = 0; for (c in "abc") { let n = n + 1; }; n"#, 3),
+            ("let i = 0; while (i < 3) { let i = i + 1; }; i", 3),
+            (
+                "let f = fn() { for (x in [1, 2, 3]) { if (x == 2) { return x * 10; } } }; f()",
+                20,
+            ),
+            (
+                "let i = 0; while (i < 2000) { let i = i + 1; }; i",
+                2000,
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_integer_object(&test_eval(input), *expected);
+        }
+
+        let tests = [
+            ("break;", "break outside of loop"),
+            (
+                "let f = fn() { continue; }; for (x in [1]) { f() }",
+                "continue outside of loop",
+            ),
+            ("for (x in 1) { x }", "cannot iterate over 1"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                test_eval(input),
+                Object::Error(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_assignments() {
+        let tests = [
+            ("let x = 1; x = x + 1; x", 2),
+            ("let x = 1; x += 10; x -= 2; x *= 3; x /= 2; x %= 7; x", 6),
+            ("let x = 0; let y = 0; x = y = 3; x + y", 6),
+            ("let xs = [1, [2, 3]]; xs[1][0] += 40; xs[1][0]", 42),
+            ("let xs = [1]; let ys = xs; ys[0] = 9; xs[0]", 1),
+            (
+                r#"let h = {"a": 1}; h["a"] += 1; h["b"] = 5; h["a"] + h["b"]"#,
+                7,
+            ),
+            ("let c = 0; let inc = fn() { c += 1 }; inc(); inc(); c", 2),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_integer_object(&test_eval(input), *expected);
+        }
+
+        let tests = [
+            ("x = 1", "identifier not found: x"),
+            ("len = 1", "cannot assign to builtin `len`"),
+            (
+                "let f = fn(a) { fn() { a = 2 } }; f(1)()",
+                "cannot assign to captured variable `a`",
+            ),
+            (
+                "let xs = [1]; xs[1] = 2",
+                "index out of range: 1 (length 1)",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                test_eval(input),
+                Object::Error(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_function_object() {
         let input = "fn(x) { x + 2; }";
@@ -960,6 +1397,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_function_application_with_wrong_arguments() {
+        let tests = [
+            (
+                "fn() { 1; }(1);",
+                "wrong number of arguments: want=0, got=1",
+            ),
+            (
+                "fn(a) { a; }();",
+                "wrong number of arguments: want=1, got=0",
+            ),
+            (
+                "fn(a, b) { a + b; }(1);",
+                "wrong number of arguments: want=2, got=1",
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            if let Object::Error(message) = test_eval(input) {
+                assert_eq!(message, *expected)
+            } else {
+                assert!(false, "no error object returned.")
+            }
+        }
+    }
+
     #[test]
     fn test_closures() {
         let input = r#"
@@ -1150,10 +1613,10 @@ mod tests {
 
         let evaluated = test_eval(input);
         if let Object::Hash(result) = evaluated {
-            assert_eq!(result.pairs.len(), expected.len());
+            assert_eq!(result.len(), expected.len());
 
             for (expected_key, expected_value) in expected.iter() {
-                let pair = result.pairs.get(expected_key).unwrap();
+                let pair = result.get(expected_key).unwrap();
                 assert_integer_object(&pair.value, *expected_value as i64);
             }
         } else {
@@ -1295,6 +1758,13 @@ mod tests {
             "#,
                 r#"if (!(10 > 5)) { puts("not greater") } else { puts("greater") }"#,
             ),
+            (
+                r#"
+            let m = macro(a) { quote(unquote(a) + 1) };
+            puts(m(2));
+            "#,
+                "puts((2 + 1))",
+            ),
         ];
 
         for (input, expected_str) in tests.iter() {
@@ -1303,12 +1773,47 @@ mod tests {
 
             let env = Environment::new();
             define_macros(&mut program, Rc::clone(&env));
-            let expanded = expand_macros(program, &mut Rc::clone(&env));
+            let expanded = expand_macros(program, &mut Rc::clone(&env)).unwrap();
 
             assert_eq!(format!("{}", expanded), format!("{}", expected));
         }
     }
 
+    #[test]
+    fn test_expand_macro_errors() {
+        let tests = [
+            (
+                "let m = macro(a) { quote(unquote(a)) };\nm(1, 2);",
+                "wrong number of macro arguments: want=1, got=2",
+                Position::new(2, 2),
+            ),
+            (
+                "let m = macro() { 1 };\nm();",
+                "we only support returning AST-nodes from macros",
+                Position::new(2, 2),
+            ),
+            (
+                "let m = macro() { x };\nm();",
+                "identifier not found: x",
+                Position::new(2, 2),
+            ),
+        ];
+
+        for (input, expected_message, expected_pos) in tests.iter() {
+            let mut program = test_parse_program(input);
+
+            let env = Environment::new();
+            define_macros(&mut program, Rc::clone(&env));
+            match expand_macros(program, &mut Rc::clone(&env)) {
+                Ok(_) => assert!(false, "expected macro error for {}", input),
+                Err(err) => {
+                    assert_eq!(&err.message, expected_message);
+                    assert_eq!(&err.pos, expected_pos);
+                }
+            }
+        }
+    }
+
     fn test_parse_program(input: &str) -> Program {
         let l = Lexer::new(input);
         let mut p = Parser::new(l);
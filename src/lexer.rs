@@ -1,8 +1,38 @@
-use super::token::{lookup_ident, Token, TokenType};
+use super::token::{lookup_ident, Position, Token, TokenType};
+use unicode_xid::UnicodeXID;
 
 pub struct Lexer {
     chars: std::iter::Peekable<std::vec::IntoIter<char>>,
     ch: Option<char>,
+    // ch の位置
+    line: usize,
+    column: usize,
+    // 読んでいる途中の ${ } の入れ子
+    interpolations: Vec<Interpolation>,
+    errors: Vec<LexError>,
+    doc_comments: Vec<DocComment>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub pos: Position,
+}
+
+// /// と /** */ のコメント。トークンにはならないが、formatter などのために残しておく
+// text はコメント記号も含めたソースそのまま
+#[derive(Debug, PartialEq)]
+pub struct DocComment {
+    pub text: String,
+    pub pos: Position,
+    pub end: Position,
+}
+
+struct Interpolation {
+    // 文字列の開始位置
+    start: Position,
+    // ${ の中で開いている { の数
+    depth: usize,
 }
 
 impl Lexer {
@@ -10,126 +40,127 @@ impl Lexer {
         let mut l = Lexer {
             chars: input.chars().collect::<Vec<_>>().into_iter().peekable(),
             ch: None,
+            line: 1,
+            column: 0,
+            interpolations: vec![],
+            errors: vec![],
+            doc_comments: vec![],
         };
         l.read_char();
         l
     }
 
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    pub fn doc_comments(&self) -> &[DocComment] {
+        &self.doc_comments
+    }
+
     fn next_token(&mut self) -> Option<Token> {
-        self.skip_whitespace();
-
-        let tok = if let Some(ch) = self.ch {
-            Some(match ch {
-                '=' => {
-                    if self.peek_char().is_some() && self.peek_char().unwrap() == &'=' {
-                        self.read_char();
-                        let literal: String = [ch, self.ch.unwrap()].iter().collect();
-                        Token {
-                            t: TokenType::Eq,
-                            literal,
-                        }
-                    } else {
-                        Token {
-                            t: TokenType::Assign,
-                            literal: ch.to_string(),
-                        }
-                    }
+        self.skip_trivia();
+
+        let pos = self.cur_position();
+        let (t, literal) = if let Some(ch) = self.ch {
+            match ch {
+                '=' => self.read_two_char_token(ch, '=', TokenType::Eq, TokenType::Assign),
+                '+' => self.read_two_char_token(ch, '=', TokenType::PlusAssign, TokenType::Plus),
+                '-' => self.read_two_char_token(ch, '=', TokenType::MinusAssign, TokenType::Minus),
+                '!' => self.read_two_char_token(ch, '=', TokenType::NotEq, TokenType::Bang),
+                '*' => self.read_two_char_token(
+                    ch,
+                    '=',
+                    TokenType::AsteriskAssign,
+                    TokenType::Asterisk,
+                ),
+                '/' => self.read_two_char_token(ch, '=', TokenType::SlashAssign, TokenType::Slash),
+                '%' => {
+                    self.read_two_char_token(ch, '=', TokenType::PercentAssign, TokenType::Percent)
                 }
-                '+' => Token {
-                    t: TokenType::Plus,
-                    literal: ch.to_string(),
-                },
-                '-' => Token {
-                    t: TokenType::Minus,
-                    literal: ch.to_string(),
-                },
-                '!' => {
-                    if self.peek_char().is_some() && self.peek_char().unwrap() == &'=' {
-                        self.read_char();
-                        let literal: String = [ch, self.ch.unwrap()].iter().collect();
-                        Token {
-                            t: TokenType::NotEq,
-                            literal,
-                        }
-                    } else {
-                        Token {
-                            t: TokenType::Bang,
-                            literal: ch.to_string(),
-                        }
+                '<' => self.read_two_char_token(ch, '=', TokenType::LtEq, TokenType::Lt),
+                '>' => self.read_two_char_token(ch, '=', TokenType::GtEq, TokenType::Gt),
+                '&' => self.read_two_char_token(ch, '&', TokenType::And, TokenType::Illegal),
+                '|' => self.read_two_char_token(ch, '|', TokenType::Or, TokenType::Illegal),
+                ',' => (TokenType::Comma, ch.to_string()),
+                ';' => (TokenType::Semicolon, ch.to_string()),
+                '(' => (TokenType::LParen, ch.to_string()),
+                ')' => (TokenType::RParen, ch.to_string()),
+                '{' => {
+                    if let Some(interpolation) = self.interpolations.last_mut() {
+                        interpolation.depth += 1;
                     }
+                    (TokenType::LBrace, ch.to_string())
                 }
-                '*' => Token {
-                    t: TokenType::Asterisk,
-                    literal: ch.to_string(),
-                },
-                '/' => Token {
-                    t: TokenType::Slash,
-                    literal: ch.to_string(),
-                },
-                '<' => Token {
-                    t: TokenType::Lt,
-                    literal: ch.to_string(),
-                },
-                '>' => Token {
-                    t: TokenType::Gt,
-                    literal: ch.to_string(),
-                },
-                ',' => Token {
-                    t: TokenType::Comma,
-                    literal: ch.to_string(),
-                },
-                ';' => Token {
-                    t: TokenType::Semicolon,
-                    literal: ch.to_string(),
-                },
-                '(' => Token {
-                    t: TokenType::LParen,
-                    literal: ch.to_string(),
-                },
-                ')' => Token {
-                    t: TokenType::RParen,
-                    literal: ch.to_string(),
-                },
-                '{' => Token {
-                    t: TokenType::LBrace,
-                    literal: ch.to_string(),
-                },
-                '}' => Token {
-                    t: TokenType::RBrace,
-                    literal: ch.to_string(),
-                },
-                '"' => Token {
-                    t: TokenType::String,
-                    literal: self.read_string(),
-                },
-                '[' => Token {
-                    t: TokenType::LBracket,
-                    literal: ch.to_string(),
-                },
-                ']' => Token {
-                    t: TokenType::RBracket,
-                    literal: ch.to_string(),
-                },
-                ':' => Token {
-                    t: TokenType::Colon,
-                    literal: ch.to_string(),
-                },
-                'a'..='z' | 'A'..='Z' | '_' => return Some(self.read_identifier()),
-                '0'..='9' => return Some(self.read_number()),
-                _ => Token {
-                    t: TokenType::Illegal,
-                    literal: ch.to_string(),
+                '}' => match self.interpolations.last_mut() {
+                    Some(interpolation) if interpolation.depth == 0 => {
+                        let start = interpolation.start;
+                        self.read_string(start, true)
+                    }
+                    Some(interpolation) => {
+                        interpolation.depth -= 1;
+                        (TokenType::RBrace, ch.to_string())
+                    }
+                    None => (TokenType::RBrace, ch.to_string()),
                 },
-            })
+                '"' => self.read_string(pos, false),
+                '[' => (TokenType::LBracket, ch.to_string()),
+                ']' => (TokenType::RBracket, ch.to_string()),
+                ':' => (TokenType::Colon, ch.to_string()),
+                _ if is_identifier_start(ch) => {
+                    let literal = self.read_identifier();
+                    return Some(self.new_token(lookup_ident(&literal), literal, pos));
+                }
+                '0'..='9' => {
+                    let (t, literal) = self.read_number();
+                    return Some(self.new_token(t, literal, pos));
+                }
+                _ => (TokenType::Illegal, ch.to_string()),
+            }
         } else {
-            None
+            return None;
         };
 
         self.read_char();
-        tok
+        Some(self.new_token(t, literal, pos))
+    }
+
+    // ch の次が next なら 2 文字の token にする
+    fn read_two_char_token(
+        &mut self,
+        ch: char,
+        next: char,
+        two: TokenType,
+        one: TokenType,
+    ) -> (TokenType, String) {
+        if self.peek_char() == Some(&next) {
+            self.read_char();
+            (two, [ch, next].iter().collect())
+        } else {
+            (one, ch.to_string())
+        }
+    }
+
+    fn new_token(&self, t: TokenType, literal: String, pos: Position) -> Token {
+        Token {
+            t,
+            literal,
+            pos,
+            end: self.cur_position(),
+        }
+    }
+
+    fn cur_position(&self) -> Position {
+        Position::new(self.line, self.column)
     }
 
     fn read_char(&mut self) {
+        if self.ch == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         self.ch = self.chars.next();
     }
 
@@ -137,26 +168,31 @@ impl Lexer {
         self.chars.peek()
     }
 
-    fn read_identifier(&mut self) -> Token {
+    fn read_identifier(&mut self) -> String {
         let mut literal = String::new();
         while let Some(ch) = self.ch {
-            match ch {
-                'a'..='z' | 'A'..='Z' | '_' => {
-                    literal.push(ch);
-                    self.read_char();
-                }
-                _ => {
-                    break;
-                }
+            if !is_identifier_continue(ch) {
+                break;
             }
+            literal.push(ch);
+            self.read_char();
         }
-        Token {
-            t: lookup_ident(literal.as_str()),
-            literal,
+        literal
+    }
+
+    // `1.` や `1.foo` の `.` は数値に含めない
+    fn read_number(&mut self) -> (TokenType, String) {
+        let mut literal = self.read_digits();
+        if self.ch == Some('.') && self.peek_char().is_some_and(|ch| ch.is_ascii_digit()) {
+            literal.push('.');
+            self.read_char();
+            literal.push_str(&self.read_digits());
+            return (TokenType::Float, literal);
         }
+        (TokenType::Int, literal)
     }
 
-    fn read_number(&mut self) -> Token {
+    fn read_digits(&mut self) -> String {
         let mut literal = String::new();
         while let Some(ch) = self.ch {
             match ch {
@@ -169,21 +205,172 @@ impl Lexer {
                 }
             };
         }
-        Token {
-            t: TokenType::Int,
-            literal,
-        }
+        literal
     }
-    fn read_string(&mut self) -> String {
+    // " か } の次から、閉じる " か ${ までを読む
+    // resumed は ${ } を読み終えて文字列の続きを読むとき
+    fn read_string(&mut self, start: Position, resumed: bool) -> (TokenType, String) {
         let mut literal = String::new();
         loop {
             self.read_char();
-            if self.ch.is_none() || self.ch.unwrap() == '"' {
+            match self.ch {
+                None => {
+                    self.errors.push(LexError {
+                        message: "unterminated string".to_string(),
+                        pos: start,
+                    });
+                    break;
+                }
+                Some('"') => break,
+                Some('\\') => {
+                    if let Some(ch) = self.read_escape() {
+                        literal.push(ch);
+                    }
+                }
+                Some('$') if self.chars.peek() == Some(&'{') => {
+                    self.read_char();
+                    if resumed {
+                        return (TokenType::StringMiddle, literal);
+                    }
+                    self.interpolations.push(Interpolation { start, depth: 0 });
+                    return (TokenType::StringHead, literal);
+                }
+                Some(ch) => literal.push(ch),
+            }
+        }
+        if resumed {
+            self.interpolations.pop();
+            (TokenType::StringTail, literal)
+        } else {
+            (TokenType::String, literal)
+        }
+    }
+
+    // \ の次を読む。読めなかったときはエラーを積んで None を返す
+    fn read_escape(&mut self) -> Option<char> {
+        let pos = self.cur_position();
+        let ch = match self.peek_char() {
+            Some(&ch) => ch,
+            None => return None,
+        };
+        self.read_char();
+        match ch {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            '$' => Some('$'),
+            'u' => self.read_unicode_escape(pos),
+            _ => {
+                self.errors.push(LexError {
+                    message: format!("unknown escape sequence: \\{}", ch),
+                    pos,
+                });
+                None
+            }
+        }
+    }
+
+    // \u{1F600} のような 16 進数 6 桁までの code point
+    fn read_unicode_escape(&mut self, pos: Position) -> Option<char> {
+        let mut digits = String::new();
+        if self.peek_char() == Some(&'{') {
+            self.read_char();
+            while let Some(&ch) = self.peek_char() {
+                if !ch.is_ascii_hexdigit() {
+                    break;
+                }
+                digits.push(ch);
+                self.read_char();
+            }
+            if self.peek_char() == Some(&'}') && (1..=6).contains(&digits.len()) {
+                self.read_char();
+                if let Some(ch) = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    return Some(ch);
+                }
+            }
+        }
+        self.errors.push(LexError {
+            message: "invalid unicode escape".to_string(),
+            pos,
+        });
+        None
+    }
+
+    // 空白とコメントを読み飛ばす
+    fn skip_trivia(&mut self) {
+        loop {
+            self.skip_whitespace();
+            match (self.ch, self.chars.peek()) {
+                (Some('#'), _) | (Some('/'), Some('/')) => self.skip_line_comment(),
+                (Some('/'), Some('*')) => self.skip_block_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    // 改行の手前まで読む
+    fn skip_line_comment(&mut self) {
+        let pos = self.cur_position();
+        let mut text = String::new();
+        while let Some(ch) = self.ch {
+            if ch == '\n' {
                 break;
             }
-            literal.push(self.ch.unwrap());
+            text.push(ch);
+            self.read_char();
+        }
+        if text.starts_with("///") && !text.starts_with("////") {
+            self.push_doc_comment(text, pos);
+        }
+    }
+
+    // /* */ は入れ子にできる
+    fn skip_block_comment(&mut self) {
+        let pos = self.cur_position();
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match (self.ch, self.chars.peek()) {
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    text.push_str("/*");
+                    self.read_char();
+                }
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    text.push_str("*/");
+                    self.read_char();
+                }
+                (Some(ch), _) => text.push(ch),
+                (None, _) => {
+                    self.errors.push(LexError {
+                        message: "unterminated block comment".to_string(),
+                        pos,
+                    });
+                    return;
+                }
+            }
+            self.read_char();
+            if depth == 0 {
+                break;
+            }
+        }
+        if text.starts_with("/**") && !text.starts_with("/***") && text != "/**/" {
+            self.push_doc_comment(text, pos);
         }
-        literal
+    }
+
+    fn push_doc_comment(&mut self, text: String, pos: Position) {
+        self.doc_comments.push(DocComment {
+            text,
+            pos,
+            end: self.cur_position(),
+        });
     }
 
     fn skip_whitespace(&mut self) {
@@ -198,6 +385,16 @@ impl Lexer {
     }
 }
 
+// Unicode の XID_Start / XID_Continue に、先頭の _ を加える
+// 数字や結合文字は 2 文字目から使える
+fn is_identifier_start(ch: char) -> bool {
+    ch == '_' || ch.is_xid_start()
+}
+
+fn is_identifier_continue(ch: char) -> bool {
+    ch.is_xid_continue()
+}
+
 impl Iterator for Lexer {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
@@ -207,7 +404,7 @@ impl Iterator for Lexer {
 
 #[cfg(test)]
 mod tests {
-    use super::{Lexer, TokenType};
+    use super::{DocComment, LexError, Lexer, Position, TokenType};
 
     #[test]
     fn test_next_token() {
@@ -219,7 +416,7 @@ let add = fn(x, y) {
 };
 
 let result = add(five, ten);
-!-/*5;
+!-/ *5;
 5 < 10 > 5;
 
 if (5 < 10) {
@@ -235,7 +432,10 @@ if (5 < 10) {
 "foo bar"
 [1,2];
 {"foo": "bar"}
-macro(x, y) { x + y; };"#;
+macro(x, y) { x + y; };
+3.14 * 10.0;
+a <= b >= c && d || e % f & g
+x += 1 -= 2 *= 3 /= 4 %= 5"#;
 
         let tests = [
             (TokenType::Let, "let"),
@@ -341,6 +541,184 @@ macro(x, y) { x + y; };"#;
             (TokenType::Semicolon, ";"),
             (TokenType::RBrace, "}"),
             (TokenType::Semicolon, ";"),
+            (TokenType::Float, "3.14"),
+            (TokenType::Asterisk, "*"),
+            (TokenType::Float, "10.0"),
+            (TokenType::Semicolon, ";"),
+            (TokenType::Ident, "a"),
+            (TokenType::LtEq, "<="),
+            (TokenType::Ident, "b"),
+            (TokenType::GtEq, ">="),
+            (TokenType::Ident, "c"),
+            (TokenType::And, "&&"),
+            (TokenType::Ident, "d"),
+            (TokenType::Or, "||"),
+            (TokenType::Ident, "e"),
+            (TokenType::Percent, "%"),
+            (TokenType::Ident, "f"),
+            (TokenType::Illegal, "&"),
+            (TokenType::Ident, "g"),
+            (TokenType::Ident, "x"),
+            (TokenType::PlusAssign, "+="),
+            (TokenType::Int, "1"),
+            (TokenType::MinusAssign, "-="),
+            (TokenType::Int, "2"),
+            (TokenType::AsteriskAssign, "*="),
+            (TokenType::Int, "3"),
+            (TokenType::SlashAssign, "/="),
+            (TokenType::Int, "4"),
+            (TokenType::PercentAssign, "%="),
+            (TokenType::Int, "5"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, expected_literal) in tests.iter() {
+            let tok = l.next().unwrap();
+            assert_eq!(tok.t, *expected_type);
+            assert_eq!(tok.literal, *expected_literal);
+        }
+        assert_eq!(l.next(), None);
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let input = "let five = 5;\nfive == \"a\nb\";\n  !five";
+
+        let tests = [
+            (TokenType::Let, (1, 1), (1, 4)),
+            (TokenType::Ident, (1, 5), (1, 9)),
+            (TokenType::Assign, (1, 10), (1, 11)),
+            (TokenType::Int, (1, 12), (1, 13)),
+            (TokenType::Semicolon, (1, 13), (1, 14)),
+            (TokenType::Ident, (2, 1), (2, 5)),
+            (TokenType::Eq, (2, 6), (2, 8)),
+            (TokenType::String, (2, 9), (3, 3)),
+            (TokenType::Semicolon, (3, 3), (3, 4)),
+            (TokenType::Bang, (4, 3), (4, 4)),
+            (TokenType::Ident, (4, 4), (4, 8)),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, (line, column), (end_line, end_column)) in tests.iter() {
+            let tok = l.next().unwrap();
+            assert_eq!(tok.t, *expected_type);
+            assert_eq!(tok.pos, Position::new(*line, *column));
+            assert_eq!(tok.end, Position::new(*end_line, *end_column));
+        }
+        assert_eq!(l.next(), None);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let tests = [
+            (r#""a\nb""#, "a\nb"),
+            (r#""\t\r""#, "\t\r"),
+            (r#""say \"hi\"""#, "say \"hi\""),
+            (r#""back\\slash""#, "back\\slash"),
+            (r#""\${x}""#, "${x}"),
+            (r#""\u{41}\u{1F600}""#, "A😀"),
+            (r#""$x $""#, "$x $"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let mut l = Lexer::new(input);
+            let tok = l.next().unwrap();
+            assert_eq!(tok.t, TokenType::String, "{}", input);
+            assert_eq!(tok.literal, *expected, "{}", input);
+            assert_eq!(l.next(), None, "{}", input);
+            assert!(l.errors().is_empty(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let input = r#""a ${x} b ${ {"k": "${y}"}["k"] } c" "${z}""#;
+
+        let tests = [
+            (TokenType::StringHead, "a "),
+            (TokenType::Ident, "x"),
+            (TokenType::StringMiddle, " b "),
+            (TokenType::LBrace, "{"),
+            (TokenType::String, "k"),
+            (TokenType::Colon, ":"),
+            (TokenType::StringHead, ""),
+            (TokenType::Ident, "y"),
+            (TokenType::StringTail, ""),
+            (TokenType::RBrace, "}"),
+            (TokenType::LBracket, "["),
+            (TokenType::String, "k"),
+            (TokenType::RBracket, "]"),
+            (TokenType::StringTail, " c"),
+            (TokenType::StringHead, ""),
+            (TokenType::Ident, "z"),
+            (TokenType::StringTail, ""),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, expected_literal) in tests.iter() {
+            let tok = l.next().unwrap();
+            assert_eq!(tok.t, *expected_type);
+            assert_eq!(tok.literal, *expected_literal);
+        }
+        assert_eq!(l.next(), None);
+        assert!(l.errors().is_empty());
+    }
+
+    #[test]
+    fn test_string_errors() {
+        let tests = [
+            ("let s = \"abc", vec![("unterminated string", (1, 9))]),
+            ("\"a ${x} b", vec![("unterminated string", (1, 1))]),
+            (r#""a\qb""#, vec![("unknown escape sequence: \\q", (1, 3))]),
+            (
+                r#""\u{110000}" "\u{zz}" "\u41""#,
+                vec![
+                    ("invalid unicode escape", (1, 2)),
+                    ("invalid unicode escape", (1, 15)),
+                    ("invalid unicode escape", (1, 24)),
+                ],
+            ),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let mut l = Lexer::new(input);
+            while l.next().is_some() {}
+            let expected: Vec<LexError> = expected
+                .iter()
+                .map(|(message, (line, column))| LexError {
+                    message: message.to_string(),
+                    pos: Position::new(*line, *column),
+                })
+                .collect();
+            assert_eq!(l.errors(), expected.as_slice(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = r#"# shebang or note
+let x = 1; // trailing
+/* block /* nested */ still comment */ x /= 2;
+a / b // c
+/**/ "// not a comment" #"#;
+
+        let tests = [
+            (TokenType::Let, "let"),
+            (TokenType::Ident, "x"),
+            (TokenType::Assign, "="),
+            (TokenType::Int, "1"),
+            (TokenType::Semicolon, ";"),
+            (TokenType::Ident, "x"),
+            (TokenType::SlashAssign, "/="),
+            (TokenType::Int, "2"),
+            (TokenType::Semicolon, ";"),
+            (TokenType::Ident, "a"),
+            (TokenType::Slash, "/"),
+            (TokenType::Ident, "b"),
+            (TokenType::String, "// not a comment"),
         ];
 
         let mut l = Lexer::new(input);
@@ -351,5 +729,89 @@ macro(x, y) { x + y; };"#;
             assert_eq!(tok.literal, *expected_literal);
         }
         assert_eq!(l.next(), None);
+        assert!(l.errors().is_empty());
+        assert!(l.doc_comments().is_empty());
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = "/// Adds one.\n//// not doc\nlet inc = /** n: /* int */ */ fn(n) { n + 1 }; /*** not doc */";
+
+        let mut l = Lexer::new(input);
+        let types: Vec<TokenType> = l.by_ref().map(|tok| tok.t).collect();
+        assert_eq!(types.len(), 13);
+
+        let expected = vec![
+            DocComment {
+                text: "/// Adds one.".to_string(),
+                pos: Position::new(1, 1),
+                end: Position::new(1, 14),
+            },
+            DocComment {
+                text: "/** n: /* int */ */".to_string(),
+                pos: Position::new(3, 11),
+                end: Position::new(3, 30),
+            },
+        ];
+        assert_eq!(l.doc_comments(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut l = Lexer::new("1 /* a /* b */ 2");
+        assert_eq!(l.next().unwrap().t, TokenType::Int);
+        assert_eq!(l.next(), None);
+        assert_eq!(
+            l.errors(),
+            &[LexError {
+                message: "unterminated block comment".to_string(),
+                pos: Position::new(1, 3),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_identifiers() {
+        let input = "user1 _x2y 1abc 名前 = café_2; ユーザー１ x@1 αβγ नमस्ते x² e\u{301}";
+
+        let tests = [
+            (TokenType::Ident, "user1"),
+            (TokenType::Ident, "_x2y"),
+            (TokenType::Int, "1"),
+            (TokenType::Ident, "abc"),
+            (TokenType::Ident, "名前"),
+            (TokenType::Assign, "="),
+            (TokenType::Ident, "café_2"),
+            (TokenType::Semicolon, ";"),
+            (TokenType::Ident, "ユーザー１"),
+            (TokenType::Ident, "x"),
+            (TokenType::Illegal, "@"),
+            (TokenType::Int, "1"),
+            (TokenType::Ident, "αβγ"),
+            // 結合文字は続く文字として使える
+            (TokenType::Ident, "नमस्ते"),
+            // 上付きの数字は identifier に使えない
+            (TokenType::Ident, "x"),
+            (TokenType::Illegal, "²"),
+            (TokenType::Ident, "e\u{301}"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, expected_literal) in tests.iter() {
+            let tok = l.next().unwrap();
+            assert_eq!(tok.t, *expected_type);
+            assert_eq!(tok.literal, *expected_literal);
+        }
+        assert_eq!(l.next(), None);
+    }
+
+    #[test]
+    fn test_unicode_identifier_positions() {
+        let mut l = Lexer::new("let 名前 = 1;");
+        let tokens: Vec<_> = l.by_ref().collect();
+        assert_eq!(tokens[1].pos, Position::new(1, 5));
+        assert_eq!(tokens[1].end, Position::new(1, 7));
+        assert_eq!(tokens[2].pos, Position::new(1, 8));
     }
 }
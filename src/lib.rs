@@ -1,14 +1,23 @@
+mod arithmetic;
 mod ast;
+mod bytecode;
 mod code;
 mod compiler;
+mod diagnostic;
+mod disassembler;
 mod evaluator;
 mod lexer;
 mod object;
 mod parser;
 pub mod repl;
+pub mod script;
+mod session;
 #[cfg(test)]
 mod test_utils;
 mod token;
 #[macro_use]
 mod utils;
 mod vm;
+
+pub use arithmetic::IntegerMode;
+pub use session::{Engine, THREAD_STACK_SIZE};
@@ -1,7 +1,136 @@
-use wacir::repl;
+use std::io::{self, IsTerminal, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::{env, fs, process, thread};
+use wacir::{repl, script, Engine, IntegerMode, THREAD_STACK_SIZE};
+
+const USAGE: &str = "usage: wacir [--engine vm|eval|diff] [--integer-mode checked|wrapping] [path/to/script.monkey | -]
+       wacir compile path/to/script.monkey [-o path/to/script.wbc]
+       wacir run [--integer-mode checked|wrapping] path/to/script.wbc
+       wacir disasm path/to/script.monkey|path/to/script.wbc";
 
 fn main() {
-    println!("Hello! This is the Monkey programming language!");
-    println!("Feel free to type in commands");
-    repl::start()
+    // evaluator の深い再帰に備えて、大きな stack の thread で動かす
+    let handle = thread::Builder::new()
+        .stack_size(THREAD_STACK_SIZE)
+        .spawn(run_command)
+        .unwrap_or_else(|err| {
+            eprintln!("wacir: cannot start: {}", err);
+            process::exit(1);
+        });
+    if handle.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run_command() {
+    let mut engine = Engine::Vm;
+    let mut integer_mode = IntegerMode::Checked;
+    let mut output = None;
+    let mut paths = vec![];
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(value) = option_value(&arg, "-o", &mut args) {
+            output = Some(value);
+        } else if let Some(value) = option_value(&arg, "--engine", &mut args) {
+            engine = parse_option(&value);
+        } else if let Some(value) = option_value(&arg, "--integer-mode", &mut args) {
+            integer_mode = parse_option(&value);
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    let result = match paths.as_slice() {
+        [command, path] if command == "compile" => compile(path, output),
+        [command, path] if command == "run" => run(path, engine, integer_mode),
+        [command, path] if command == "disasm" => disasm(path),
+        _ if output.is_some() => exit_with_usage("-o is only available for compile"),
+        [] if io::stdin().is_terminal() => {
+            println!("Hello! This is the Monkey programming language!");
+            println!("Feel free to type in commands");
+            return repl::start(engine, integer_mode);
+        }
+        // パイプで渡されたスクリプトはファイルと同じように実行する
+        [] => script::run("<stdin>", &read_stdin(), engine, integer_mode),
+        [path] if path == "-" => script::run("<stdin>", &read_stdin(), engine, integer_mode),
+        [path] => script::run(path, &read_source(path), engine, integer_mode),
+        _ => exit_with_usage("too many arguments"),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+// `--name value` と `--name=value` のどちらも受け付ける
+fn option_value(arg: &str, name: &str, args: &mut impl Iterator<Item = String>) -> Option<String> {
+    if arg == name {
+        let value = args.next();
+        Some(value.unwrap_or_else(|| exit_with_usage(&format!("missing value for {}", name))))
+    } else {
+        arg.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+            .map(|value| value.to_string())
+    }
+}
+
+fn parse_option<T: FromStr<Err = String>>(value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|err: String| exit_with_usage(&err))
+}
+
+fn compile(path: &str, output: Option<String>) -> Result<(), String> {
+    let output = output.unwrap_or_else(|| {
+        Path::new(path)
+            .with_extension("wbc")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let bytes = script::compile(path, &read_source(path))?;
+    fs::write(&output, bytes).map_err(|err| format!("wacir: cannot write {}: {}", output, err))
+}
+
+// compile 済みのファイルでもソースでも実行できる
+fn run(path: &str, engine: Engine, integer_mode: IntegerMode) -> Result<(), String> {
+    let bytes = fs::read(path).unwrap_or_else(|err| exit_with_read_error(path, err));
+    if !script::is_bytecode(&bytes) {
+        return script::run(path, &read_source(path), engine, integer_mode);
+    }
+    if engine != Engine::Vm {
+        exit_with_usage("compiled bytecode can only run on the vm engine");
+    }
+    script::run_bytecode(path, &bytes, integer_mode)
+}
+
+fn disasm(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).unwrap_or_else(|err| exit_with_read_error(path, err));
+    print!("{}", script::disasm(path, &bytes)?);
+    Ok(())
+}
+
+fn read_source(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| exit_with_read_error(path, err))
+}
+
+fn read_stdin() -> String {
+    let mut source = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut source) {
+        exit_with_read_error("stdin", err);
+    }
+    source
+}
+
+fn exit_with_read_error(path: &str, err: io::Error) -> ! {
+    eprintln!("wacir: cannot read {}: {}", path, err);
+    process::exit(1);
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("wacir: {}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
 }
@@ -3,19 +3,24 @@ use super::ast::{BlockStatement, Expression, Identifier};
 use super::code::Instructions;
 use super::enum_with_fmt;
 use super::evaluator::Environment;
+use super::token::Position;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::Hasher;
 use std::rc::Rc;
 
+pub mod builtin;
+
 enum_with_fmt!(
-    #[derive(Debug,Clone,PartialEq,Eq)]
+    #[derive(Debug,Clone,PartialEq)]
     pub enum Object {
         Integer(i64),
         Boolean(bool),
         ReturnValue(Box<Object>),
         Function(Function),
         CompiledFunction(CompiledFunction),
+        Closure(Closure),
         Builtin(Builtin),
         Array(Array),
         Hash(Hash),
@@ -23,13 +28,42 @@ enum_with_fmt!(
         Quote(Quote),
         Macro(Macro),
         => // custom format
+        // 1.0 を 1 と表示しないように Debug 形式を使う
+        Float(f64) => "{:?}",
         Error(String) => "Error: {}",
         ;=> // without data and custom format
         Null => "null",
+        // evaluator でループの外へ伝える合図。VM では使わない
+        Break => "break",
+        Continue => "continue",
     }
 );
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl Object {
+    // エラーメッセージで値の種類を示すための名前
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::Function(_)
+            | Object::CompiledFunction(_)
+            | Object::Closure(_)
+            | Object::Builtin(_) => "FUNCTION",
+            Object::Null => "NULL",
+            Object::ReturnValue(_) => "RETURN_VALUE",
+            Object::Error(_) => "ERROR",
+            Object::Quote(_) => "QUOTE",
+            Object::Macro(_) => "MACRO",
+            Object::Break | Object::Continue => "LOOP_SIGNAL",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub parameters: Vec<Identifier>,
     pub body: BlockStatement,
@@ -55,15 +89,61 @@ impl fmt::Display for Function {
 pub struct CompiledFunction {
     pub instructions: Instructions,
     pub num_locals: usize,
+    pub num_parameters: usize,
+    pub positions: Vec<(usize, Position)>,
+}
+
+impl CompiledFunction {
+    // ip の命令を生成したソース上の位置
+    pub fn position_at(&self, ip: usize) -> Option<Position> {
+        self.positions
+            .iter()
+            .take_while(|(offset, _)| *offset <= ip)
+            .last()
+            .map(|(_, pos)| *pos)
+    }
+
+    // 関数を見分けるための命令列の FNV-1a hash。実行ごとに変わらない
+    // 表示が長くならないように下位 32 bit だけ使う
+    pub fn fingerprint(&self) -> u32 {
+        let mut hasher = hash::Fnv1a::default();
+        hasher.write(&self.instructions.0);
+        hasher.finish() as u32
+    }
 }
 
 impl fmt::Display for CompiledFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CompiledFunction[{}]", self)
+        write!(
+            f,
+            "CompiledFunction[{:08x} params={} locals={}]",
+            self.fingerprint(),
+            self.num_parameters,
+            self.num_locals
+        )
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub func: Rc<CompiledFunction>,
+    pub free: Vec<Object>,
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Closure[{:08x} params={} locals={} free={}]",
+            self.func.fingerprint(),
+            self.func.num_parameters,
+            self.func.num_locals,
+            self.free.len()
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Array {
     pub elements: Vec<Object>,
 }
@@ -82,26 +162,38 @@ impl fmt::Display for Array {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Builtin {
-    pub func: fn(Vec<Object>) -> Object,
+    pub name: &'static str,
+    pub func: fn(&mut dyn builtin::Caller, Vec<Object>) -> Object,
 }
 
+// 関数ポインタのアドレスは一意とは限らないので名前で比べる
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Builtin {}
+
 impl fmt::Display for Builtin {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "builtin function")
+        write!(f, "Builtin[{}]", self.name)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct HashPair {
     pub key: Object,
     pub value: Object,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// 入れた順に key を並べる。index は key から entries の位置を引く
+#[derive(Debug, Clone, Default)]
 pub struct Hash {
-    pub pairs: HashMap<HashKey, HashPair>,
+    entries: Vec<(HashKey, HashPair)>,
+    index: HashMap<HashKey, usize>,
 }
 
 impl fmt::Display for Hash {
@@ -109,15 +201,164 @@ impl fmt::Display for Hash {
         write!(
             f,
             "{{{}}}",
-            self.pairs
-                .iter()
-                .map(|(_, pair)| format!("{}: {}", pair.key, pair.value))
+            self.pairs()
+                .map(|pair| format!("{}: {}", pair.key, pair.value))
                 .collect::<Vec<_>>()
                 .join(", ")
         )
     }
 }
 
+// 順番が違っても同じ key と value を持っていれば等しい
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .entries
+                .iter()
+                .all(|(key, pair)| other.get(key).is_some_and(|p| p.value == pair.value))
+    }
+}
+
+impl Hash {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &HashKey) -> Option<&HashPair> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn get_mut(&mut self, key: &HashKey) -> Option<&mut HashPair> {
+        match self.index.get(key) {
+            Some(&i) => Some(&mut self.entries[i].1),
+            None => None,
+        }
+    }
+
+    // すでにある key なら順番はそのままで値を置き換える
+    pub fn insert(&mut self, key: HashKey, pair: HashPair) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = pair,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, pair));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &HashKey) -> Option<HashPair> {
+        let i = self.index.remove(key)?;
+        let (_, pair) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Some(pair)
+    }
+
+    // 入れた順
+    pub fn pairs(&self) -> impl Iterator<Item = &HashPair> {
+        self.entries.iter().map(|(_, pair)| pair)
+    }
+}
+
+// for-in で回す要素。VM と evaluator で同じ順番になるようにここで作る
+// with_value のときは配列と文字列なら [index, 要素]、ハッシュなら [key, value] の組を返す
+pub fn iteration_items(iterable: &Object, with_value: bool) -> Result<Vec<Object>, String> {
+    let pair = |first: Object, second: Object| {
+        Object::Array(Array {
+            elements: vec![first, second],
+        })
+    };
+    let elements = match iterable {
+        Object::Array(array) => array.elements.clone(),
+        Object::String(string) => string
+            .chars()
+            .map(|c| Object::String(c.to_string()))
+            .collect(),
+        Object::Hash(hash) => {
+            return Ok(hash
+                .pairs()
+                .map(|p| {
+                    if with_value {
+                        pair(p.key.clone(), p.value.clone())
+                    } else {
+                        p.key.clone()
+                    }
+                })
+                .collect())
+        }
+        _ => return Err(format!("cannot iterate over {}", iterable)),
+    };
+    if !with_value {
+        return Ok(elements);
+    }
+    Ok(elements
+        .into_iter()
+        .enumerate()
+        .map(|(i, el)| pair(Object::Integer(i as i64), el))
+        .collect())
+}
+
+// xs[i][j] = v の代入。配列とハッシュは値なので、書き換えた container を新しく返す
+// update は今の値 (ハッシュにない key なら null) から代入する値を作る
+// 戻り値は書き換えた container と代入した値
+pub fn update_index<F>(
+    container: Object,
+    indices: &[Object],
+    update: F,
+) -> Result<(Object, Object), String>
+where
+    F: FnOnce(Object) -> Result<Object, String>,
+{
+    let (index, rest) = indices
+        .split_first()
+        .expect("update_index needs at least one index");
+    let update_element = |current: Object| {
+        if rest.is_empty() {
+            let value = update(current)?;
+            Ok((value.clone(), value))
+        } else {
+            update_index(current, rest, update)
+        }
+    };
+
+    match container {
+        Object::Array(mut array) => {
+            let len = array.elements.len();
+            let i = match index {
+                Object::Integer(i) if *i >= 0 && (*i as usize) < len => *i as usize,
+                Object::Integer(i) => {
+                    return Err(format!("index out of range: {} (length {})", i, len))
+                }
+                _ => return Err(format!("array index must be an integer: {}", index)),
+            };
+            let current = std::mem::replace(&mut array.elements[i], Object::Null);
+            let (element, value) = update_element(current)?;
+            array.elements[i] = element;
+            Ok((Object::Array(array), value))
+        }
+        Object::Hash(mut hash) => {
+            let key = hash::hash_key_of(index)?;
+            let current = hash.get_mut(&key).map_or(Object::Null, |pair| {
+                std::mem::replace(&mut pair.value, Object::Null)
+            });
+            let (element, value) = update_element(current)?;
+            hash.insert(
+                key,
+                HashPair {
+                    key: index.clone(),
+                    value: element,
+                },
+            );
+            Ok((Object::Hash(hash), value))
+        }
+        other => Err(format!("index assignment not supported: {}", other)),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Quote {
     pub node: Expression,
@@ -129,7 +370,7 @@ impl fmt::Display for Quote {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Macro {
     pub parameters: Vec<Identifier>,
     pub body: BlockStatement,
@@ -153,29 +394,115 @@ impl fmt::Display for Macro {
 
 pub mod hash {
     use super::Object;
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::Hasher;
+    use std::hash::{Hash, Hasher};
 
+    // 関数の表示は engine ごとに違うので、エラーには型の名前を出す
+    // 配列とハッシュの中に使えない値があれば、その値の型を出す
     pub fn hash_key_of(object: &Object) -> Result<HashKey, String> {
         Ok(match object {
             Object::String(string) => string.hash_key(),
             Object::Integer(integer) => integer.hash_key(),
             Object::Boolean(boolean) => boolean.hash_key(),
-            _ => return Err(format!("unusable as hash key: {}", object)),
+            Object::Null => HashKey::Null,
+            // 配列とハッシュは値なので、中身をたどって key にする
+            Object::Array(array) => HashKey::Array(
+                array
+                    .elements
+                    .iter()
+                    .map(hash_key_of)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Object::Hash(hash) => {
+                let mut pairs = hash
+                    .pairs()
+                    .map(|pair| Ok((hash_key_of(&pair.key)?, hash_key_of(&pair.value)?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                // == と同じく順番によらず同じ key にする
+                pairs.sort();
+                HashKey::Hash(pairs)
+            }
+            _ => return Err(format!("unusable as hash key: {}", object.type_name())),
         })
     }
 
-    #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-    pub struct HashKey {
-        t: HashType,
-        value: u64,
+    // key の値そのものを持つ。hash 値が衝突しても、値が違えば別の key になる
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum HashKey {
+        Integer(i64),
+        Boolean(bool),
+        String(String),
+        Null,
+        Array(Vec<HashKey>),
+        // key の順に並べた組
+        Hash(Vec<(HashKey, HashKey)>),
     }
 
-    #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-    enum HashType {
-        Integer,
-        Boolean,
-        String,
+    impl HashKey {
+        // 型の tag と値の byte 列の 64 bit FNV-1a
+        // 配列とハッシュは要素数と、各要素の stable_hash を並べた byte 列にする
+        // Rust の版や実行ごとに変わらないので、保存するデータから使ってよい
+        pub fn stable_hash(&self) -> u64 {
+            let mut hasher = Fnv1a::default();
+            match self {
+                HashKey::Integer(integer) => {
+                    hasher.write(&[0]);
+                    hasher.write(&integer.to_le_bytes());
+                }
+                HashKey::Boolean(boolean) => {
+                    hasher.write(&[1]);
+                    hasher.write(&[*boolean as u8]);
+                }
+                HashKey::String(string) => {
+                    hasher.write(&[2]);
+                    hasher.write(string.as_bytes());
+                }
+                HashKey::Null => hasher.write(&[3]),
+                HashKey::Array(elements) => {
+                    hasher.write(&[4]);
+                    hasher.write(&(elements.len() as u64).to_le_bytes());
+                    for element in elements {
+                        hasher.write(&element.stable_hash().to_le_bytes());
+                    }
+                }
+                HashKey::Hash(pairs) => {
+                    hasher.write(&[5]);
+                    hasher.write(&(pairs.len() as u64).to_le_bytes());
+                    for (key, value) in pairs {
+                        hasher.write(&key.stable_hash().to_le_bytes());
+                        hasher.write(&value.stable_hash().to_le_bytes());
+                    }
+                }
+            }
+            hasher.finish()
+        }
+    }
+
+    // HashMap の bucket 分けにも stable_hash を使う
+    impl Hash for HashKey {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write_u64(self.stable_hash());
+        }
+    }
+
+    // 64 bit FNV-1a。HashKey と関数の fingerprint で使う
+    pub(crate) struct Fnv1a(u64);
+
+    impl Default for Fnv1a {
+        fn default() -> Self {
+            Fnv1a(0xcbf29ce484222325)
+        }
+    }
+
+    impl Hasher for Fnv1a {
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
     }
 
     pub trait Hashable {
@@ -184,38 +511,28 @@ pub mod hash {
 
     impl Hashable for String {
         fn hash_key(&self) -> HashKey {
-            let mut hasher = DefaultHasher::new();
-            hasher.write(self.as_bytes());
-            HashKey {
-                t: HashType::String,
-                value: hasher.finish(),
-            }
+            HashKey::String(self.clone())
         }
     }
 
     impl Hashable for i64 {
         fn hash_key(&self) -> HashKey {
-            HashKey {
-                t: HashType::Integer,
-                value: *self as u64,
-            }
+            HashKey::Integer(*self)
         }
     }
 
     impl Hashable for bool {
         fn hash_key(&self) -> HashKey {
-            let value = if *self { 1 } else { 0 };
-
-            HashKey {
-                t: HashType::Boolean,
-                value,
-            }
+            HashKey::Boolean(*self)
         }
     }
 
     #[cfg(test)]
     mod tests {
-        use super::Hashable;
+        use super::super::{Array, Hash, HashPair, Object};
+        use super::{hash_key_of, Fnv1a, HashKey, Hashable};
+        use std::collections::HashMap;
+        use std::hash::{BuildHasherDefault, Hasher};
 
         #[test]
         fn test_string_hash_key() {
@@ -252,5 +569,342 @@ pub mod hash {
             assert_eq!(two1.hash_key(), two2.hash_key());
             assert_ne!(one1.hash_key(), two1.hash_key());
         }
+
+        #[test]
+        fn test_fnv1a() {
+            let tests = [
+                ("", 0xcbf29ce484222325),
+                ("a", 0xaf63dc4c8601ec8c),
+                ("foobar", 0x85944171f73967e8),
+            ];
+            for (input, expected) in tests.iter() {
+                let mut hasher = Fnv1a::default();
+                hasher.write(input.as_bytes());
+                assert_eq!(hasher.finish(), *expected, "{:?}", input);
+            }
+        }
+
+        #[test]
+        fn test_stable_hash() {
+            // 値が変わると保存したデータが読めなくなるので固定しておく
+            assert_eq!(
+                HashKey::String("a".to_string()).stable_hash(),
+                0x08393307b4f0fe2c
+            );
+            assert_eq!(HashKey::Integer(1).stable_hash(), 0xc709bb3119a0df9e);
+            assert_eq!(HashKey::Boolean(true).stable_hash(), 0x082f2307b4e88e77);
+            // 型が違えば同じ byte 列でも別の値になる
+            assert_ne!(
+                HashKey::Integer(0).stable_hash(),
+                HashKey::String("\0\0\0\0\0\0\0\0".to_string()).stable_hash()
+            );
+        }
+
+        #[test]
+        fn test_structural_hash_keys() {
+            let array = |elements: Vec<Object>| Object::Array(Array { elements });
+            let hash = |pairs: Vec<(Object, Object)>| {
+                let mut hash = Hash::default();
+                for (key, value) in pairs {
+                    hash.insert(hash_key_of(&key).unwrap(), HashPair { key, value });
+                }
+                Object::Hash(hash)
+            };
+            let int = Object::Integer;
+            let string = |s: &str| Object::String(s.to_string());
+
+            let equal = vec![
+                (
+                    array(vec![int(2024), int(1)]),
+                    array(vec![int(2024), int(1)]),
+                ),
+                (
+                    hash(vec![(string("a"), int(1)), (string("b"), array(vec![]))]),
+                    hash(vec![(string("b"), array(vec![])), (string("a"), int(1))]),
+                ),
+                (
+                    array(vec![hash(vec![(Object::Null, int(1))])]),
+                    array(vec![hash(vec![(Object::Null, int(1))])]),
+                ),
+            ];
+            for (a, b) in equal {
+                let (key_a, key_b) = (hash_key_of(&a).unwrap(), hash_key_of(&b).unwrap());
+                assert_eq!(key_a, key_b, "{} {}", a, b);
+                assert_eq!(key_a.stable_hash(), key_b.stable_hash(), "{} {}", a, b);
+            }
+
+            let different = vec![
+                (array(vec![int(1), int(2)]), array(vec![int(2), int(1)])),
+                (
+                    array(vec![string("ab"), string("c")]),
+                    array(vec![string("a"), string("bc")]),
+                ),
+                (array(vec![]), Object::Null),
+                (array(vec![int(1)]), int(1)),
+                (
+                    hash(vec![(string("a"), int(1))]),
+                    hash(vec![(string("a"), int(2))]),
+                ),
+                (array(vec![array(vec![])]), array(vec![])),
+            ];
+            for (a, b) in different {
+                let (key_a, key_b) = (hash_key_of(&a).unwrap(), hash_key_of(&b).unwrap());
+                assert_ne!(key_a, key_b, "{} {}", a, b);
+                assert_ne!(key_a.stable_hash(), key_b.stable_hash(), "{} {}", a, b);
+            }
+
+            let unusable = array(vec![int(1), Object::Float(1.5)]);
+            assert_eq!(
+                hash_key_of(&unusable),
+                Err("unusable as hash key: FLOAT".to_string())
+            );
+        }
+
+        // すべての key が同じ bucket に入っても、値で見分けられる
+        #[test]
+        fn test_colliding_hash_keys() {
+            #[derive(Default)]
+            struct Colliding;
+
+            impl Hasher for Colliding {
+                fn write(&mut self, _: &[u8]) {}
+
+                fn finish(&self) -> u64 {
+                    0
+                }
+            }
+
+            let mut map: HashMap<HashKey, i64, BuildHasherDefault<Colliding>> = HashMap::default();
+            map.insert("a".to_string().hash_key(), 1);
+            map.insert("b".to_string().hash_key(), 2);
+            map.insert(1_i64.hash_key(), 3);
+            map.insert(true.hash_key(), 4);
+
+            assert_eq!(map.len(), 4);
+            assert_eq!(map.get(&"a".to_string().hash_key()), Some(&1));
+            assert_eq!(map.get(&"b".to_string().hash_key()), Some(&2));
+            assert_eq!(map.get(&1_i64.hash_key()), Some(&3));
+            assert_eq!(map.get(&true.hash_key()), Some(&4));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::code::{make, Opcode};
+    use super::builtin::get_builtin_by_name;
+    use super::hash::hash_key_of;
+    use super::{
+        iteration_items, update_index, Array, Closure, CompiledFunction, Hash, HashPair, Object,
+    };
+    use std::rc::Rc;
+
+    #[test]
+    fn test_opaque_object_display() {
+        let func = CompiledFunction {
+            instructions: make(Opcode::OpReturn),
+            num_locals: 2,
+            num_parameters: 1,
+            positions: vec![],
+        };
+        let closure = Closure {
+            func: Rc::new(func.clone()),
+            free: vec![Object::Integer(1)],
+        };
+
+        assert_eq!(
+            format!("{}", Object::CompiledFunction(func.clone())),
+            format!(
+                "CompiledFunction[{:08x} params=1 locals=2]",
+                func.fingerprint()
+            )
+        );
+        assert_eq!(
+            format!("{}", Object::Closure(closure)),
+            format!(
+                "Closure[{:08x} params=1 locals=2 free=1]",
+                func.fingerprint()
+            )
+        );
+        assert_eq!(
+            format!("{}", Object::Builtin(get_builtin_by_name("len").unwrap())),
+            "Builtin[len]"
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let func = |op| CompiledFunction {
+            instructions: make(op),
+            num_locals: 0,
+            num_parameters: 0,
+            positions: vec![],
+        };
+
+        assert_eq!(
+            func(Opcode::OpReturn).fingerprint(),
+            func(Opcode::OpReturn).fingerprint()
+        );
+        assert_ne!(
+            func(Opcode::OpReturn).fingerprint(),
+            func(Opcode::OpNull).fingerprint()
+        );
+        // FNV-1a の空入力は offset basis の下位 32 bit になる
+        assert_eq!(
+            CompiledFunction {
+                instructions: super::super::code::Instructions(vec![]),
+                ..func(Opcode::OpNull)
+            }
+            .fingerprint(),
+            0x84222325
+        );
+    }
+
+    #[test]
+    fn test_update_index() {
+        let array = |elements: Vec<Object>| Object::Array(Array { elements });
+        let set = |value: i64| move |_| Ok(Object::Integer(value));
+        let nested = array(vec![
+            Object::Integer(1),
+            array(vec![Object::Integer(2), Object::Integer(3)]),
+        ]);
+
+        let result = update_index(
+            nested.clone(),
+            &[Object::Integer(1), Object::Integer(0)],
+            set(9),
+        )
+        .map(|(container, value)| (format!("{}", container), value));
+        assert_eq!(result, Ok(("[1, [9, 3]]".to_string(), Object::Integer(9))));
+
+        let result = update_index(
+            nested.clone(),
+            &[Object::Integer(0)],
+            |current| match current {
+                Object::Integer(i) => Ok(Object::Integer(i + 10)),
+                _ => Err("not an integer".to_string()),
+            },
+        )
+        .map(|(container, value)| (format!("{}", container), value));
+        assert_eq!(
+            result,
+            Ok(("[11, [2, 3]]".to_string(), Object::Integer(11)))
+        );
+
+        let hash = Object::Hash(Hash::default());
+        let key = Object::String("k".to_string());
+        let (hash, _) = update_index(hash, &[key.clone()], set(1)).unwrap();
+        let (hash, value) = update_index(hash, &[key], |current| {
+            assert_eq!(current, Object::Integer(1));
+            Ok(Object::Integer(2))
+        })
+        .unwrap();
+        assert_eq!(format!("{}", hash), "{k: 2}");
+        assert_eq!(value, Object::Integer(2));
+
+        let tests = vec![
+            (
+                nested.clone(),
+                Object::Integer(2),
+                "index out of range: 2 (length 2)",
+            ),
+            (
+                nested.clone(),
+                Object::Integer(-1),
+                "index out of range: -1 (length 2)",
+            ),
+            (
+                nested,
+                Object::Boolean(true),
+                "array index must be an integer: true",
+            ),
+            (
+                Object::Integer(1),
+                Object::Integer(0),
+                "index assignment not supported: 1",
+            ),
+        ];
+        for (container, index, expected) in tests {
+            assert_eq!(
+                update_index(container, &[index], set(0)),
+                Err(expected.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_hash_insertion_order() {
+        let pair = |key: &str, value: i64| {
+            let key = Object::String(key.to_string());
+            (
+                hash_key_of(&key).unwrap(),
+                HashPair {
+                    key,
+                    value: Object::Integer(value),
+                },
+            )
+        };
+        let mut hash = Hash::default();
+        for (key, value) in [("c", 1), ("a", 2), ("b", 3), ("a", 4)] {
+            let (key, pair) = pair(key, value);
+            hash.insert(key, pair);
+        }
+        assert_eq!(format!("{}", hash), "{c: 1, a: 4, b: 3}");
+
+        let (c, _) = pair("c", 0);
+        assert_eq!(hash.remove(&c).map(|p| p.value), Some(Object::Integer(1)));
+        assert_eq!(hash.remove(&c), None);
+        let (b, _) = pair("b", 0);
+        assert_eq!(
+            hash.get(&b).map(|p| p.value.clone()),
+            Some(Object::Integer(3))
+        );
+        let (key, value) = pair("c", 5);
+        hash.insert(key, value);
+        assert_eq!(format!("{}", hash), "{a: 4, b: 3, c: 5}");
+        assert_eq!(hash.len(), 3);
+
+        let mut reordered = Hash::default();
+        for (key, value) in [("c", 5), ("b", 3), ("a", 4)] {
+            let (key, pair) = pair(key, value);
+            reordered.insert(key, pair);
+        }
+        assert_eq!(hash, reordered);
+    }
+
+    #[test]
+    fn test_iteration_items() {
+        let array = |elements: Vec<Object>| Object::Array(Array { elements });
+        let mut hash = Hash::default();
+        for (key, value) in [(2, "b"), (1, "a")] {
+            let key = Object::Integer(key);
+            let value = Object::String(value.to_string());
+            hash.insert(hash_key_of(&key).unwrap(), HashPair { key, value });
+        }
+        let hash = Object::Hash(hash);
+
+        let tests = vec![
+            (
+                array(vec![Object::Boolean(true), Object::Null]),
+                false,
+                Ok("[true, null]"),
+            ),
+            (array(vec![Object::Boolean(true)]), true, Ok("[[0, true]]")),
+            (Object::String("ab".to_string()), false, Ok("[a, b]")),
+            (
+                Object::String("ab".to_string()),
+                true,
+                Ok("[[0, a], [1, b]]"),
+            ),
+            (hash.clone(), false, Ok("[2, 1]")),
+            (hash, true, Ok("[[2, b], [1, a]]")),
+            (Object::Integer(1), false, Err("cannot iterate over 1")),
+        ];
+
+        for (iterable, with_value, expected) in tests {
+            let items =
+                iteration_items(&iterable, with_value).map(|items| format!("{}", array(items)));
+            assert_eq!(items, expected.map(String::from).map_err(String::from));
+        }
     }
 }
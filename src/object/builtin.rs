@@ -0,0 +1,672 @@
+use super::super::arithmetic;
+use super::{Array, Builtin, Object};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+const NULL: Object = Object::Null;
+// range が作る配列の長さの上限。大きすぎる配列でメモリを使い切らないようにする
+const MAX_RANGE_LENGTH: i128 = 1_000_000;
+
+thread_local! {
+    // Some のあいだは puts の出力を標準出力に書かずにためておく
+    static OUTPUT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// f を実行するあいだの puts の出力を集めて返す
+pub fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let previous = OUTPUT.with(|output| output.replace(Some(String::new())));
+    let result = f();
+    let captured = OUTPUT.with(|output| output.replace(previous));
+    (result, captured.unwrap_or_default())
+}
+
+// puts と同じ出力先に書く
+pub fn write_output(text: &str) {
+    OUTPUT.with(|output| match &mut *output.borrow_mut() {
+        Some(buffer) => buffer.push_str(text),
+        None => print!("{}", text),
+    });
+}
+
+// builtin から Monkey の関数を呼ぶための口。VM と evaluator がそれぞれ実装する
+pub trait Caller {
+    fn call(&mut self, func: Object, args: Vec<Object>) -> Result<Object, String>;
+}
+
+// Result を返す処理を Object を返す builtin の中で ? のように使う
+macro_rules! try_object {
+    ($e: expr) => {
+        match $e {
+            Ok(value) => value,
+            Err(message) => return new_error(message),
+        }
+    };
+}
+
+pub mod hash;
+pub mod string;
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "len",
+        func: len,
+    },
+    Builtin {
+        name: "puts",
+        func: puts,
+    },
+    Builtin {
+        name: "first",
+        func: first,
+    },
+    Builtin {
+        name: "last",
+        func: last,
+    },
+    Builtin {
+        name: "rest",
+        func: rest,
+    },
+    Builtin {
+        name: "push",
+        func: push,
+    },
+    Builtin {
+        name: "int",
+        func: int,
+    },
+    Builtin {
+        name: "float",
+        func: float,
+    },
+    Builtin {
+        name: "str",
+        func: str,
+    },
+    Builtin {
+        name: "map",
+        func: map,
+    },
+    Builtin {
+        name: "filter",
+        func: filter,
+    },
+    Builtin {
+        name: "reduce",
+        func: reduce,
+    },
+    Builtin {
+        name: "sort",
+        func: sort,
+    },
+    Builtin {
+        name: "reverse",
+        func: reverse,
+    },
+    Builtin {
+        name: "slice",
+        func: slice,
+    },
+    Builtin {
+        name: "concat",
+        func: concat,
+    },
+    Builtin {
+        name: "contains",
+        func: contains,
+    },
+    Builtin {
+        name: "index_of",
+        func: index_of,
+    },
+    Builtin {
+        name: "join",
+        func: join,
+    },
+    Builtin {
+        name: "range",
+        func: range,
+    },
+    Builtin {
+        name: "split",
+        func: string::split,
+    },
+    Builtin {
+        name: "trim",
+        func: string::trim,
+    },
+    Builtin {
+        name: "upper",
+        func: string::upper,
+    },
+    Builtin {
+        name: "lower",
+        func: string::lower,
+    },
+    Builtin {
+        name: "replace",
+        func: string::replace,
+    },
+    Builtin {
+        name: "starts_with",
+        func: string::starts_with,
+    },
+    Builtin {
+        name: "ends_with",
+        func: string::ends_with,
+    },
+    Builtin {
+        name: "chars",
+        func: string::chars,
+    },
+    Builtin {
+        name: "to_string",
+        func: str,
+    },
+    Builtin {
+        name: "parse_int",
+        func: string::parse_int,
+    },
+    Builtin {
+        name: "keys",
+        func: hash::keys,
+    },
+    Builtin {
+        name: "values",
+        func: hash::values,
+    },
+    Builtin {
+        name: "items",
+        func: hash::items,
+    },
+    Builtin {
+        name: "has_key",
+        func: hash::has_key,
+    },
+    Builtin {
+        name: "get",
+        func: hash::get,
+    },
+    Builtin {
+        name: "delete",
+        func: hash::delete,
+    },
+    Builtin {
+        name: "merge",
+        func: hash::merge,
+    },
+];
+
+pub fn get_builtin_by_name(name: &str) -> Option<Builtin> {
+    BUILTINS
+        .iter()
+        .find(|builtin| builtin.name == name)
+        .cloned()
+}
+
+fn new_error(message: String) -> Object {
+    Object::Error(message)
+}
+
+fn len(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    match &args[0] {
+        Object::String(string) => Object::Integer(string::char_len(string) as i64),
+        Object::Array(array) => Object::Integer(array.elements.len() as i64),
+        Object::Hash(hash) => Object::Integer(hash.len() as i64),
+        _ => new_error(format!(
+            "argument to `len` not supported, got {:?}",
+            args[0]
+        )),
+    }
+}
+
+fn puts(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    for arg in args {
+        write_output(&format!("{}\n", arg));
+    }
+    NULL
+}
+
+fn first(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    if let Object::Array(array) = &args[0] {
+        if !array.elements.is_empty() {
+            array.elements.first().unwrap().clone()
+        } else {
+            NULL
+        }
+    } else {
+        new_error(format!(
+            "argument to `first` must be ARRAY, got {:?}",
+            args[0]
+        ))
+    }
+}
+
+fn last(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    if let Object::Array(array) = &args[0] {
+        if !array.elements.is_empty() {
+            array.elements.last().unwrap().clone()
+        } else {
+            NULL
+        }
+    } else {
+        new_error(format!(
+            "argument to `last` must be ARRAY, got {:?}",
+            args[0]
+        ))
+    }
+}
+
+fn rest(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    if let Object::Array(array) = &args[0] {
+        if !array.elements.is_empty() {
+            Object::Array(Array {
+                elements: array.elements[1..].to_vec(),
+            })
+        } else {
+            NULL
+        }
+    } else {
+        new_error(format!(
+            "argument to `rest` must be ARRAY, got {:?}",
+            args[0]
+        ))
+    }
+}
+
+fn push(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 2 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=2",
+            args.len()
+        ));
+    }
+    if let Object::Array(array) = &args[0] {
+        let mut elements = array.elements[..].to_vec();
+        elements.push(args[1].clone());
+        Object::Array(Array { elements })
+    } else {
+        new_error(format!(
+            "argument to `push` must be ARRAY, got {:?}",
+            args[0]
+        ))
+    }
+}
+
+// 浮動小数点数は 0 に向かって切り捨てる
+fn int(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    match &args[0] {
+        Object::Integer(integer) => Object::Integer(*integer),
+        Object::Float(float) if float.is_finite() && float.trunc().abs() < i64::MAX as f64 => {
+            Object::Integer(float.trunc() as i64)
+        }
+        Object::Float(float) => new_error(format!("float out of integer range: {:?}", float)),
+        Object::String(string) => match string.trim().parse() {
+            Ok(integer) => Object::Integer(integer),
+            Err(_) => new_error(format!("could not parse {:?} as integer", string)),
+        },
+        _ => new_error(format!(
+            "argument to `int` not supported, got {:?}",
+            args[0]
+        )),
+    }
+}
+
+fn float(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    match &args[0] {
+        Object::Integer(integer) => Object::Float(*integer as f64),
+        Object::Float(float) => Object::Float(*float),
+        Object::String(string) => match string.trim().parse() {
+            Ok(float) => Object::Float(float),
+            Err(_) => new_error(format!("could not parse {:?} as float", string)),
+        },
+        _ => new_error(format!(
+            "argument to `float` not supported, got {:?}",
+            args[0]
+        )),
+    }
+}
+
+// 文字列の ${} で埋め込むときと同じ表示にする
+fn str(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return new_error(format!(
+            "wrong number of arguments. got={}, want=1",
+            args.len()
+        ));
+    }
+    match &args[0] {
+        Object::String(string) => Object::String(string.clone()),
+        obj => Object::String(obj.to_string()),
+    }
+}
+
+// min..=max 個の引数を受け取る
+fn check_arity(args: &[Object], min: usize, max: usize) -> Result<(), String> {
+    if (min..=max).contains(&args.len()) {
+        return Ok(());
+    }
+    let want = match max - min {
+        0 => min.to_string(),
+        1 => format!("{} or {}", min, max),
+        _ => format!("{} to {}", min, max),
+    };
+    Err(format!(
+        "wrong number of arguments. got={}, want={}",
+        args.len(),
+        want
+    ))
+}
+
+fn type_error(name: &str, want: &str, got: &Object) -> String {
+    format!(
+        "argument to `{}` must be {}, got {}",
+        name,
+        want,
+        got.type_name()
+    )
+}
+
+fn array_arg<'a>(name: &str, arg: &'a Object) -> Result<&'a [Object], String> {
+    match arg {
+        Object::Array(array) => Ok(&array.elements),
+        _ => Err(type_error(name, "ARRAY", arg)),
+    }
+}
+
+fn integer_arg(name: &str, arg: &Object) -> Result<i64, String> {
+    match arg {
+        Object::Integer(integer) => Ok(*integer),
+        _ => Err(type_error(name, "INTEGER", arg)),
+    }
+}
+
+fn function_arg(name: &str, arg: &Object) -> Result<Object, String> {
+    match arg {
+        Object::Function(_) | Object::Closure(_) | Object::Builtin(_) => Ok(arg.clone()),
+        _ => Err(type_error(name, "FUNCTION", arg)),
+    }
+}
+
+fn is_truthy(obj: &Object) -> bool {
+    !matches!(obj, Object::Boolean(false) | Object::Null)
+}
+
+// == と同じく 1 と 1.0 は等しい
+fn equals(left: &Object, right: &Object) -> bool {
+    match arithmetic::float_operands(left, right) {
+        Some((l, r)) => l == r,
+        None => left == right,
+    }
+}
+
+fn map(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let elements = try_object!(array_arg("map", &args[0]));
+    let func = try_object!(function_arg("map", &args[1]));
+
+    let mut mapped = Vec::with_capacity(elements.len());
+    for element in elements {
+        mapped.push(try_object!(caller.call(func.clone(), vec![element.clone()])));
+    }
+    Object::Array(Array { elements: mapped })
+}
+
+fn filter(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let elements = try_object!(array_arg("filter", &args[0]));
+    let func = try_object!(function_arg("filter", &args[1]));
+
+    let mut filtered = vec![];
+    for element in elements {
+        if is_truthy(&try_object!(
+            caller.call(func.clone(), vec![element.clone()])
+        )) {
+            filtered.push(element.clone());
+        }
+    }
+    Object::Array(Array { elements: filtered })
+}
+
+// reduce([1, 2, 3], 0, fn(acc, x) { acc + x })
+fn reduce(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 3, 3));
+    let elements = try_object!(array_arg("reduce", &args[0]));
+    let func = try_object!(function_arg("reduce", &args[2]));
+
+    let mut accumulator = args[1].clone();
+    for element in elements {
+        accumulator = try_object!(caller.call(func.clone(), vec![accumulator, element.clone()]));
+    }
+    accumulator
+}
+
+// sort(xs) は数値どうしか文字列どうしを昇順に並べる
+// sort(xs, fn(a, b) { a > b }) は a を b より前に置くときに true を返す関数で並べる
+// どちらも安定ソート
+fn sort(caller: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 2));
+    let elements = try_object!(array_arg("sort", &args[0])).to_vec();
+
+    let sorted = match args.get(1) {
+        Some(arg) => {
+            let func = try_object!(function_arg("sort", arg));
+            merge_sort(elements, &mut |a, b| {
+                caller
+                    .call(func.clone(), vec![a.clone(), b.clone()])
+                    .map(|result| is_truthy(&result))
+            })
+        }
+        None => merge_sort(elements, &mut |a, b| {
+            compare(a, b).map(|ordering| ordering == Ordering::Less)
+        }),
+    };
+    Object::Array(Array {
+        elements: try_object!(sorted),
+    })
+}
+
+fn compare(left: &Object, right: &Object) -> Result<Ordering, String> {
+    if let Some((l, r)) = arithmetic::float_operands(left, right) {
+        // NaN は他のどの値とも同じ順位にする
+        return Ok(l.partial_cmp(&r).unwrap_or(Ordering::Equal));
+    }
+    match (left, right) {
+        (Object::Integer(l), Object::Integer(r)) => Ok(l.cmp(r)),
+        (Object::String(l), Object::String(r)) => Ok(l.cmp(r)),
+        _ => Err(format!(
+            "cannot compare {} and {}",
+            left.type_name(),
+            right.type_name()
+        )),
+    }
+}
+
+// 比較関数が全順序にならなくても panic しないように、slice::sort_by は使わない
+fn merge_sort<F>(mut elements: Vec<Object>, less: &mut F) -> Result<Vec<Object>, String>
+where
+    F: FnMut(&Object, &Object) -> Result<bool, String>,
+{
+    if elements.len() <= 1 {
+        return Ok(elements);
+    }
+    let right = elements.split_off(elements.len() / 2);
+    let left = merge_sort(elements, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // 同じ順位なら左を先にして安定にする
+        if less(r, l)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn reverse(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let elements = try_object!(array_arg("reverse", &args[0]));
+    Object::Array(Array {
+        elements: elements.iter().rev().cloned().collect(),
+    })
+}
+
+// 文字列は文字単位で切り出す
+fn slice(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 3));
+    let start = try_object!(integer_arg("slice", &args[1]));
+    let end = match args.get(2) {
+        Some(arg) => Some(try_object!(integer_arg("slice", arg))),
+        None => None,
+    };
+    match &args[0] {
+        Object::Array(array) => {
+            let len = array.elements.len();
+            let start = clamp_index(start, len);
+            let end = end.map_or(len, |end| clamp_index(end, len));
+            Object::Array(Array {
+                elements: array.elements[start..end.max(start)].to_vec(),
+            })
+        }
+        Object::String(string) => Object::String(string::slice_chars(string, start, end)),
+        arg => new_error(type_error("slice", "ARRAY or STRING", arg)),
+    }
+}
+
+// slice(xs, start) と slice(xs, start, end) の添字。負の index は末尾から数え、範囲外は端に寄せる
+fn clamp_index(index: i64, len: usize) -> usize {
+    if index < 0 {
+        len.saturating_sub(index.unsigned_abs() as usize)
+    } else {
+        (index as usize).min(len)
+    }
+}
+
+fn concat(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let left = try_object!(array_arg("concat", &args[0]));
+    let right = try_object!(array_arg("concat", &args[1]));
+    Object::Array(Array {
+        elements: [left, right].concat(),
+    })
+}
+
+fn contains(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let elements = try_object!(array_arg("contains", &args[0]));
+    Object::Boolean(elements.iter().any(|element| equals(element, &args[1])))
+}
+
+// 見つからなければ null
+fn index_of(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let elements = try_object!(array_arg("index_of", &args[0]));
+    match elements
+        .iter()
+        .position(|element| equals(element, &args[1]))
+    {
+        Some(index) => Object::Integer(index as i64),
+        None => NULL,
+    }
+}
+
+// 要素は str と同じ表示でつなぐ。区切りを省くと "" になる
+fn join(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 2));
+    let elements = try_object!(array_arg("join", &args[0]));
+    let separator = match args.get(1) {
+        Some(Object::String(separator)) => separator.as_str(),
+        Some(arg) => return new_error(type_error("join", "STRING", arg)),
+        None => "",
+    };
+    Object::String(
+        elements
+            .iter()
+            .map(|element| match element {
+                Object::String(string) => string.clone(),
+                _ => element.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(separator),
+    )
+}
+
+// range(end), range(start, end), range(start, end, step)。end は含まない
+fn range(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 3));
+    let mut bounds = vec![];
+    for arg in &args {
+        bounds.push(try_object!(integer_arg("range", arg)));
+    }
+    let (start, end, step) = match bounds[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step),
+        _ => unreachable!(),
+    };
+    if step == 0 {
+        return new_error("range step must not be zero".to_string());
+    }
+
+    // i64 の端でもあふれないように i128 で数える
+    let (start, end, step) = (start as i128, end as i128, step as i128);
+    let length = if (step > 0 && start < end) || (step < 0 && start > end) {
+        (end - start + step - step.signum()) / step
+    } else {
+        0
+    };
+    if length > MAX_RANGE_LENGTH {
+        return new_error(format!(
+            "range too long: {} elements, max {}",
+            length, MAX_RANGE_LENGTH
+        ));
+    }
+
+    let elements = (0..length)
+        .map(|i| Object::Integer((start + i * step) as i64))
+        .collect();
+    Object::Array(Array { elements })
+}
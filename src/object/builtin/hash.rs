@@ -0,0 +1,78 @@
+// ハッシュの builtin。VM と evaluator で共通に使う
+// 書き換えるものも引数のハッシュはそのままにして、新しいハッシュを返す
+use super::super::hash::hash_key_of;
+use super::super::{Array, Hash, Object};
+use super::{check_arity, new_error, type_error, Caller, NULL};
+
+fn hash_arg<'a>(name: &str, arg: &'a Object) -> Result<&'a Hash, String> {
+    match arg {
+        Object::Hash(hash) => Ok(hash),
+        _ => Err(type_error(name, "HASH", arg)),
+    }
+}
+
+fn array(elements: Vec<Object>) -> Object {
+    Object::Array(Array { elements })
+}
+
+pub fn keys(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let hash = try_object!(hash_arg("keys", &args[0]));
+    array(hash.pairs().map(|pair| pair.key.clone()).collect())
+}
+
+pub fn values(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let hash = try_object!(hash_arg("values", &args[0]));
+    array(hash.pairs().map(|pair| pair.value.clone()).collect())
+}
+
+// [[key, value], ...]
+pub fn items(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let hash = try_object!(hash_arg("items", &args[0]));
+    array(
+        hash.pairs()
+            .map(|pair| array(vec![pair.key.clone(), pair.value.clone()]))
+            .collect(),
+    )
+}
+
+pub fn has_key(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let hash = try_object!(hash_arg("has_key", &args[0]));
+    let key = try_object!(hash_key_of(&args[1]));
+    Object::Boolean(hash.get(&key).is_some())
+}
+
+// get(h, key) は h[key] と同じ。get(h, key, default) は key がなければ default を返す
+pub fn get(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 3));
+    let hash = try_object!(hash_arg("get", &args[0]));
+    let key = try_object!(hash_key_of(&args[1]));
+    match hash.get(&key) {
+        Some(pair) => pair.value.clone(),
+        None => args.get(2).cloned().unwrap_or(NULL),
+    }
+}
+
+// key がなければそのまま返す
+pub fn delete(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let mut hash = try_object!(hash_arg("delete", &args[0])).clone();
+    let key = try_object!(hash_key_of(&args[1]));
+    hash.remove(&key);
+    Object::Hash(hash)
+}
+
+// 同じ key は後ろのハッシュの値で置き換える。順番は先に出てきた位置のまま
+pub fn merge(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let mut merged = try_object!(hash_arg("merge", &args[0])).clone();
+    let other = try_object!(hash_arg("merge", &args[1]));
+    for pair in other.pairs() {
+        let key = try_object!(hash_key_of(&pair.key));
+        merged.insert(key, pair.clone());
+    }
+    Object::Hash(merged)
+}
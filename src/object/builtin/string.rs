@@ -0,0 +1,127 @@
+// 文字列の builtin と添字。VM と evaluator で共通に使う
+// 添字や長さはすべて byte ではなく文字 (char) で数える
+use super::super::{Array, Object};
+use super::{check_arity, clamp_index, new_error, type_error, Caller, NULL};
+
+fn string_arg<'a>(name: &str, arg: &'a Object) -> Result<&'a str, String> {
+    match arg {
+        Object::String(string) => Ok(string),
+        _ => Err(type_error(name, "STRING", arg)),
+    }
+}
+
+// s[i]。範囲外は配列と同じく null
+pub fn char_at(string: &str, index: i64) -> Object {
+    if index < 0 {
+        return NULL;
+    }
+    match string.chars().nth(index as usize) {
+        Some(ch) => Object::String(ch.to_string()),
+        None => NULL,
+    }
+}
+
+pub fn char_len(string: &str) -> usize {
+    string.chars().count()
+}
+
+// slice(s, start, end) の中身。添字の扱いは配列の slice と同じ
+pub fn slice_chars(string: &str, start: i64, end: Option<i64>) -> String {
+    let len = char_len(string);
+    let start = clamp_index(start, len);
+    let end = end.map_or(len, |end| clamp_index(end, len));
+    string
+        .chars()
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect()
+}
+
+fn strings(elements: Vec<String>) -> Object {
+    Object::Array(Array {
+        elements: elements.into_iter().map(Object::String).collect(),
+    })
+}
+
+// split(s) は空白で、split(s, sep) は sep で区切る。sep が "" なら 1 文字ずつにする
+pub fn split(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 2));
+    let string = try_object!(string_arg("split", &args[0]));
+    let parts = match args.get(1) {
+        None => string.split_whitespace().map(String::from).collect(),
+        Some(arg) => match try_object!(string_arg("split", arg)) {
+            "" => string.chars().map(String::from).collect(),
+            separator => string.split(separator).map(String::from).collect(),
+        },
+    };
+    strings(parts)
+}
+
+pub fn trim(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let string = try_object!(string_arg("trim", &args[0]));
+    Object::String(string.trim().to_string())
+}
+
+pub fn upper(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let string = try_object!(string_arg("upper", &args[0]));
+    Object::String(string.to_uppercase())
+}
+
+pub fn lower(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let string = try_object!(string_arg("lower", &args[0]));
+    Object::String(string.to_lowercase())
+}
+
+// すべて置き換える
+pub fn replace(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 3, 3));
+    let string = try_object!(string_arg("replace", &args[0]));
+    let from = try_object!(string_arg("replace", &args[1]));
+    let to = try_object!(string_arg("replace", &args[2]));
+    if from.is_empty() {
+        return new_error("cannot replace an empty string".to_string());
+    }
+    Object::String(string.replace(from, to))
+}
+
+pub fn starts_with(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let string = try_object!(string_arg("starts_with", &args[0]));
+    let prefix = try_object!(string_arg("starts_with", &args[1]));
+    Object::Boolean(string.starts_with(prefix))
+}
+
+pub fn ends_with(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 2, 2));
+    let string = try_object!(string_arg("ends_with", &args[0]));
+    let suffix = try_object!(string_arg("ends_with", &args[1]));
+    Object::Boolean(string.ends_with(suffix))
+}
+
+pub fn chars(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 1));
+    let string = try_object!(string_arg("chars", &args[0]));
+    strings(string.chars().map(String::from).collect())
+}
+
+// int と違って、数として読めないときはエラーではなく null を返す
+// parse_int(s, base) の base は 2 から 36
+pub fn parse_int(_: &mut dyn Caller, args: Vec<Object>) -> Object {
+    try_object!(check_arity(&args, 1, 2));
+    let string = try_object!(string_arg("parse_int", &args[0]));
+    let base = match args.get(1) {
+        Some(Object::Integer(base)) if (2..=36).contains(base) => *base as u32,
+        Some(Object::Integer(base)) => {
+            return new_error(format!("parse_int base must be 2 to 36, got {}", base))
+        }
+        Some(arg) => return new_error(type_error("parse_int", "INTEGER", arg)),
+        None => 10,
+    };
+    match i64::from_str_radix(string.trim(), base) {
+        Ok(integer) => Object::Integer(integer),
+        Err(_) => NULL,
+    }
+}
@@ -1,17 +1,20 @@
 use super::ast::{
-    ArrayLiteral, BlockStatement, Boolean, CallExpression, Expression, ExpressionStatement,
+    ArrayLiteral, AssignExpression, BlockStatement, Boolean, BreakStatement, CallExpression,
+    ContinueStatement, Expression, ExpressionStatement, FloatLiteral, ForStatement,
     FunctionLiteral, HashLiteral, Identifier, IfExpression, IndexExpression, InfixExpression,
-    IntegerLiteral, LetStatement, MacroLiteral, PrefixExpression, Program, ReturnStatement,
-    Statement, StringLiteral,
+    IntegerLiteral, InterpolatedString, LetStatement, MacroLiteral, PrefixExpression, Program,
+    ReturnStatement, Statement, StringLiteral, WhileStatement,
 };
-use super::lexer::Lexer;
-use super::token::{Token, TokenType};
-use std::collections::BTreeMap;
+use super::lexer::{DocComment, Lexer};
+use super::token::{Position, Token, TokenType};
 use std::fmt;
 
 #[derive(PartialEq, PartialOrd)]
 enum Precedence {
     Lowest,
+    Assign,      // = OR +=
+    LogicalOr,   // ||
+    LogicalAnd,  // &&
     Equals,      // ==
     LessGreater, // > OR <
     Sum,         // +
@@ -22,8 +25,9 @@ enum Precedence {
 }
 
 #[derive(Debug)]
-struct ParseError {
-    message: String,
+pub struct ParseError {
+    pub message: String,
+    pub pos: Position,
 }
 
 impl fmt::Display for ParseError {
@@ -39,6 +43,8 @@ pub struct Parser {
     _cur_token: Option<Token>,
     _peek_token: Option<Token>,
     errors: Vec<ParseError>,
+    // 読み終えた最後のトークンの直後の位置
+    last_end: Position,
 }
 
 impl Parser {
@@ -51,6 +57,7 @@ impl Parser {
             _cur_token,
             _peek_token,
             errors: vec![],
+            last_end: Position::default(),
         }
     }
 
@@ -64,27 +71,63 @@ impl Parser {
             }
             self.next_token();
         }
+
+        // 字句エラーも構文エラーと一緒に位置順で返す。同じ位置なら原因になる字句エラーを先にする
+        let mut errors: Vec<ParseError> = self
+            .l
+            .errors()
+            .iter()
+            .map(|error| ParseError {
+                message: error.message.clone(),
+                pos: error.pos,
+            })
+            .collect();
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|error| error.pos);
+        self.errors = errors;
+
         Program { statements }
     }
 
-    pub fn errors(&self) -> std::vec::Vec<String> {
-        self.errors.iter().map(|e| format!("{}", e)).collect()
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<ParseError> {
+        self.errors
+    }
+
+    // 読み飛ばした doc comment。parse_program の後ならソース全体の分がそろっている
+    #[allow(dead_code)]
+    pub fn doc_comments(&self) -> &[DocComment] {
+        self.l.doc_comments()
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         Ok(match self.cur_token().t {
             TokenType::Let => Statement::LetStatement(self.parse_let_statement()?),
             TokenType::Return => Statement::ReturnStatement(self.parse_return_statement()?),
+            TokenType::While => Statement::WhileStatement(self.parse_while_statement()?),
+            TokenType::For => Statement::ForStatement(self.parse_for_statement()?),
+            TokenType::Break => {
+                let pos = self.cur_token().pos;
+                self.skip_semicolon();
+                Statement::BreakStatement(BreakStatement { pos })
+            }
+            TokenType::Continue => {
+                let pos = self.cur_token().pos;
+                self.skip_semicolon();
+                Statement::ContinueStatement(ContinueStatement { pos })
+            }
             _ => Statement::ExpressionStatement(self.parse_expression_statement()?),
         })
     }
 
     fn parse_let_statement(&mut self) -> Result<LetStatement, ParseError> {
+        let pos = self.cur_token().pos;
         self.expect_peek(&TokenType::Ident);
 
-        let name = Identifier {
-            value: self.cur_token().literal.clone(),
-        };
+        let name = self.parse_identifier();
 
         self.expect_peek(&TokenType::Assign);
 
@@ -96,10 +139,11 @@ impl Parser {
             self.next_token();
         }
 
-        Ok(LetStatement { name, value })
+        Ok(LetStatement { name, value, pos })
     }
 
     fn parse_return_statement(&mut self) -> Result<ReturnStatement, ParseError> {
+        let pos = self.cur_token().pos;
         self.next_token();
 
         let return_value = self.parse_expression(Precedence::Lowest)?;
@@ -108,17 +152,96 @@ impl Parser {
             self.next_token();
         }
 
-        Ok(ReturnStatement { return_value })
+        Ok(ReturnStatement { return_value, pos })
+    }
+
+    fn parse_while_statement(&mut self) -> Result<WhileStatement, ParseError> {
+        let pos = self.cur_token().pos;
+        if !self.expect_peek(&TokenType::LParen) {
+            return Err(self.error_at_peek("Missing LParen"));
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(&TokenType::RParen) {
+            return Err(self.error_at_peek("Missing RParen"));
+        }
+        if !self.expect_peek(&TokenType::LBrace) {
+            return Err(self.error_at_peek("Missing LBrace"));
+        }
+
+        let body = self.parse_block_statement()?;
+        self.skip_semicolon();
+
+        Ok(WhileStatement {
+            condition,
+            body,
+            pos,
+        })
+    }
+
+    fn parse_for_statement(&mut self) -> Result<ForStatement, ParseError> {
+        let pos = self.cur_token().pos;
+        if !self.expect_peek(&TokenType::LParen) {
+            return Err(self.error_at_peek("Missing LParen"));
+        }
+        if !self.expect_peek(&TokenType::Ident) {
+            return Err(self.error_at_peek("Missing loop variable"));
+        }
+        let key = self.parse_identifier();
+
+        let value = if self.peek_token_is(&TokenType::Comma) {
+            self.next_token();
+            if !self.expect_peek(&TokenType::Ident) {
+                return Err(self.error_at_peek("Missing loop variable"));
+            }
+            Some(self.parse_identifier())
+        } else {
+            None
+        };
+
+        if !self.expect_peek(&TokenType::In) {
+            return Err(self.error_at_peek("Missing In"));
+        }
+
+        self.next_token();
+        let iterable = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(&TokenType::RParen) {
+            return Err(self.error_at_peek("Missing RParen"));
+        }
+        if !self.expect_peek(&TokenType::LBrace) {
+            return Err(self.error_at_peek("Missing LBrace"));
+        }
+
+        let body = self.parse_block_statement()?;
+        self.skip_semicolon();
+
+        Ok(ForStatement {
+            key,
+            value,
+            iterable,
+            body,
+            pos,
+        })
+    }
+
+    fn skip_semicolon(&mut self) {
+        if self.peek_token_is(&TokenType::Semicolon) {
+            self.next_token();
+        }
     }
 
     fn parse_expression_statement(&mut self) -> Result<ExpressionStatement, ParseError> {
+        let pos = self.cur_token().pos;
         let expression = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_token_is(&TokenType::Semicolon) {
             self.next_token()
         }
 
-        Ok(ExpressionStatement { expression })
+        Ok(ExpressionStatement { expression, pos })
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> Result<Expression, ParseError> {
@@ -131,13 +254,27 @@ impl Parser {
                     | TokenType::Minus
                     | TokenType::Slash
                     | TokenType::Asterisk
+                    | TokenType::Percent
                     | TokenType::Eq
                     | TokenType::NotEq
                     | TokenType::Lt
-                    | TokenType::Gt => {
+                    | TokenType::Gt
+                    | TokenType::LtEq
+                    | TokenType::GtEq
+                    | TokenType::And
+                    | TokenType::Or => {
                         self.next_token();
                         Expression::InfixExpression(self.parse_infix_expression(left_exp)?)
                     }
+                    TokenType::Assign
+                    | TokenType::PlusAssign
+                    | TokenType::MinusAssign
+                    | TokenType::AsteriskAssign
+                    | TokenType::SlashAssign
+                    | TokenType::PercentAssign => {
+                        self.next_token();
+                        Expression::AssignExpression(self.parse_assign_expression(left_exp)?)
+                    }
                     TokenType::LBracket => {
                         self.next_token();
                         Expression::IndexExpression(self.parse_index_expression(left_exp)?)
@@ -157,9 +294,14 @@ impl Parser {
     }
 
     fn parse_prefix(&mut self) -> Result<Expression, ParseError> {
-        Ok(match self.cur_token().t {
+        let t = match &self._cur_token {
+            Some(token) => &token.t,
+            None => return Err(self.error_at_peek("unexpected end of input")),
+        };
+        Ok(match t {
             TokenType::Ident => Expression::Identifier(self.parse_identifier()),
             TokenType::Int => Expression::IntegerLiteral(self.parse_integer_literal()?),
+            TokenType::Float => Expression::FloatLiteral(self.parse_float_literal()?),
             TokenType::Bang | TokenType::Minus => {
                 Expression::PrefixExpression(self.parse_prefix_expression()?)
             }
@@ -168,12 +310,14 @@ impl Parser {
             TokenType::If => Expression::IfExpression(self.parse_if_expression()?),
             TokenType::Function => Expression::FunctionLiteral(self.parse_function_literal()?),
             TokenType::String => Expression::StringLiteral(self.parse_string_literal()?),
+            TokenType::StringHead => self.parse_interpolated_string()?,
             TokenType::LBracket => Expression::ArrayLiteral(self.parse_array_literal()?),
             TokenType::LBrace => Expression::HashLiteral(self.parse_hash_literal()?),
             TokenType::Macro => Expression::MacroLiteral(self.parse_macro_literal()?),
             _ => {
                 return Err(ParseError {
-                    message: String::from("not implemented"),
+                    message: format!("no prefix parse function for {:?} found", t),
+                    pos: self.cur_token().pos,
                 })
             }
         })
@@ -181,6 +325,7 @@ impl Parser {
 
     fn parse_infix_expression(&mut self, left: Expression) -> Result<InfixExpression, ParseError> {
         let operator = self.cur_token().literal.clone();
+        let pos = self.cur_token().pos;
         let precedence = self.cur_precedence();
         self.next_token();
         let right = self.parse_expression(precedence)?;
@@ -188,20 +333,51 @@ impl Parser {
             left: Box::new(left),
             operator,
             right: Box::new(right),
+            pos,
+        })
+    }
+
+    fn parse_assign_expression(
+        &mut self,
+        target: Expression,
+    ) -> Result<AssignExpression, ParseError> {
+        let operator = self.cur_token().literal.clone();
+        let pos = self.cur_token().pos;
+
+        // xs[0][1] = v のように index をたどった先が識別子でないと代入できない
+        let mut base = &target;
+        while let Expression::IndexExpression(exp) = base {
+            base = &exp.left;
+        }
+        if !matches!(base, Expression::Identifier(_)) {
+            return Err(ParseError {
+                message: format!("cannot assign to {}", target),
+                pos,
+            });
+        }
+
+        self.next_token();
+        // 右結合にするため、右辺は Lowest から読む
+        let value = self.parse_expression(Precedence::Lowest)?;
+        Ok(AssignExpression {
+            target: Box::new(target),
+            operator,
+            value: Box::new(value),
+            pos,
         })
     }
 
     fn parse_index_expression(&mut self, left: Expression) -> Result<IndexExpression, ParseError> {
+        let pos = self.cur_token().pos;
         self.next_token();
         let index = self.parse_expression(Precedence::Lowest)?;
         if !self.expect_peek(&TokenType::RBracket) {
-            Err(ParseError {
-                message: String::from("Missing RBracket"),
-            })
+            Err(self.error_at_peek("Missing RBracket"))
         } else {
             Ok(IndexExpression {
                 left: Box::new(left),
                 index: Box::new(index),
+                pos,
             })
         }
     }
@@ -209,20 +385,39 @@ impl Parser {
     fn parse_identifier(&self) -> Identifier {
         Identifier {
             value: self.cur_token().literal.clone(),
+            pos: self.cur_token().pos,
         }
     }
 
     fn parse_integer_literal(&self) -> Result<IntegerLiteral, ParseError> {
         match self.cur_token().literal.parse() {
-            Ok(value) => Ok(IntegerLiteral { value }),
+            Ok(value) => Ok(IntegerLiteral {
+                value,
+                pos: self.cur_token().pos,
+            }),
             Err(_) => Err(ParseError {
                 message: format!("could not parse {} as integer", self.cur_token().literal),
+                pos: self.cur_token().pos,
+            }),
+        }
+    }
+
+    fn parse_float_literal(&self) -> Result<FloatLiteral, ParseError> {
+        match self.cur_token().literal.parse() {
+            Ok(value) => Ok(FloatLiteral {
+                value,
+                pos: self.cur_token().pos,
+            }),
+            Err(_) => Err(ParseError {
+                message: format!("could not parse {} as float", self.cur_token().literal),
+                pos: self.cur_token().pos,
             }),
         }
     }
 
     fn parse_prefix_expression(&mut self) -> Result<PrefixExpression, ParseError> {
         let operator = self.cur_token().literal.clone();
+        let pos = self.cur_token().pos;
 
         self.next_token();
 
@@ -231,12 +426,14 @@ impl Parser {
         Ok(PrefixExpression {
             operator,
             right: Box::new(right),
+            pos,
         })
     }
 
     fn parse_boolean(&mut self) -> Boolean {
         Boolean {
             value: self.cur_token_is(&TokenType::True),
+            pos: self.cur_token().pos,
         }
     }
 
@@ -245,33 +442,26 @@ impl Parser {
 
         let exp = self.parse_expression(Precedence::Lowest)?;
         if !self.expect_peek(&TokenType::RParen) {
-            Err(ParseError {
-                message: String::from("Missing RParen"),
-            })
+            Err(self.error_at_peek("Missing RParen"))
         } else {
             Ok(exp)
         }
     }
 
     fn parse_if_expression(&mut self) -> Result<IfExpression, ParseError> {
+        let pos = self.cur_token().pos;
         if !self.expect_peek(&TokenType::LParen) {
-            return Err(ParseError {
-                message: String::from("Missing LParen"),
-            });
+            return Err(self.error_at_peek("Missing LParen"));
         }
 
         self.next_token();
         let condition = self.parse_expression(Precedence::Lowest)?;
 
         if !self.expect_peek(&TokenType::RParen) {
-            return Err(ParseError {
-                message: String::from("Missing RParen"),
-            });
+            return Err(self.error_at_peek("Missing RParen"));
         }
         if !self.expect_peek(&TokenType::LBrace) {
-            return Err(ParseError {
-                message: String::from("Missing LBrace"),
-            });
+            return Err(self.error_at_peek("Missing LBrace"));
         }
 
         let consequence = self.parse_block_statement()?;
@@ -279,9 +469,7 @@ impl Parser {
         let alternative = if self.peek_token_is(&TokenType::Else) {
             self.next_token();
             if !self.expect_peek(&TokenType::LBrace) {
-                return Err(ParseError {
-                    message: String::from("Missing LBrace"),
-                });
+                return Err(self.error_at_peek("Missing LBrace"));
             }
             Some(Box::new(self.parse_block_statement()?))
         } else {
@@ -292,6 +480,7 @@ impl Parser {
             condition: Box::new(condition),
             consequence: Box::new(consequence),
             alternative,
+            pos,
         })
     }
 
@@ -309,18 +498,15 @@ impl Parser {
     }
 
     fn parse_function_literal(&mut self) -> Result<FunctionLiteral, ParseError> {
+        let pos = self.cur_token().pos;
         if !self.expect_peek(&TokenType::LParen) {
-            return Err(ParseError {
-                message: String::from("Missing LParen"),
-            });
+            return Err(self.error_at_peek("Missing LParen"));
         }
 
         let parameters = self.parse_function_parameters()?;
 
         if !self.expect_peek(&TokenType::LBrace) {
-            return Err(ParseError {
-                message: String::from("Missing LBrace"),
-            });
+            return Err(self.error_at_peek("Missing LBrace"));
         }
 
         let body = self.parse_block_statement()?;
@@ -328,6 +514,7 @@ impl Parser {
         Ok(FunctionLiteral {
             parameters,
             body: Box::new(body),
+            pos,
         })
     }
 
@@ -341,22 +528,16 @@ impl Parser {
 
         self.next_token();
 
-        identifiers.push(Identifier {
-            value: self.cur_token().literal.clone(),
-        });
+        identifiers.push(self.parse_identifier());
 
         while self.peek_token_is(&TokenType::Comma) {
             self.next_token();
             self.next_token();
-            identifiers.push(Identifier {
-                value: self.cur_token().literal.clone(),
-            });
+            identifiers.push(self.parse_identifier());
         }
 
         if !self.expect_peek(&TokenType::RParen) {
-            return Err(ParseError {
-                message: String::from("Missing RParen"),
-            });
+            return Err(self.error_at_peek("Missing RParen"));
         }
 
         Ok(identifiers)
@@ -366,10 +547,12 @@ impl Parser {
         &mut self,
         function: Expression,
     ) -> Result<CallExpression, ParseError> {
+        let pos = self.cur_token().pos;
         let arguments = self.parse_expression_list(TokenType::RParen)?;
         Ok(CallExpression {
             function: Box::new(function),
             arguments,
+            pos,
         })
     }
 
@@ -391,9 +574,7 @@ impl Parser {
         }
 
         if !self.expect_peek(&end) {
-            return Err(ParseError {
-                message: String::from("Missing RParen"),
-            });
+            return Err(self.error_at_peek("Missing RParen"));
         }
 
         Ok(args)
@@ -402,61 +583,85 @@ impl Parser {
     fn parse_string_literal(&self) -> Result<StringLiteral, ParseError> {
         Ok(StringLiteral {
             value: self.cur_token().literal.clone(),
+            pos: self.cur_token().pos,
         })
     }
 
+    // "a ${x} b" を文字列の断片 "a ", " b" と式 x に分ける
+    fn parse_interpolated_string(&mut self) -> Result<Expression, ParseError> {
+        let pos = self.cur_token().pos;
+        let mut parts = vec![];
+        loop {
+            let text = self.parse_string_literal()?;
+            if !text.value.is_empty() {
+                parts.push(Expression::StringLiteral(text));
+            }
+            if self.cur_token_is(&TokenType::String) || self.cur_token_is(&TokenType::StringTail) {
+                break;
+            }
+
+            self.next_token();
+            parts.push(self.parse_expression(Precedence::Lowest)?);
+
+            if !self.peek_token_is(&TokenType::StringMiddle)
+                && !self.peek_token_is(&TokenType::StringTail)
+            {
+                return Err(self.error_at_peek("Missing RBrace"));
+            }
+            self.next_token();
+        }
+
+        Ok(Expression::InterpolatedString(InterpolatedString {
+            parts,
+            pos,
+        }))
+    }
+
     fn parse_array_literal(&mut self) -> Result<ArrayLiteral, ParseError> {
+        let pos = self.cur_token().pos;
         let elements = self.parse_expression_list(TokenType::RBracket)?;
-        Ok(ArrayLiteral { elements })
+        Ok(ArrayLiteral { elements, pos })
     }
 
     fn parse_hash_literal(&mut self) -> Result<HashLiteral, ParseError> {
-        let mut pairs = BTreeMap::new();
+        let pos = self.cur_token().pos;
+        let mut pairs = vec![];
 
         while !self.peek_token_is(&TokenType::RBrace) {
             self.next_token();
             let key = self.parse_expression(Precedence::Lowest)?;
 
             if !self.expect_peek(&TokenType::Colon) {
-                return Err(ParseError {
-                    message: String::from("Missing Colon"),
-                });
+                return Err(self.error_at_peek("Missing Colon"));
             }
 
             self.next_token();
             let value = self.parse_expression(Precedence::Lowest)?;
 
-            pairs.insert(key, value);
+            pairs.push((key, value));
 
             if !self.peek_token_is(&TokenType::RBrace) && !self.expect_peek(&TokenType::Comma) {
-                return Err(ParseError {
-                    message: String::from("Missing RBrace or Comma"),
-                });
+                return Err(self.error_at_peek("Missing RBrace or Comma"));
             }
         }
 
         if !self.expect_peek(&TokenType::RBrace) {
-            return Err(ParseError {
-                message: String::from("Missing RBrace"),
-            });
+            return Err(self.error_at_peek("Missing RBrace"));
         }
 
-        Ok(HashLiteral { pairs })
+        Ok(HashLiteral { pairs, pos })
     }
 
     fn parse_macro_literal(&mut self) -> Result<MacroLiteral, ParseError> {
+        let pos = self.cur_token().pos;
         if !self.expect_peek(&TokenType::LParen) {
-            return Err(ParseError {
-                message: String::from("Missing LParen"),
-            });
+            return Err(self.error_at_peek("Missing LParen"));
         }
 
         let parameters = self.parse_function_parameters()?;
 
         if !self.expect_peek(&TokenType::LBrace) {
-            return Err(ParseError {
-                message: String::from("Missing LBrace"),
-            });
+            return Err(self.error_at_peek("Missing LBrace"));
         }
 
         let body = self.parse_block_statement()?;
@@ -464,9 +669,13 @@ impl Parser {
         Ok(MacroLiteral {
             parameters,
             body: Box::new(body),
+            pos,
         })
     }
     fn next_token(&mut self) {
+        if let Some(cur) = &self._cur_token {
+            self.last_end = cur.end;
+        }
         self._cur_token = self._peek_token.take();
         self._peek_token = self.l.next();
     }
@@ -492,17 +701,34 @@ impl Parser {
             self.next_token();
             true
         } else {
-            self.errors.push(ParseError {
-                message: format!(
+            let message = match self.peek_token() {
+                Some(peek) => format!(
                     "expected next token to be {:?}, got {:?} instead",
-                    t,
-                    self.peek_token().unwrap().t
+                    t, peek.t
                 ),
-            });
+                None => format!("expected next token to be {:?}, got end of input", t),
+            };
+            self.errors.push(self.error_at_peek(&message));
             false
         }
     }
 
+    // 次のトークンの位置。入力の終わりでは最後のトークンの直後を指す
+    fn peek_pos(&self) -> Position {
+        match (self.peek_token(), &self._cur_token) {
+            (Some(peek), _) => peek.pos,
+            (None, Some(cur)) => cur.end,
+            (None, None) => self.last_end,
+        }
+    }
+
+    fn error_at_peek(&self, message: &str) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            pos: self.peek_pos(),
+        }
+    }
+
     fn cur_precedence(&self) -> Precedence {
         Parser::precedence(&self.cur_token().t)
     }
@@ -517,14 +743,25 @@ impl Parser {
 
     fn precedence(t: &TokenType) -> Precedence {
         match t {
+            TokenType::Assign
+            | TokenType::PlusAssign
+            | TokenType::MinusAssign
+            | TokenType::AsteriskAssign
+            | TokenType::SlashAssign
+            | TokenType::PercentAssign => Precedence::Assign,
+            TokenType::Or => Precedence::LogicalOr,
+            TokenType::And => Precedence::LogicalAnd,
             TokenType::Eq => Precedence::Equals,
             TokenType::NotEq => Precedence::Equals,
             TokenType::Lt => Precedence::LessGreater,
             TokenType::Gt => Precedence::LessGreater,
+            TokenType::LtEq => Precedence::LessGreater,
+            TokenType::GtEq => Precedence::LessGreater,
             TokenType::Plus => Precedence::Sum,
             TokenType::Minus => Precedence::Sum,
             TokenType::Slash => Precedence::Product,
             TokenType::Asterisk => Precedence::Product,
+            TokenType::Percent => Precedence::Product,
             TokenType::LParen => Precedence::Call,
             TokenType::LBracket => Precedence::Index,
             _ => Precedence::Lowest,
@@ -534,8 +771,8 @@ impl Parser {
 
 #[cfg(test)]
 mod tests {
-    use super::super::ast::{Expression, Statement};
-    use super::{Lexer, Parser};
+    use super::super::ast::{Expression, ExpressionStatement, Statement};
+    use super::{Lexer, Parser, Position};
     use std::collections::HashMap;
     use std::vec::Vec;
 
@@ -635,6 +872,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_float_literal_expressions() {
+        let tests = vec![("5.25;", 5.25), ("0.5", 0.5), ("10.0", 10.0)];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parse_errors(p);
+
+            match &program.statements[0] {
+                Statement::ExpressionStatement(ExpressionStatement {
+                    expression: Expression::FloatLiteral(literal),
+                    ..
+                }) => {
+                    assert_eq!(literal.value, expected);
+                    assert_eq!(literal.to_string(), format!("{:?}", expected));
+                }
+                other => assert!(false, "not ast::FloatLiteral: {:?}", other),
+            }
+        }
+    }
+
     #[test]
     fn test_parsing_prefix_expressions() {
         test(vec![("!5;", "!", 5), ("-15;", "-", 15)]);
@@ -676,12 +936,17 @@ mod tests {
             ("5 < 5;", 5, "<", 5),
             ("5 == 5;", 5, "==", 5),
             ("5 != 5;", 5, "!=", 5),
+            ("5 <= 5;", 5, "<=", 5),
+            ("5 >= 5;", 5, ">=", 5),
+            ("5 % 5;", 5, "%", 5),
         ]);
 
         test(vec![
             ("true == true", true, "==", true),
             ("true != false", true, "!=", false),
             ("false == false", false, "==", false),
+            ("true && false", true, "&&", false),
+            ("false || true", false, "||", true),
         ]);
 
         fn test<T: AssertWithExpression, U: AssertWithExpression>(tests: Vec<(&str, T, &str, U)>) {
@@ -723,6 +988,14 @@ mod tests {
             ("3 + 4; -5 * 5", "(3 + 4)((-5) * 5)"),
             ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
             ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+            ("5 <= 4 == 3 >= 4", "((5 <= 4) == (3 >= 4))"),
+            ("a + b % c * d", "(a + ((b % c) * d))"),
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b || c && d", "((a && b) || (c && d))"),
+            ("a == b && c != d", "((a == b) && (c != d))"),
+            ("!a && b", "((!a) && b)"),
+            ("x = y = a + b", "(x = (y = (a + b)))"),
+            ("xs[i][j] += a || b", "(((xs[i])[j]) += (a || b))"),
             (
                 "3 + 4 * 5 == 3 * 1 + 4 * 5",
                 "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))",
@@ -966,6 +1239,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_string_interpolation() {
+        let tests = [
+            (r#""a ${x} b""#, r#""a ${x} b""#),
+            (r#""${x}""#, r#""${x}""#),
+            (r#""${x}${y}!""#, r#""${x}${y}!""#),
+            (r#""${a + b} = ${"${c}"}""#, r#""${(a + b)} = ${"${c}"}""#),
+            (r#""${ {"k": 1}["k"] }""#, r#""${({"k": 1}["k"])}""#),
+            (r#""n: ${n}" + "!""#, r#"("n: ${n}" + "!")"#),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parse_errors(p);
+            assert_eq!(program.to_string(), *expected);
+        }
+    }
+
     #[test]
     fn test_parsing_array_literals() {
         let input = "[1, 2 * 2, 3 + 3]";
@@ -1147,6 +1440,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_while_statement() {
+        let input = "while (x < y) { x; break; continue; }";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parse_errors(p);
+        assert_eq!(program.statements.len(), 1);
+
+        if let Statement::WhileStatement(stmt) = &program.statements[0] {
+            assert_infix_expression(&stmt.condition, "x".to_string(), "<", "y".to_string());
+            assert_eq!(stmt.body.statements.len(), 3);
+            assert!(matches!(
+                stmt.body.statements[1],
+                Statement::BreakStatement(_)
+            ));
+            assert!(matches!(
+                stmt.body.statements[2],
+                Statement::ContinueStatement(_)
+            ));
+        } else {
+            assert!(false, "program.statements[0] is not ast::WhileStatement")
+        }
+    }
+
+    #[test]
+    fn test_for_statement() {
+        let tests = vec![
+            ("for (x in xs) { x }", "x", None),
+            ("for (k, v in {}) { k };", "k", Some("v")),
+        ];
+
+        for (input, expected_key, expected_value) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parse_errors(p);
+            assert_eq!(program.statements.len(), 1);
+
+            if let Statement::ForStatement(stmt) = &program.statements[0] {
+                assert_eq!(stmt.key.value, expected_key);
+                assert_eq!(
+                    stmt.value.as_ref().map(|v| v.value.as_str()),
+                    expected_value
+                );
+                assert_eq!(stmt.body.statements.len(), 1);
+            } else {
+                assert!(false, "program.statements[0] is not ast::ForStatement")
+            }
+        }
+    }
+
     #[test]
     fn test_macro_literal_parsing() {
         let input = "macro(x, y) { x + y; }";
@@ -1187,6 +1533,76 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_error_positions() {
+        let tests = vec![
+            (
+                "let x 5;",
+                "expected next token to be Assign, got Int instead",
+                (1, 7),
+            ),
+            (
+                "let a = 1;\nlet b = (1 + 2",
+                "expected next token to be RParen, got end of input",
+                (2, 15),
+            ),
+            (
+                "1 +\n  }",
+                "no prefix parse function for RBrace found",
+                (2, 3),
+            ),
+            ("f(x) = 1", "cannot assign to f(x)", (1, 6)),
+            ("a + b -= 1", "cannot assign to (a + b)", (1, 7)),
+            (
+                "for (x of xs) {}",
+                "expected next token to be In, got Ident instead",
+                (1, 8),
+            ),
+            ("let s = \"abc", "unterminated string", (1, 9)),
+            (
+                "1 +; \"\\q\"",
+                "no prefix parse function for Semicolon found",
+                (1, 4),
+            ),
+            ("\"a${x\"", "unterminated string", (1, 6)),
+            ("\"a ${x y}\"", "Missing RBrace", (1, 8)),
+        ];
+
+        for (input, expected_message, (line, column)) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            let err = &p.errors()[0];
+            assert_eq!(err.message, expected_message);
+            assert_eq!(err.pos, Position::new(line, column));
+        }
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = "/// Adds one.\nlet inc = fn(n) { n + 1 };\n/** Two. */ inc(/// arg\n1)";
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        assert!(p.errors().is_empty());
+        assert_eq!(program.statements.len(), 2);
+
+        let texts: Vec<(&str, Position)> = p
+            .doc_comments()
+            .iter()
+            .map(|comment| (comment.text.as_str(), comment.pos))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("/// Adds one.", Position::new(1, 1)),
+                ("/** Two. */", Position::new(3, 1)),
+                ("/// arg", Position::new(3, 17)),
+            ]
+        );
+    }
+
     fn check_parse_errors(p: Parser) {
         let errors = p.errors;
         let len = errors.len();
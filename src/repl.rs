@@ -1,51 +1,71 @@
-use super::{compiler, lexer, parser, vm};
+use super::arithmetic::IntegerMode;
+use super::session::{Engine, Session, SessionError};
 use std::io;
 use std::io::prelude::Write;
 
-pub fn start() {
-    const PROMPT: &str = ">> ";
+const PROMPT: &str = ">> ";
 
-    let mut constants = compiler::new_constants();
-    let mut globals = vm::new_globals_store();
-    let mut symbol_table_stack = compiler::new_symbol_table_stack();
+pub fn start(engine: Engine, integer_mode: IntegerMode) {
+    // engine ごとに束縛を持つので、切り替えると前の engine の定義は見えない
+    let mut session = Session::new(engine);
+    session.set_integer_mode(integer_mode);
 
     loop {
         print!("{}", PROMPT);
         io::stdout().flush().unwrap();
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-
-        let l = lexer::Lexer::new(&input);
-        let mut p = parser::Parser::new(l);
-        let program = p.parse_program();
-
-        let errors = p.errors();
-        if !errors.is_empty() {
-            print_parser_errors(errors);
-            continue;
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            // EOF
+            println!();
+            return;
         }
 
-        let mut comp = compiler::Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
-        if let Err(err) = comp.compile(program) {
-            println!("Woops! Compilation failed:\n {}", err);
+        if let Some(command) = input.trim().strip_prefix(':') {
+            run_command(&mut session, command);
             continue;
         }
 
-        let mut machine = vm::VM::new_with_globals_store(comp.bytecode(), &mut globals);
-        if let Err(err) = machine.run() {
-            println!("Woops! Executing bytecode failed:\n {}", err);
-            continue;
+        match session.run(&input) {
+            Ok(Some(obj)) => println!("{}", obj),
+            Ok(None) => println!("None."),
+            Err(err) => print_error(&input, &err),
         }
+    }
+}
 
-        match machine.last_popped_stack_elem {
-            Some(stack_top) => println!("{}", stack_top),
-            None => println!("None."),
-        }
+fn run_command(session: &mut Session, command: &str) {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        ["engine"] => println!("engine: {}", session.engine()),
+        ["engine", name] => match name.parse() {
+            Ok(engine) => {
+                session.set_engine(engine);
+                println!("engine: {}", engine);
+            }
+            Err(err) => println!("{}", err),
+        },
+        ["integer-mode"] => println!("integer-mode: {}", session.integer_mode()),
+        ["integer-mode", name] => match name.parse() {
+            Ok(mode) => {
+                session.set_integer_mode(mode);
+                println!("integer-mode: {}", mode);
+            }
+            Err(err) => println!("{}", err),
+        },
+        _ => println!(
+            "unknown command: :{} (try :engine [vm|eval|diff] or :integer-mode [checked|wrapping])",
+            command
+        ),
     }
 }
 
-fn print_parser_errors(errors: std::vec::Vec<String>) {
-    for msg in errors {
-        println!("{}", msg)
+fn print_error(input: &str, err: &SessionError) {
+    match err {
+        SessionError::Parse(_) => {}
+        SessionError::Macro(_) => println!("Woops! Macro expansion failed:"),
+        SessionError::Compile(_) => println!("Woops! Compilation failed:"),
+        SessionError::Runtime { .. } => println!("Woops! Executing failed:"),
+        SessionError::Divergence { .. } => println!("Woops! Engines disagree:"),
     }
+    println!("{}", err.render("repl", input));
 }
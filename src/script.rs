@@ -0,0 +1,159 @@
+use super::arithmetic::IntegerMode;
+use super::bytecode::{self, BytecodeFile};
+use super::disassembler;
+use super::session::{Engine, Session};
+
+// ファイル全体を指定の engine で実行する。エラーは位置付きで整形して返す
+pub fn run(
+    name: &str,
+    source: &str,
+    engine: Engine,
+    integer_mode: IntegerMode,
+) -> Result<(), String> {
+    let mut session = Session::new(engine);
+    session.set_integer_mode(integer_mode);
+    match session.run(source) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.render(name, source)),
+    }
+}
+
+pub fn compile(name: &str, source: &str) -> Result<Vec<u8>, String> {
+    let mut session = Session::new(Engine::Vm);
+    let file = session
+        .compile(name, source)
+        .map_err(|err| err.render(name, source))?;
+    file.encode().map_err(|err| format!("{}: {}", name, err))
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytecode::is_bytecode(bytes)
+}
+
+// compile 済みのファイルは VM でしか実行できない
+pub fn run_bytecode(name: &str, bytes: &[u8], integer_mode: IntegerMode) -> Result<(), String> {
+    let file = BytecodeFile::decode(bytes).map_err(|err| format!("{}: {}", name, err))?;
+    // 元のソースはないので、エラーは位置だけを表示する
+    let source_name = file.source_name.clone();
+
+    let mut session = Session::new(Engine::Vm);
+    session.set_integer_mode(integer_mode);
+    match session.run_bytecode(file) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.render(&source_name, "")),
+    }
+}
+
+// compile 済みのファイルでもソースでも、VM の命令列を一覧にする
+pub fn disasm(name: &str, bytes: &[u8]) -> Result<String, String> {
+    let file = if is_bytecode(bytes) {
+        BytecodeFile::decode(bytes).map_err(|err| format!("{}: {}", name, err))?
+    } else {
+        let source = String::from_utf8_lossy(bytes);
+        Session::new(Engine::Vm)
+            .compile(name, &source)
+            .map_err(|err| err.render(name, &source))?
+    };
+    Ok(disassembler::disassemble(&file.main, &file.constants))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, disasm, run, run_bytecode, Engine, IntegerMode};
+
+    #[test]
+    fn test_run() {
+        let tests = vec![
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };\nfib(10);",
+                Ok(()),
+            ),
+            (
+                "let x = 1;\nlet y = (x + 2;",
+                Err("test.monkey:2:15: expected next token to be RParen, got Semicolon instead\nlet y = (x + 2;\n              ^"),
+            ),
+            (
+                "let x = 1;\nx + zzz;",
+                Err("test.monkey:2:5: undefined variable: zzz\nx + zzz;\n    ^"),
+            ),
+            (
+                "let x = 1;\n-true;",
+                Err("test.monkey:2:1: unknown operator: -BOOLEAN\n-true;\n^"),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let result = run("test.monkey", input, Engine::Vm, IntegerMode::Checked);
+            match expected {
+                Ok(_) => assert!(result.is_ok(), "{:?}", result),
+                Err(expected) => match result {
+                    Ok(_) => assert!(false, "expected error for {}", input),
+                    Err(err) => assert!(err.starts_with(expected), "{}", err),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_with_other_engines() {
+        let input = "let x = 1;\nlen(x);\nx";
+
+        // builtin のエラーはどの engine でも実行時エラーになる
+        let vm_error =
+            "test.monkey:2:4: argument to `len` not supported, got Integer(1)\nlen(x);\n   ^";
+        assert_eq!(
+            run("test.monkey", input, Engine::Vm, IntegerMode::Checked),
+            Err(vm_error.to_string())
+        );
+        assert_eq!(
+            run("test.monkey", input, Engine::Eval, IntegerMode::Checked),
+            Err("test.monkey: argument to `len` not supported, got Integer(1)".to_string())
+        );
+        assert_eq!(
+            run("test.monkey", input, Engine::Diff, IntegerMode::Checked),
+            Err(vm_error.to_string())
+        );
+    }
+
+    #[test]
+    fn test_compile_and_run_bytecode() {
+        let input = "let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) };
+let map = fn(arr, f) {
+  let iter = fn(arr, acc) {
+    if (len(arr) == 0) { acc } else { iter(rest(arr), push(acc, f(first(arr)))) }
+  };
+  iter(arr, [])
+};
+let doubled = map([1, 2, 3], fn(x) { x * 2 });
+unless(len(doubled) == 3, doubled, -\"oops\");";
+
+        let bytes = compile("test.monkey", input).unwrap();
+        assert_eq!(
+            run_bytecode("test.wbc", &bytes, IntegerMode::Checked),
+            Err("test.monkey:9:36: unknown operator: -STRING".to_string())
+        );
+
+        assert_eq!(
+            run_bytecode("test.wbc", b"WBC", IntegerMode::Checked),
+            Err("test.wbc: not a wacir bytecode file".to_string())
+        );
+    }
+
+    #[test]
+    fn test_disasm() {
+        let input = "let x = 1;\nputs(x + 2);";
+        let from_source = disasm("test.monkey", input.as_bytes()).unwrap();
+        let bytes = compile("test.monkey", input).unwrap();
+        assert_eq!(disasm("test.wbc", &bytes), Ok(from_source.clone()));
+        assert!(
+            from_source.contains("OpConstant 1             ; 2"),
+            "{}",
+            from_source
+        );
+
+        assert_eq!(
+            disasm("test.monkey", b"let x = y;"),
+            Err("test.monkey:1:9: undefined variable: y\nlet x = y;\n        ^".to_string())
+        );
+    }
+}
@@ -0,0 +1,1017 @@
+use super::arithmetic::IntegerMode;
+use super::ast::{Program, Statement};
+use super::bytecode::BytecodeFile;
+use super::compiler::{self, CompileError, SymbolTableStack};
+use super::diagnostic;
+use super::evaluator::{self, Environment, Eval, MacroError};
+use super::lexer::Lexer;
+use super::object::builtin;
+use super::object::{CompiledFunction, Object};
+use super::parser::{ParseError, Parser};
+use super::token::Position;
+use super::vm;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Vm,
+    Eval,
+    // 両方で実行して結果を突き合わせる
+    Diff,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vm" => Ok(Engine::Vm),
+            "eval" => Ok(Engine::Eval),
+            "diff" => Ok(Engine::Diff),
+            _ => Err(format!("unknown engine: {} (expected vm, eval or diff)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Vm => write!(f, "vm"),
+            Engine::Eval => write!(f, "eval"),
+            Engine::Diff => write!(f, "diff"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Parse(Vec<ParseError>),
+    Macro(MacroError),
+    Compile(CompileError),
+    Runtime {
+        message: String,
+        pos: Option<Position>,
+    },
+    Divergence {
+        vm: String,
+        eval: String,
+    },
+}
+
+impl SessionError {
+    pub fn render(&self, name: &str, source: &str) -> String {
+        match self {
+            SessionError::Parse(errors) => errors
+                .iter()
+                .map(|err| diagnostic::render(name, source, err.pos, &err.message))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            SessionError::Macro(err) => diagnostic::render(name, source, err.pos, &err.message),
+            SessionError::Compile(err) => {
+                diagnostic::render(name, source, err.pos(), &err.to_string())
+            }
+            SessionError::Runtime {
+                message,
+                pos: Some(pos),
+            } => diagnostic::render(name, source, *pos, message),
+            SessionError::Runtime { message, pos: None } => format!("{}: {}", name, message),
+            SessionError::Divergence { vm, eval } => format!(
+                "{}: engines diverged\n  vm:   {}\n  eval: {}",
+                name, vm, eval
+            ),
+        }
+    }
+}
+
+// evaluator は関数呼び出しごとに Rust の stack を使うので、MAX_FRAMES の深さまで
+// 呼べるだけの stack を持った thread で実行する
+pub const THREAD_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// 実行結果。最後の文が式文でなければ値はない
+type Outcome = Result<Option<Object>, SessionError>;
+
+// REPL の入力やスクリプトをまたいで、各 engine の状態を保持する
+pub struct Session {
+    engine: Engine,
+    integer_mode: IntegerMode,
+    constants: Vec<Object>,
+    globals: Vec<Object>,
+    symbol_table_stack: SymbolTableStack,
+    env: Rc<RefCell<Environment>>,
+    // 定義済みの macro。どの engine でも実行前に展開する
+    macro_env: Rc<RefCell<Environment>>,
+}
+
+impl Session {
+    pub fn new(engine: Engine) -> Session {
+        Session {
+            engine,
+            integer_mode: IntegerMode::default(),
+            constants: compiler::new_constants(),
+            globals: vm::new_globals_store(),
+            symbol_table_stack: compiler::new_symbol_table_stack(),
+            env: Environment::new(),
+            macro_env: Environment::new(),
+        }
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    pub fn integer_mode(&self) -> IntegerMode {
+        self.integer_mode
+    }
+
+    pub fn set_integer_mode(&mut self, mode: IntegerMode) {
+        self.integer_mode = mode;
+        self.env.borrow_mut().set_integer_mode(mode);
+    }
+
+    pub fn run(&mut self, source: &str) -> Outcome {
+        let program = self.parse(source)?;
+
+        match self.engine {
+            Engine::Vm => self.run_vm(program),
+            Engine::Eval => self.run_eval(program),
+            Engine::Diff => {
+                // 出力も比べるので両方ためておき、VM の分だけを書き出す
+                let (eval_outcome, eval_output) =
+                    builtin::capture_output(|| self.run_eval(program.clone()));
+                let (vm_outcome, vm_output) = builtin::capture_output(|| self.run_vm(program));
+                builtin::write_output(&vm_output);
+                match (&vm_outcome, &eval_outcome) {
+                    // compile error は VM にしかないので比べない
+                    (Err(SessionError::Compile(_)), _) => vm_outcome,
+                    _ if !agree(&vm_outcome, &eval_outcome) => Err(SessionError::Divergence {
+                        vm: describe(&vm_outcome),
+                        eval: describe(&eval_outcome),
+                    }),
+                    _ if vm_output != eval_output => Err(SessionError::Divergence {
+                        vm: format!("output {:?}", vm_output),
+                        eval: format!("output {:?}", eval_output),
+                    }),
+                    _ => vm_outcome,
+                }
+            }
+        }
+    }
+
+    // engine によらず VM 向けに compile する
+    pub fn compile(&mut self, name: &str, source: &str) -> Result<BytecodeFile, SessionError> {
+        let program = self.parse(source)?;
+
+        let mut comp =
+            compiler::Compiler::new_with_state(&mut self.symbol_table_stack, &mut self.constants);
+        comp.compile(program).map_err(SessionError::Compile)?;
+        let bytecode = comp.bytecode();
+
+        Ok(BytecodeFile {
+            source_name: name.to_string(),
+            main: CompiledFunction {
+                instructions: bytecode.instructions,
+                num_locals: 0,
+                num_parameters: 0,
+                positions: bytecode.positions,
+            },
+            constants: bytecode.constants.clone(),
+        })
+    }
+
+    pub fn run_bytecode(&mut self, file: BytecodeFile) -> Outcome {
+        let mut constants = file.constants;
+        let bytecode = compiler::ByteCode {
+            instructions: file.main.instructions,
+            constants: &mut constants,
+            positions: file.main.positions,
+        };
+
+        let mut machine = vm::VM::new_with_globals_store(bytecode, &mut self.globals);
+        machine.set_integer_mode(self.integer_mode);
+        machine.run().map_err(|err| SessionError::Runtime {
+            message: err.message,
+            pos: err.pos,
+        })?;
+        Ok(machine.last_popped_stack_elem)
+    }
+
+    fn parse(&mut self, source: &str) -> Result<Program, SessionError> {
+        let l = Lexer::new(source);
+        let mut p = Parser::new(l);
+        let mut program = p.parse_program();
+        if !p.errors().is_empty() {
+            return Err(SessionError::Parse(p.into_errors()));
+        }
+
+        evaluator::define_macros(&mut program, Rc::clone(&self.macro_env));
+        evaluator::expand_macros(program, &mut self.macro_env).map_err(SessionError::Macro)
+    }
+
+    fn run_vm(&mut self, program: Program) -> Outcome {
+        let has_value = ends_with_expression(&program);
+
+        let mut comp =
+            compiler::Compiler::new_with_state(&mut self.symbol_table_stack, &mut self.constants);
+        comp.compile(program).map_err(SessionError::Compile)?;
+
+        let mut machine = vm::VM::new_with_globals_store(comp.bytecode(), &mut self.globals);
+        machine.set_integer_mode(self.integer_mode);
+        machine.run().map_err(|err| SessionError::Runtime {
+            message: err.message,
+            pos: err.pos,
+        })?;
+
+        Ok(if has_value {
+            machine.last_popped_stack_elem
+        } else {
+            None
+        })
+    }
+
+    fn run_eval(&mut self, program: Program) -> Outcome {
+        match program.eval(&mut self.env) {
+            Object::Error(message) => Err(SessionError::Runtime { message, pos: None }),
+            obj if ends_with_expression(&program) => Ok(Some(obj)),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn ends_with_expression(program: &Program) -> bool {
+    matches!(
+        program.statements.last(),
+        Some(Statement::ExpressionStatement(_))
+    )
+}
+
+// 実行時エラーは文言までそろっていれば一致とみなす。位置は evaluator にはないので比べない
+fn agree(vm: &Outcome, eval: &Outcome) -> bool {
+    match (vm, eval) {
+        (Ok(Some(a)), Ok(Some(b))) => same_value(a, b),
+        (Ok(None), Ok(None)) => true,
+        (
+            Err(SessionError::Runtime { message: a, .. }),
+            Err(SessionError::Runtime { message: b, .. }),
+        ) => a == b,
+        _ => false,
+    }
+}
+
+// 関数は engine ごとに表現が違うので、関数同士であれば同じとみなす
+fn same_value(a: &Object, b: &Object) -> bool {
+    match (a, b) {
+        (Object::Array(a), Object::Array(b)) => {
+            a.elements.len() == b.elements.len()
+                && a.elements
+                    .iter()
+                    .zip(b.elements.iter())
+                    .all(|(a, b)| same_value(a, b))
+        }
+        // 表示の順番もそろっていないといけないので、順番どおりに比べる
+        (Object::Hash(a), Object::Hash(b)) => {
+            a.len() == b.len()
+                && a.pairs().zip(b.pairs()).all(|(pair, other)| {
+                    same_value(&pair.key, &other.key) && same_value(&pair.value, &other.value)
+                })
+        }
+        _ if is_callable(a) && is_callable(b) => true,
+        _ => a == b,
+    }
+}
+
+fn is_callable(obj: &Object) -> bool {
+    matches!(
+        obj,
+        Object::Function(_) | Object::Closure(_) | Object::CompiledFunction(_) | Object::Builtin(_)
+    )
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Ok(Some(obj)) => format!("{}", obj),
+        Ok(None) => "no value".to_string(),
+        Err(SessionError::Runtime { message, .. }) => format!("error: {}", message),
+        Err(err) => format!("error: {:?}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::code::{make, make_with_operands, Instructions, Opcode};
+    use super::super::object::builtin::capture_output;
+    use super::super::object::Object;
+    use super::{
+        agree, describe, BytecodeFile, CompileError, CompiledFunction, Engine, IntegerMode,
+        Outcome, Session, SessionError, THREAD_STACK_SIZE,
+    };
+    use std::thread;
+
+    #[test]
+    fn test_engines() {
+        let tests = vec![
+            ("1 + 2", Some(Object::Integer(3))),
+            ("let x = 5;", None),
+            ("let x = 5; x * 2", Some(Object::Integer(10))),
+            (
+                "let add = fn(a, b) { a + b }; add(1, 2) == 3",
+                Some(Object::Boolean(true)),
+            ),
+            ("[1, 2][1]", Some(Object::Integer(2))),
+            ("{\"a\": 1}[\"a\"]", Some(Object::Integer(1))),
+            // トップレベルの return はそこで止まる
+            ("return 5; 6", Some(Object::Integer(5))),
+        ];
+
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            for (input, expected) in &tests {
+                let mut session = Session::new(engine);
+                match session.run(input) {
+                    Ok(result) => assert_eq!(&result, expected, "{} ({})", input, engine),
+                    Err(err) => assert!(false, "{} ({}): {:?}", input, engine, err),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_invalid_bytecode() {
+        let tests = vec![
+            (vec![make(Opcode::OpPop)], Err("stack underflow")),
+            (
+                vec![make_with_operands(Opcode::OpArray, &[2])],
+                Err("stack underflow"),
+            ),
+            (
+                vec![make_with_operands(Opcode::OpCall, &[1])],
+                Err("stack underflow"),
+            ),
+            (
+                vec![make_with_operands(Opcode::OpGetFree, &[0])],
+                Err("free variable 0 out of range"),
+            ),
+            (
+                vec![
+                    make_with_operands(Opcode::OpConstant, &[0]),
+                    make(Opcode::OpReturnValue),
+                    make(Opcode::OpPop),
+                ],
+                Ok(Object::Integer(7)),
+            ),
+            (vec![make(Opcode::OpReturn)], Ok(Object::Null)),
+        ];
+
+        for (instructions, expected) in tests {
+            let file = BytecodeFile {
+                source_name: "test.monkey".to_string(),
+                main: CompiledFunction {
+                    instructions: Instructions(
+                        instructions.into_iter().flat_map(|ins| ins.0).collect(),
+                    ),
+                    num_locals: 0,
+                    num_parameters: 0,
+                    positions: vec![],
+                },
+                constants: vec![Object::Integer(7)],
+            };
+            match (Session::new(Engine::Vm).run_bytecode(file), expected) {
+                (Ok(Some(obj)), Ok(expected)) => assert_eq!(obj, expected),
+                (Err(SessionError::Runtime { message, .. }), Err(expected)) => {
+                    assert_eq!(message, expected)
+                }
+                (result, expected) => assert!(false, "expected {:?}, got {:?}", expected, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_is_kept_between_runs() {
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            let mut session = Session::new(engine);
+            session.run("let x = 40;").unwrap();
+            session.run("let f = fn(y) { x + y };").unwrap();
+            assert_eq!(
+                session.run("f(2)").unwrap(),
+                Some(Object::Integer(42)),
+                "{}",
+                engine
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_compile_defines_nothing() {
+        // evaluator は失敗するまでの let を実行するので、compile する engine だけを見る
+        for engine in vec![Engine::Vm, Engine::Diff] {
+            let mut session = Session::new(engine);
+            for input in ["let a = 1; let b = zzz;", "let f = fn() { qqq };"] {
+                match session.run(input) {
+                    Err(SessionError::Compile(_)) => {}
+                    other => assert!(false, "{} ({}): {:?}", input, engine, other),
+                }
+            }
+            for name in ["a", "b", "f()"] {
+                match session.run(name) {
+                    Err(SessionError::Compile(CompileError::UndefinedVariable { .. })) => {}
+                    other => assert!(false, "{} ({}): {:?}", name, engine, other),
+                }
+            }
+            // 後の入力は今までどおり compile して実行できる
+            session.run("let a = 2;").unwrap();
+            assert_eq!(
+                session.run("a").unwrap(),
+                Some(Object::Integer(2)),
+                "{}",
+                engine
+            );
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            // builtin のエラーも、どの engine でもそこで止まる
+            for input in ["1 + true", "len(1); 5"] {
+                let mut session = Session::new(engine);
+                match session.run(input) {
+                    Err(SessionError::Runtime { .. }) => {}
+                    other => assert!(false, "{} ({}): {:?}", input, engine, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_macros() {
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            let mut session = Session::new(engine);
+            session
+                .run(
+                    "let unless = macro(condition, consequence, alternative) {
+                        quote(if (!(unquote(condition))) {
+                            unquote(consequence);
+                        } else {
+                            unquote(alternative);
+                        });
+                    };",
+                )
+                .unwrap();
+            // 前の入力で定義した macro も展開される
+            assert_eq!(
+                session.run("unless(10 > 5, 1, 2)").unwrap(),
+                Some(Object::Integer(2)),
+                "{}",
+                engine
+            );
+            assert_eq!(
+                session.run("let x = unless(1 > 5, 3, 4); x").unwrap(),
+                Some(Object::Integer(3)),
+                "{}",
+                engine
+            );
+            match session.run("unless(true)") {
+                Err(SessionError::Macro(_)) => {}
+                other => assert!(false, "{}: {:?}", engine, other),
+            }
+
+            // 他の呼び出しの引数に書いた macro も展開される
+            session
+                .run("let plus_one = macro(a) { quote(unquote(a) + 1) };")
+                .unwrap();
+            assert_eq!(
+                session.run("len([plus_one(2)]) + plus_one(2)").unwrap(),
+                Some(Object::Integer(4)),
+                "{}",
+                engine
+            );
+            assert_eq!(
+                session
+                    .run("fn(x) { x * 10 }(plus_one(plus_one(1)))")
+                    .unwrap(),
+                Some(Object::Integer(30)),
+                "{}",
+                engine
+            );
+
+            // unquote した値は literal に戻して埋め込む
+            let tests = [
+                (r#"quote(unquote("a" + "b"))"#, "ab"),
+                (r#"quote(unquote([1, "x", [true]]))"#, "[1, x, [true]]"),
+                (r#"quote(unquote({"a": [1], 2: "b"}))"#, "{a: [1], 2: b}"),
+                ("quote(unquote(if (false) { 1 }))", "null"),
+            ];
+            for (body, expected) in tests.iter() {
+                session
+                    .run(&format!("let m = macro() {{ {} }};", body))
+                    .unwrap();
+                assert_eq!(
+                    session.run("m()").unwrap().map(|o| format!("{}", o)),
+                    Some(expected.to_string()),
+                    "{}: {}",
+                    engine,
+                    body
+                );
+            }
+            match session.run("let m = macro() { quote(unquote(fn(x) { x })) }; m()") {
+                Err(SessionError::Macro(err)) => assert_eq!(
+                    err.message,
+                    "cannot convert FUNCTION to AST node in unquote"
+                ),
+                other => assert!(false, "{}: {:?}", engine, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_divergence() {
+        let vm: Outcome = Ok(Some(Object::Integer(1)));
+        let eval: Outcome = Ok(Some(Object::Integer(2)));
+        assert!(!agree(&vm, &eval));
+        assert_eq!(describe(&vm), "1");
+        assert!(agree(&vm, &Ok(Some(Object::Integer(1)))));
+        assert!(!agree(&vm, &Ok(None)));
+
+        // 実行時エラーは文言で比べる
+        let error = |message: &str| -> Outcome {
+            Err(SessionError::Runtime {
+                message: message.to_string(),
+                pos: None,
+            })
+        };
+        assert!(agree(&error("stack overflow"), &error("stack overflow")));
+        assert!(!agree(
+            &error("stack overflow"),
+            &error("division by zero: 1 / 0")
+        ));
+        assert!(!agree(&error("stack overflow"), &vm));
+
+        let mut session = Session::new(Engine::Diff);
+        match session.run("1 + true") {
+            Err(SessionError::Runtime { message, .. }) => {
+                assert_eq!(message, "type mismatch: INTEGER + BOOLEAN")
+            }
+            other => assert!(false, "{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_diff_output() {
+        // 出力は VM の分を一度だけ書く
+        let mut session = Session::new(Engine::Diff);
+        let (outcome, output) = capture_output(|| session.run("puts(1, \"a\"); 2"));
+        assert_eq!(outcome.unwrap(), Some(Object::Integer(2)));
+        assert_eq!(output, "1\na\n");
+
+        // 値が同じでも、出力が違えば食い違いにする。関数の表示は engine ごとに違う
+        let (outcome, output) = capture_output(|| session.run("puts(fn(x) { x }); 1"));
+        match outcome {
+            Err(SessionError::Divergence { vm, eval }) => {
+                assert!(vm.starts_with("output \"Closure["), "{}", vm);
+                assert_eq!(eval, "output \"fn(x) {x}\\n\"");
+            }
+            other => assert!(false, "{:?}", other),
+        }
+        assert!(output.starts_with("Closure["), "{}", output);
+    }
+
+    #[test]
+    fn test_call_depth() {
+        // 深い再帰はどの engine でも host を落とさずに実行時エラーになる
+        let handle = thread::Builder::new()
+            .stack_size(THREAD_STACK_SIZE)
+            .spawn(|| {
+                let tests = [
+                    (
+                        "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(5000)",
+                        "stack overflow",
+                    ),
+                    ("let f = fn() { f() }; f()", "stack overflow"),
+                    (
+                        "let f = fn(n) { if (n == 0) { 0 } else { map([n], fn(x) { f(x - 1) })[0] } }; f(5000)",
+                        "stack overflow",
+                    ),
+                ];
+                run_errors_on_engines(&tests);
+
+                let tests = [(
+                    "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(500)",
+                    "500",
+                )];
+                run_on_engines(&tests);
+            })
+            .unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_integer_mode() {
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            let mut session = Session::new(engine);
+            for input in [
+                "9223372036854775807 + 1",
+                "1 / 0",
+                "let f = fn(x) { x * 2 }; f(-9223372036854775807 - 1)",
+            ] {
+                match session.run(input) {
+                    Err(SessionError::Runtime { .. }) => {}
+                    other => assert!(false, "{} ({}): {:?}", input, engine, other),
+                }
+            }
+
+            session.set_integer_mode(IntegerMode::Wrapping);
+            assert_eq!(
+                session
+                    .run("let f = fn(x) { x + 1 }; f(9223372036854775807)")
+                    .unwrap(),
+                Some(Object::Integer(i64::MIN)),
+                "{}",
+                engine
+            );
+            assert!(session.run("1 / 0").is_err(), "{}", engine);
+        }
+    }
+
+    #[test]
+    fn test_closure_capture() {
+        let tests = [
+            // 関数の中の変数は、closure を作った時点の値を取り込む
+            (
+                "let g = fn() { let x = 1; let f = fn() { x }; x = 2; f() }; g()",
+                "1",
+            ),
+            (
+                "let g = fn() {
+                    let fs = [];
+                    for (i in range(3)) { let j = i; fs = push(fs, fn() { j }); }
+                    map(fs, fn(f) { f() })
+                };
+                g()",
+                "[0, 1, 2]",
+            ),
+            // global は共有する
+            ("let x = 1; let f = fn() { x }; x = 2; f()", "2"),
+            (
+                "let g = fn() { let f = fn(n) { if (n == 0) { 0 } else { n + f(n - 1) } }; f(3) }; g()",
+                "6",
+            ),
+        ];
+        run_on_engines(&tests);
+    }
+
+    #[test]
+    fn test_loop_control_in_expressions() {
+        // 式の途中の break と continue も、それを囲むループに届く
+        let tests = [
+            (
+                "let n = 0; while (true) { let a = [if (true) { break; }]; n += 1; }; n",
+                "0",
+            ),
+            (
+                "let n = 0; while (n < 3) { n += 1; puts(1 + if (true) { continue; }); }; n",
+                "3",
+            ),
+            (
+                "let n = 0; for (x in [1, 2]) { n += x; len(if (true) { continue; }); n = 100; }; n",
+                "3",
+            ),
+            (
+                "let n = 0; while (true) { n += 1; {\"a\": if (n > 2) { break; } else { 1 }}; }; n",
+                "3",
+            ),
+            (
+                "let n = 0; while (true) { n += 1; [1][if (true) { break; }]; }; n",
+                "1",
+            ),
+            (
+                "let n = 0; for (x in [1, 2]) { while (if (x == 1) { continue; } else { false }) {}; n += x; }; n",
+                "2",
+            ),
+        ];
+        run_on_engines(&tests);
+    }
+
+    #[test]
+    fn test_operator_evaluation_order() {
+        // 比較も左辺から評価する
+        let tests = [
+            (
+                r#"let log = ""; let f = fn(x) { log += x; 1 }; f("L") < f("R"); log"#,
+                "LR",
+            ),
+            (
+                r#"let log = ""; let f = fn(x) { log += x; 1 }; f("L") <= f("R"); log"#,
+                "LR",
+            ),
+            (
+                r#"let log = ""; let f = fn(x) { log += x; x }; f("a") || f("b"); log"#,
+                "a",
+            ),
+            // && と || は右辺を評価しないとき左辺の値になる
+            ("1 || 2", "1"),
+            ("0 || 2", "0"),
+            ("if (false) { 1 } || 2", "2"),
+            ("if (false) { 1 } && true", "null"),
+            ("false && 1", "false"),
+            ("2 && 3", "3"),
+        ];
+        run_on_engines(&tests);
+
+        let error_tests = [
+            ("(1 / 0) < len(1)", "division by zero: 1 / 0"),
+            ("(1 / 0) <= len(1)", "division by zero: 1 / 0"),
+        ];
+        run_errors_on_engines(&error_tests);
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let tests = [
+            (r#"let name = "Monkey"; "Hello ${name}!""#, "Hello Monkey!"),
+            (
+                r#""${1 + 2} ${1.5} ${true} ${[1, "a"]} ${if (false) { 1 }}""#,
+                "3 1.5 true [1, a] null",
+            ),
+            (r#"let f = fn(x) { "<${x}>" }; "${f("${f(1)}")}""#, "<<1>>"),
+            (
+                r#""tab\there \"q\" \\ \${x} \u{1F412}""#,
+                "tab\there \"q\" \\ ${x} \u{1F412}",
+            ),
+            (r#"str(12) + str("ab") + str({"k": [1]})"#, "12ab{k: [1]}"),
+            // str という名前の変数があっても組み込みの str を使う
+            (r#"let str = fn(x) { "pwned" }; "v=${1}""#, "v=1"),
+            (r#"fn(str) { "v=${str}" }(5)"#, "v=5"),
+        ];
+        run_on_engines(&tests);
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let tests = [
+            ("map([1, 2, 3], fn(x) { x * 2 })", "[2, 4, 6]"),
+            ("let k = 10; map([1, 2], fn(x) { x + k })", "[11, 12]"),
+            ("map([[1], [2, 3]], len)", "[1, 2]"),
+            ("filter(range(10), fn(x) { x % 3 == 0 })", "[0, 3, 6, 9]"),
+            ("reduce([1, 2, 3, 4], 0, fn(acc, x) { acc + x })", "10"),
+            ("reduce([], 7, fn(acc, x) { acc + x })", "7"),
+            ("sort([3, 1.5, -2, 2])", "[-2, 1.5, 2, 3]"),
+            (r#"sort(["b", "c", "a"])"#, "[a, b, c]"),
+            (
+                r#"sort(["bb", "a", "cc", "d"], fn(a, b) { len(a) < len(b) })"#,
+                "[a, d, bb, cc]",
+            ),
+            ("sort([1, 3, 2], fn(a, b) { a > b })", "[3, 2, 1]"),
+            ("reverse([1, 2, 3])", "[3, 2, 1]"),
+            ("slice([1, 2, 3, 4], 1, 3)", "[2, 3]"),
+            ("slice([1, 2, 3, 4], -2)", "[3, 4]"),
+            ("slice([1, 2, 3, 4], 3, 1)", "[]"),
+            ("slice([1, 2], 0, 100)", "[1, 2]"),
+            ("concat([1], [2, 3])", "[1, 2, 3]"),
+            ("contains([1, 2, 3], 2.0)", "true"),
+            (r#"contains([[1], "a"], [1])"#, "true"),
+            ("contains([], 1)", "false"),
+            (r#"index_of(["a", "b"], "b")"#, "1"),
+            ("index_of([1], 2)", "null"),
+            (r#"join([1, "a", [true]], ", ")"#, "1, a, [true]"),
+            ("join([1, 2])", "12"),
+            ("range(3)", "[0, 1, 2]"),
+            ("range(2, 5)", "[2, 3, 4]"),
+            ("range(5, 0, -2)", "[5, 3, 1]"),
+            ("range(3, 1)", "[]"),
+            ("len(range(-1000000, 0))", "1000000"),
+            ("range(9223372036854775806, 9223372036854775807)", "[9223372036854775806]"),
+            ("range(-9223372036854775807 - 1, 0, 9223372036854775807)", "[-9223372036854775808, -1]"),
+            (
+                "let sum = fn(xs) { reduce(map(xs, fn(x) { if (len(x) == 0) { 0 } else { sum(x) } }), len(xs), fn(a, b) { a + b }) }; sum([[], [[]], [[], []]])",
+                "6",
+            ),
+        ];
+        run_on_engines(&tests);
+
+        let error_tests = [
+            (
+                "map(1, len)",
+                "argument to `map` must be ARRAY, got INTEGER",
+            ),
+            (
+                "map([1], 1)",
+                "argument to `map` must be FUNCTION, got INTEGER",
+            ),
+            ("map([1])", "wrong number of arguments. got=1, want=2"),
+            (
+                "slice([1])",
+                "wrong number of arguments. got=1, want=2 or 3",
+            ),
+            ("range()", "wrong number of arguments. got=0, want=1 to 3"),
+            ("range(1, 2, 0)", "range step must not be zero"),
+            (
+                "range(9223372036854775807)",
+                "range too long: 9223372036854775807 elements, max 1000000",
+            ),
+            (
+                "range(1000001, 0, -1)",
+                "range too long: 1000001 elements, max 1000000",
+            ),
+            (r#"sort([1, "a"])"#, "cannot compare STRING and INTEGER"),
+            (
+                r#"join([1], 2)"#,
+                "argument to `join` must be STRING, got INTEGER",
+            ),
+            ("map([1, 0], fn(x) { 1 / x })", "division by zero: 1 / 0"),
+            (
+                r#"let r = map([1], fn(x) { x / 0 }); puts("continued"); r"#,
+                "division by zero: 1 / 0",
+            ),
+            (
+                "filter([1], fn(x) { 9223372036854775807 + x })",
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+            (
+                "reduce([1], 0, fn(acc, x) { acc + len(x) })",
+                "argument to `len` not supported, got Integer(1)",
+            ),
+            (
+                "map([1], fn(a, b) { a })",
+                "wrong number of arguments: want=2, got=1",
+            ),
+            (
+                "map([1], fn(x) { map(x, len) })",
+                "argument to `map` must be ARRAY, got INTEGER",
+            ),
+        ];
+        run_errors_on_engines(&error_tests);
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        let tests = [
+            (r#"len("héllo 世界")"#, "8"),
+            (r#""héllo"[1]"#, "é"),
+            (r#"let s = "世界"; s[1]"#, "界"),
+            (r#""abc"[3]"#, "null"),
+            (r#""abc"[-1]"#, "null"),
+            (r#"slice("héllo", 1, 3)"#, "él"),
+            (r#"slice("héllo", -2)"#, "lo"),
+            (r#"slice("abc", 2, 1)"#, ""),
+            (r#"split("a,b,,c", ",")"#, "[a, b, , c]"),
+            (r#"split(" a  b\tc ")"#, "[a, b, c]"),
+            (r#"split("日本", "")"#, "[日, 本]"),
+            (r#"trim("  x y  ")"#, "x y"),
+            (r#"upper("straße")"#, "STRASSE"),
+            (r#"lower("ÀB")"#, "àb"),
+            (r#"replace("a-b-c", "-", "+")"#, "a+b+c"),
+            (r#"starts_with("héllo", "hé")"#, "true"),
+            (r#"ends_with("héllo", "x")"#, "false"),
+            (r#"chars("héllo")"#, "[h, é, l, l, o]"),
+            (r#"to_string([1, "a"])"#, "[1, a]"),
+            (r#"parse_int(" -42 ")"#, "-42"),
+            (r#"parse_int("ff", 16)"#, "255"),
+            (r#"parse_int("12x")"#, "null"),
+            (r#"join(map(chars("abc"), upper), "")"#, "ABC"),
+        ];
+        run_on_engines(&tests);
+
+        let error_tests = [
+            (
+                "upper(1)",
+                "argument to `upper` must be STRING, got INTEGER",
+            ),
+            (
+                r#"split("a", 1)"#,
+                "argument to `split` must be STRING, got INTEGER",
+            ),
+            (r#"replace("a", "", "b")"#, "cannot replace an empty string"),
+            (
+                r#"parse_int("1", 1)"#,
+                "parse_int base must be 2 to 36, got 1",
+            ),
+            (
+                "slice(1, 0)",
+                "argument to `slice` must be ARRAY or STRING, got INTEGER",
+            ),
+            (
+                r#"trim("a", "b")"#,
+                "wrong number of arguments. got=2, want=1",
+            ),
+        ];
+        run_errors_on_engines(&error_tests);
+    }
+
+    #[test]
+    fn test_hash_builtins() {
+        let tests = [
+            (r#"{"b": 1, "a": 2, 3: 4}"#, "{b: 1, a: 2, 3: 4}"),
+            (
+                r#"let h = {"b": 1, "a": 2}; h["b"] = 3; h["c"] = 4; h"#,
+                "{b: 3, a: 2, c: 4}",
+            ),
+            (r#"keys({"b": 1, "a": 2})"#, "[b, a]"),
+            (r#"values({"b": 1, "a": 2})"#, "[1, 2]"),
+            (r#"items({"b": 1, true: [2]})"#, "[[b, 1], [true, [2]]]"),
+            (r#"has_key({"a": 1}, "a")"#, "true"),
+            (r#"has_key({"a": 1}, 1)"#, "false"),
+            (r#"get({"a": 1}, "a")"#, "1"),
+            (r#"get({"a": 1}, "b")"#, "null"),
+            (r#"get({"a": 1}, "b", 0)"#, "0"),
+            (r#"delete({"a": 1, "b": 2, "c": 3}, "b")"#, "{a: 1, c: 3}"),
+            (r#"delete({"a": 1}, "z")"#, "{a: 1}"),
+            (r#"let h = {"a": 1}; delete(h, "a"); h"#, "{a: 1}"),
+            (
+                r#"merge({"a": 1, "b": 2}, {"c": 3, "a": 4})"#,
+                "{a: 4, b: 2, c: 3}",
+            ),
+            (r#"len({"a": 1, "b": 2})"#, "2"),
+            (
+                r#"let h = delete({"a": 1, "b": 2, "c": 3}, "a"); h["a"] = 0; keys(h)"#,
+                "[b, c, a]",
+            ),
+            (r#"{"a": 1, "b": 2} == {"b": 2, "a": 1}"#, "true"),
+        ];
+        run_on_engines(&tests);
+
+        let error_tests = [
+            ("keys([1])", "argument to `keys` must be HASH, got ARRAY"),
+            (
+                r#"merge({}, 1)"#,
+                "argument to `merge` must be HASH, got INTEGER",
+            ),
+            (r#"has_key({}, 1.5)"#, "unusable as hash key: FLOAT"),
+            (
+                r#"get({})"#,
+                "wrong number of arguments. got=1, want=2 or 3",
+            ),
+        ];
+        run_errors_on_engines(&error_tests);
+    }
+
+    #[test]
+    fn test_composite_hash_keys() {
+        let tests = [
+            (r#"{[2024, 1]: "jan", [2024, 2]: "feb"}[[2024, 1]]"#, "jan"),
+            (
+                r#"let m = 2; {[2024, 1]: "jan", [2024, 2]: "feb"}[[2024, m]]"#,
+                "feb",
+            ),
+            (r#"{[2024, 1]: "jan"}[[2024, 1, 0]]"#, "null"),
+            (r#"{{"a": 1, "b": 2}: "ab"}[{"b": 2, "a": 1}]"#, "ab"),
+            (r#"{[{"a": [1]}]: 1}[[{"a": [1]}]]"#, "1"),
+            (r#"let n = if (false) { 1 }; {n: "null"}[n]"#, "null"),
+            (
+                r#"let h = {}; h[[1, 2]] = 3; h[[1, 2]] = 4; h"#,
+                "{[1, 2]: 4}",
+            ),
+            (r#"has_key({[1, [2]]: 0}, [1, [2]])"#, "true"),
+            (
+                r#"keys({[1, 2]: 0, {"a": [true]}: 1})"#,
+                "[[1, 2], {a: [true]}]",
+            ),
+        ];
+        run_on_engines(&tests);
+
+        let error_tests = [
+            (r#"{[1, 1.5]: 1}"#, "unusable as hash key: FLOAT"),
+            (r#"{"a": 1}[{"b": [1.5]}]"#, "unusable as hash key: FLOAT"),
+        ];
+        run_errors_on_engines(&error_tests);
+    }
+
+    #[test]
+    fn test_parse_engine() {
+        assert_eq!("vm".parse::<Engine>(), Ok(Engine::Vm));
+        assert_eq!("eval".parse::<Engine>(), Ok(Engine::Eval));
+        assert_eq!("diff".parse::<Engine>(), Ok(Engine::Diff));
+        assert!("jit".parse::<Engine>().is_err());
+    }
+
+    // 同じ表をすべての engine で実行して、表示した値で比べる
+    fn run_on_engines(tests: &[(&str, &str)]) {
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            for (input, expected) in tests {
+                match Session::new(engine).run(input) {
+                    Ok(Some(result)) => {
+                        assert_eq!(result.to_string(), *expected, "{} ({})", input, engine)
+                    }
+                    other => assert!(false, "{} ({}): {:?}", input, engine, other),
+                }
+            }
+        }
+    }
+
+    fn run_errors_on_engines(tests: &[(&str, &str)]) {
+        for engine in vec![Engine::Vm, Engine::Eval, Engine::Diff] {
+            for (input, expected) in tests {
+                match Session::new(engine).run(input) {
+                    Err(SessionError::Runtime { message, .. }) => {
+                        assert_eq!(message, *expected, "{} ({})", input, engine)
+                    }
+                    other => assert!(false, "{} ({}): {:?}", input, engine, other),
+                }
+            }
+        }
+    }
+}
@@ -31,6 +31,16 @@ impl Expectable for i64 {
     }
 }
 
+impl Expectable for f64 {
+    fn assert_eq(&self, actual: &Object) {
+        if let Object::Float(float) = actual {
+            assert_eq!(self, float);
+        } else {
+            assert!(false, "object is not Float. {}", actual)
+        }
+    }
+}
+
 impl Expectable for bool {
     fn assert_eq(&self, actual: &Object) {
         if let Object::Boolean(boolean) = actual {
@@ -44,7 +54,7 @@ impl Expectable for bool {
 impl Expectable for Object {
     fn assert_eq(&self, actual: &Object) {
         match self {
-            Object::Null => assert_eq!(self, actual),
+            Object::Null | Object::Error(_) => assert_eq!(self, actual),
             _ => assert!(false, "Not expectable object: {}", self),
         }
     }
@@ -76,10 +86,9 @@ impl<T: Expectable> Expectable for Vec<T> {
 impl<T: Expectable> Expectable for HashMap<HashKey, T> {
     fn assert_eq(&self, actual: &Object) {
         if let Object::Hash(hash) = actual {
-            assert_eq!(self.len(), hash.pairs.len());
+            assert_eq!(self.len(), hash.len());
             for (expected_key, expected_value) in self {
                 let pair = hash
-                    .pairs
                     .get(expected_key)
                     .expect("no pair for given key in Pairs");
                 test_expected_object(expected_value, &pair.value);
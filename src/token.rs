@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum TokenType {
     Illegal,
@@ -6,6 +8,7 @@ pub enum TokenType {
     // 識別子 + リテラル
     Ident, // add, foobar, x, y, ...
     Int,   // 12345
+    Float, // 1.5
 
     // 演算子
     Assign,   // =
@@ -14,11 +17,22 @@ pub enum TokenType {
     Bang,     // !
     Asterisk, // *
     Slash,    // /
+    Percent,  // %
 
     Lt,    // <
     Gt,    // >
+    LtEq,  // <=
+    GtEq,  // >=
     Eq,    // ==
     NotEq, // !=
+    And,   // &&
+    Or,    // ||
+
+    PlusAssign,     // +=
+    MinusAssign,    // -=
+    AsteriskAssign, // *=
+    SlashAssign,    // /=
+    PercentAssign,  // %=
 
     // デリミタ
     Comma,     // ,
@@ -37,8 +51,17 @@ pub enum TokenType {
     If,
     Else,
     Return,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
 
     String,
+    // "a ${x} b ${y} c" は StringHead ("a ") x StringMiddle (" b ") y StringTail (" c") になる
+    StringHead,
+    StringMiddle,
+    StringTail,
 
     LBracket, // [
     RBracket, // ]
@@ -48,10 +71,32 @@ pub enum TokenType {
     Macro, // macro
 }
 
+// 1 始まりの行・桁 (桁は文字単位)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// pos はトークンの先頭、end はトークンの直後の位置
 #[derive(Debug, Eq, PartialEq)]
 pub struct Token {
     pub t: TokenType,
     pub literal: String,
+    pub pos: Position,
+    pub end: Position,
 }
 
 pub fn lookup_ident(literal: &str) -> TokenType {
@@ -63,6 +108,11 @@ pub fn lookup_ident(literal: &str) -> TokenType {
         "if" => TokenType::If,
         "else" => TokenType::Else,
         "return" => TokenType::Return,
+        "while" => TokenType::While,
+        "for" => TokenType::For,
+        "in" => TokenType::In,
+        "break" => TokenType::Break,
+        "continue" => TokenType::Continue,
         "macro" => TokenType::Macro,
         _ => TokenType::Ident,
     }
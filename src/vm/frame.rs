@@ -1,21 +1,21 @@
 use super::super::code::Instructions;
-use super::super::object::CompiledFunction;
+use super::super::object::Closure;
 
 pub struct Frame {
-    func: CompiledFunction,
+    pub cl: Closure,
     pub ip: usize,
     pub base_pointer: usize,
 }
 
 impl Frame {
     pub fn instructions(&self) -> &Instructions {
-        &self.func.instructions
+        &self.cl.func.instructions
     }
 }
 
-pub fn new_frame(func: CompiledFunction, base_pointer: usize) -> Frame {
+pub fn new_frame(cl: Closure, base_pointer: usize) -> Frame {
     Frame {
-        func,
+        cl,
         ip: 0,
         base_pointer,
     }
@@ -1,21 +1,39 @@
 mod frame;
 
+use super::arithmetic::{self, IntegerMode};
 use super::code::*;
 use super::compiler::*;
 use super::object;
+use super::object::builtin::{string, Caller, BUILTINS};
 use super::object::hash::hash_key_of;
 use super::object::Object;
+use super::token::Position;
 use frame::*;
-use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fmt;
+use std::rc::Rc;
 
 const STACK_SIZE: usize = 2048;
 pub const GLOBALS_SIZE: usize = 65536;
-const MAX_FRAMES: usize = 1024;
+pub const MAX_FRAMES: usize = 1024;
 const TRUE: Object = Object::Boolean(true);
 const FALSE: Object = Object::Boolean(false);
 const NULL: Object = Object::Null;
 
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub pos: Option<Position>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
 pub fn new_globals_store() -> Vec<Object> {
     Vec::with_capacity(GLOBALS_SIZE)
 }
@@ -28,6 +46,7 @@ pub struct VM<'a> {
     globals: &'a mut Vec<Object>,
     frames: Vec<Frame>,
     frame_index: usize,
+    integer_mode: IntegerMode,
 }
 
 impl<'a> VM<'a> {
@@ -35,8 +54,14 @@ impl<'a> VM<'a> {
         let main_fn = object::CompiledFunction {
             instructions: bytecode.instructions,
             num_locals: 0,
+            num_parameters: 0,
+            positions: bytecode.positions,
         };
-        let main_frame = new_frame(main_fn, 0);
+        let main_closure = object::Closure {
+            func: Rc::new(main_fn),
+            free: vec![],
+        };
+        let main_frame = new_frame(main_closure, 0);
 
         let mut frames = Vec::with_capacity(MAX_FRAMES);
         frames.push(main_frame);
@@ -49,10 +74,23 @@ impl<'a> VM<'a> {
             globals: s,
             frames: frames,
             frame_index: 1,
+            integer_mode: IntegerMode::default(),
         }
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
+    pub fn set_integer_mode(&mut self, mode: IntegerMode) {
+        self.integer_mode = mode;
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.execute(0).map_err(|message| RuntimeError {
+            message,
+            pos: self.current_position(),
+        })
+    }
+
+    // frame の数が stop_depth に戻ったら止める。builtin から関数を呼んだときに使う
+    fn execute(&mut self, stop_depth: usize) -> Result<(), String> {
         while self.current_frame().ip < self.current_frame().instructions().0.len() {
             let ip = self.current_frame().ip;
             let ins = self.current_frame().instructions();
@@ -65,11 +103,11 @@ impl<'a> VM<'a> {
                     let constant = self.constants[const_index as usize].clone();
                     self.push(constant)?;
                 }
-                Opcode::OpAdd | Opcode::OpSub | Opcode::OpMul | Opcode::OpDiv => {
+                Opcode::OpAdd | Opcode::OpSub | Opcode::OpMul | Opcode::OpDiv | Opcode::OpMod => {
                     self.execute_binary_operation(op)?;
                 }
                 Opcode::OpPop => {
-                    self.pop();
+                    self.pop()?;
                 }
                 Opcode::OpTrue => {
                     self.push(TRUE)?;
@@ -77,7 +115,12 @@ impl<'a> VM<'a> {
                 Opcode::OpFalse => {
                     self.push(FALSE)?;
                 }
-                Opcode::OpEqual | Opcode::OpNotEqual | Opcode::OpGreaterThan => {
+                Opcode::OpEqual
+                | Opcode::OpNotEqual
+                | Opcode::OpGreaterThan
+                | Opcode::OpGreaterThanOrEqual
+                | Opcode::OpLessThan
+                | Opcode::OpLessThanOrEqual => {
                     self.execute_comparison(op)?;
                 }
                 Opcode::OpBang => {
@@ -88,79 +131,94 @@ impl<'a> VM<'a> {
                 }
                 Opcode::OpJump => {
                     let pos = read_uint16(ins, ip + 1) as usize;
-                    self.current_frame().ip = pos - 1;
+                    // ループの先頭は 0 番地のこともあるので、pos - 1 にせず continue する
+                    self.current_frame().ip = pos;
+                    continue;
                 }
                 Opcode::OpJumpNotTruthy => {
                     let pos = read_uint16(ins, ip + 1) as usize;
                     self.current_frame().ip += 2;
 
-                    let condition = self.pop();
+                    let condition = self.pop()?;
                     if !Self::is_truthy(condition) {
-                        self.current_frame().ip = pos - 1;
+                        self.current_frame().ip = pos;
+                        continue;
                     }
                 }
+                Opcode::OpJumpNotTruthyOrPop | Opcode::OpJumpTruthyOrPop => {
+                    let pos = read_uint16(ins, ip + 1) as usize;
+                    self.current_frame().ip += 2;
+
+                    let condition = self.stack[self.stack_start(1)?].clone();
+                    if Self::is_truthy(condition) == (op == Opcode::OpJumpTruthyOrPop) {
+                        self.current_frame().ip = pos;
+                        continue;
+                    }
+                    self.pop()?;
+                }
                 Opcode::OpNull => {
                     self.push(NULL)?;
                 }
                 Opcode::OpSetGlobal => {
                     let global_index = read_uint16(ins, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let popped = self.pop();
-                    if self.globals.len() == global_index {
-                        self.globals.push(popped);
-                    } else {
-                        self.globals[global_index] = popped;
+                    let popped = self.pop()?;
+                    // 先に定義された関数の slot が後ろにあることもある
+                    if self.globals.len() <= global_index {
+                        self.globals.resize(global_index + 1, NULL);
                     }
+                    self.globals[global_index] = popped;
                 }
                 Opcode::OpGetGlobal => {
                     let global_index = read_uint16(ins, ip + 1) as usize;
                     self.current_frame().ip += 2;
                     // TODO: clone() どうにかなるか
-                    let obj = (&self.globals[global_index]).clone();
+                    let obj = self.globals.get(global_index).cloned().unwrap_or(NULL);
                     self.push(obj)?;
                 }
                 Opcode::OpArray => {
                     let num_elements = read_uint16(ins, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let array = self.build_array(self.sp - num_elements, self.sp);
+                    let array = self.build_array(self.stack_start(num_elements)?, self.sp);
                     self.sp -= num_elements;
                     self.push(array)?;
                 }
                 Opcode::OpHash => {
                     let num_elements = read_uint16(ins, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let hash = self.build_hash(self.sp - num_elements, self.sp)?;
+                    let hash = self.build_hash(self.stack_start(num_elements)?, self.sp)?;
                     self.sp -= num_elements;
                     self.push(hash)?;
                 }
                 Opcode::OpIndex => {
-                    let index = self.pop();
-                    let left = self.pop();
+                    let index = self.pop()?;
+                    let left = self.pop()?;
                     self.execute_index_expression(left, index)?;
                 }
                 Opcode::OpCall => {
+                    let num_args = read_uint8(ins, ip + 1) as usize;
                     self.current_frame().ip += 1;
-                    let obj = &self.stack[self.sp - 1];
-                    if let Object::CompiledFunction(func) = obj {
-                        // TODO: clone() でいいのか？ self.stack から pop したものじゃダメ？
-                        let frame = new_frame(func.clone(), self.sp);
-                        let next_sp = frame.base_pointer + func.num_locals;
-                        self.push_frame(frame);
-                        self.sp = next_sp;
+                    if self.execute_call(num_args)? {
                         // self.current_frame().ip += 1; させないために continue する
                         continue;
-                    } else {
-                        return Err("calling non-function".to_string());
                     }
                 }
                 Opcode::OpReturnValue => {
-                    let return_value = self.pop();
+                    let return_value = self.pop()?;
+                    if self.frame_index == 1 {
+                        // トップレベルの return は、evaluator と同じくそこで実行を終える
+                        return Ok(());
+                    }
                     let frame = self.pop_frame();
                     self.sp = frame.base_pointer - 1;
                     self.stack.truncate(self.sp);
                     self.push(return_value)?;
                 }
                 Opcode::OpReturn => {
+                    if self.frame_index == 1 {
+                        self.last_popped_stack_elem = Some(NULL);
+                        return Ok(());
+                    }
                     let frame = self.pop_frame();
                     self.sp = frame.base_pointer - 1;
                     self.stack.truncate(self.sp);
@@ -170,15 +228,11 @@ impl<'a> VM<'a> {
                     let local_index = read_uint8(ins, ip + 1) as usize;
                     self.current_frame().ip += 1;
 
-                    let popped = self.pop();
+                    let popped = self.pop()?;
                     let base_poiner = self.current_frame().base_pointer;
-                    let index = base_poiner + local_index;
-                    if index < self.stack.len() {
-                        self.stack[index] = popped;
-                    } else if index == self.stack.len() {
-                        self.stack.push(popped);
-                    } else {
-                        return Err(format!("unexpected stack index: {}", index).to_string());
+                    match self.stack.get_mut(base_poiner + local_index) {
+                        Some(slot) => *slot = popped,
+                        None => return Err(format!("local {} out of range", local_index)),
                     }
                 }
                 Opcode::OpGetLocal => {
@@ -186,10 +240,60 @@ impl<'a> VM<'a> {
                     self.current_frame().ip += 1;
 
                     let base_poiner = self.current_frame().base_pointer;
-                    self.push(self.stack[base_poiner + local_index].clone())?;
+                    let obj = match self.stack.get(base_poiner + local_index) {
+                        Some(obj) => obj.clone(),
+                        None => return Err(format!("local {} out of range", local_index)),
+                    };
+                    self.push(obj)?;
+                }
+                Opcode::OpGetBuiltin => {
+                    let builtin_index = read_uint8(ins, ip + 1) as usize;
+                    self.current_frame().ip += 1;
+
+                    let builtin = &BUILTINS[builtin_index];
+                    self.push(Object::Builtin(builtin.clone()))?;
+                }
+                Opcode::OpClosure => {
+                    let const_index = read_uint16(ins, ip + 1) as usize;
+                    let num_free = read_uint8(ins, ip + 3) as usize;
+                    self.current_frame().ip += 3;
+
+                    self.push_closure(const_index, num_free)?;
+                }
+                Opcode::OpCurrentClosure => {
+                    let current_closure = self.current_frame().cl.clone();
+                    self.push(Object::Closure(current_closure))?;
+                }
+                Opcode::OpGetFree => {
+                    let free_index = read_uint8(ins, ip + 1) as usize;
+                    self.current_frame().ip += 1;
+
+                    let obj = match self.current_frame().cl.free.get(free_index) {
+                        Some(obj) => obj.clone(),
+                        None => return Err(format!("free variable {} out of range", free_index)),
+                    };
+                    self.push(obj)?;
+                }
+                Opcode::OpSetIndex => {
+                    let num_indices = read_uint8(ins, ip + 1) as usize;
+                    let op = read_uint8(ins, ip + 2);
+                    self.current_frame().ip += 2;
+
+                    self.execute_set_index(num_indices, op)?;
                 }
-                //
-                // _ => todo!("unknown Opcode: {:?}", op),
+                Opcode::OpIterItems => {
+                    let with_value = read_uint8(ins, ip + 1) == 1;
+                    self.current_frame().ip += 1;
+
+                    let iterable = self.pop()?;
+                    let elements = object::iteration_items(&iterable, with_value)?;
+                    self.push(Object::Array(object::Array { elements }))?;
+                } //
+                  // _ => todo!("unknown Opcode: {:?}", op),
+            }
+            if self.frame_index == stop_depth {
+                // 呼び出し元の ip は builtin を呼んだ側で進める
+                return Ok(());
             }
             self.current_frame().ip += 1;
         }
@@ -207,18 +311,103 @@ impl<'a> VM<'a> {
         Ok(())
     }
 
-    fn pop(&mut self) -> Object {
-        let o = self.stack.pop();
+    fn pop(&mut self) -> Result<Object, String> {
+        let obj = match self.stack.pop() {
+            Some(obj) => obj,
+            None => return Err("stack underflow".to_string()),
+        };
         self.sp -= 1;
-        let obj = o.unwrap();
         // TODO: obj.clone()
         self.last_popped_stack_elem = Some(obj.clone());
-        obj
+        Ok(obj)
+    }
+
+    // 上に積んである n 個の値のうち、一番下の位置を返す
+    fn stack_start(&self, n: usize) -> Result<usize, String> {
+        self.sp
+            .checked_sub(n)
+            .ok_or_else(|| "stack underflow".to_string())
+    }
+
+    // Returns true when a new frame was pushed.
+    fn execute_call(&mut self, num_args: usize) -> Result<bool, String> {
+        let callee = self.stack[self.stack_start(num_args + 1)?].clone();
+        match callee {
+            Object::Closure(cl) => {
+                self.call_closure(cl, num_args)?;
+                Ok(true)
+            }
+            Object::Builtin(builtin) => {
+                self.call_builtin(builtin, num_args)?;
+                Ok(false)
+            }
+            callee => Err(format!("not a function: {}", callee.type_name())),
+        }
+    }
+
+    fn call_closure(&mut self, cl: object::Closure, num_args: usize) -> Result<(), String> {
+        if num_args != cl.func.num_parameters {
+            return Err(format!(
+                "wrong number of arguments: want={}, got={}",
+                cl.func.num_parameters, num_args
+            ));
+        }
+
+        if self.frames.len() >= MAX_FRAMES {
+            return Err("stack overflow".to_string());
+        }
+
+        let num_locals = cl.func.num_locals;
+        // 引数はそのまま先頭の local になる
+        let frame = new_frame(cl, self.sp - num_args);
+        let next_sp = frame.base_pointer + num_locals;
+        if next_sp > STACK_SIZE {
+            return Err("stack overflow".to_string());
+        }
+        self.push_frame(frame);
+        // local 用の領域を確保して stack の長さと sp を揃える
+        self.stack.resize(next_sp, NULL);
+        self.sp = next_sp;
+        Ok(())
+    }
+
+    fn call_builtin(&mut self, builtin: object::Builtin, num_args: usize) -> Result<(), String> {
+        let args = self.stack.drain(self.sp - num_args..self.sp).collect();
+        // 引数と builtin 自身を stack から取り除く
+        self.sp -= num_args + 1;
+        self.stack.truncate(self.sp);
+
+        // evaluator と同じく、builtin のエラーで実行を止める
+        match (builtin.func)(self, args) {
+            Object::Error(message) => Err(message),
+            result => self.push(result),
+        }
+    }
+
+    fn push_closure(&mut self, const_index: usize, num_free: usize) -> Result<(), String> {
+        let func = match &self.constants[const_index] {
+            Object::CompiledFunction(func) => func.clone(),
+            constant => return Err(format!("not a function: {}", constant)),
+        };
+
+        let free = self
+            .stack
+            .drain(self.stack_start(num_free)?..self.sp)
+            .collect();
+        self.sp -= num_free;
+
+        self.push(Object::Closure(object::Closure {
+            func: Rc::new(func),
+            free,
+        }))
     }
 
     fn execute_binary_operation(&mut self, op: Opcode) -> Result<(), String> {
-        let right = self.pop();
-        let left = self.pop();
+        let right = self.pop()?;
+        let left = self.pop()?;
+        if let Some((left_value, right_value)) = arithmetic::float_operands(&left, &right) {
+            return self.execute_binary_float_operation(op, left_value, right_value);
+        }
         match (&left, &right) {
             (Object::Integer(left_value), Object::Integer(right_value)) => {
                 self.execute_binary_integer_operation(op, *left_value, *right_value)?;
@@ -226,12 +415,7 @@ impl<'a> VM<'a> {
             (Object::String(left_value), Object::String(right_value)) => {
                 self.execute_binary_string_operation(op, left_value, right_value)?;
             }
-            _ => {
-                return Err(format!(
-                    "unsupported object: right: {}, left: {}",
-                    &right, &left
-                ))
-            }
+            _ => return Err(arithmetic::infix_error(operator_of(op), &left, &right)),
         }
         Ok(())
     }
@@ -242,16 +426,21 @@ impl<'a> VM<'a> {
         left_value: i64,
         right_value: i64,
     ) -> Result<(), String> {
-        let result = match op {
-            Opcode::OpAdd => left_value + right_value,
-            Opcode::OpSub => left_value - right_value,
-            Opcode::OpMul => left_value * right_value,
-            Opcode::OpDiv => left_value / right_value,
-            _ => return Err(format!("unknown integer oprerator: {:?}", op)),
-        };
+        let result =
+            arithmetic::integer_infix(self.integer_mode, operator_of(op), left_value, right_value)?;
         self.push(Object::Integer(result))
     }
 
+    fn execute_binary_float_operation(
+        &mut self,
+        op: Opcode,
+        left_value: f64,
+        right_value: f64,
+    ) -> Result<(), String> {
+        let result = arithmetic::float_infix(operator_of(op), left_value, right_value)?;
+        self.push(Object::Float(result))
+    }
+
     fn execute_binary_string_operation(
         &mut self,
         op: Opcode,
@@ -259,23 +448,31 @@ impl<'a> VM<'a> {
         right_value: &str,
     ) -> Result<(), String> {
         if op != Opcode::OpAdd {
-            return Err(format!("unknown string operator: {:?}", op));
+            return Err(arithmetic::infix_error(
+                operator_of(op),
+                &Object::String(left_value.to_string()),
+                &Object::String(right_value.to_string()),
+            ));
         }
         self.push(Object::String(left_value.to_string() + right_value))
     }
 
     fn execute_comparison(&mut self, op: Opcode) -> Result<(), String> {
-        let right = self.pop();
-        let left = self.pop();
+        let right = self.pop()?;
+        let left = self.pop()?;
 
         if let (Object::Integer(left_value), Object::Integer(right_value)) = (&right, &left) {
             return self.execute_integer_comparison(op, *left_value, *right_value);
         }
+        // 1 == 1.0 のように数値として比べる
+        if let Some((left_value, right_value)) = arithmetic::float_operands(&left, &right) {
+            return self.execute_float_comparison(op, left_value, right_value);
+        }
 
         match op {
             Opcode::OpEqual => self.push(Self::native_bool_to_boolean_object(right == left)),
             Opcode::OpNotEqual => self.push(Self::native_bool_to_boolean_object(right != left)),
-            _ => Err(format!("unknown operator: {:?} {} {}", op, right, left)),
+            _ => Err(arithmetic::infix_error(operator_of(op), &left, &right)),
         }
     }
 
@@ -295,12 +492,39 @@ impl<'a> VM<'a> {
             Opcode::OpGreaterThan => self.push(Self::native_bool_to_boolean_object(
                 right_value > left_value,
             )),
+            Opcode::OpGreaterThanOrEqual => self.push(Self::native_bool_to_boolean_object(
+                right_value >= left_value,
+            )),
+            Opcode::OpLessThan => self.push(Self::native_bool_to_boolean_object(
+                right_value < left_value,
+            )),
+            Opcode::OpLessThanOrEqual => self.push(Self::native_bool_to_boolean_object(
+                right_value <= left_value,
+            )),
             _ => Err(format!("unknown operator: {:?}", op)),
         }
     }
 
+    fn execute_float_comparison(
+        &mut self,
+        op: Opcode,
+        left_value: f64,
+        right_value: f64,
+    ) -> Result<(), String> {
+        let result = match op {
+            Opcode::OpEqual => left_value == right_value,
+            Opcode::OpNotEqual => left_value != right_value,
+            Opcode::OpGreaterThan => left_value > right_value,
+            Opcode::OpGreaterThanOrEqual => left_value >= right_value,
+            Opcode::OpLessThan => left_value < right_value,
+            Opcode::OpLessThanOrEqual => left_value <= right_value,
+            _ => return Err(format!("unknown operator: {:?}", op)),
+        };
+        self.push(Self::native_bool_to_boolean_object(result))
+    }
+
     fn execute_bang_operator(&mut self) -> Result<(), String> {
-        let operand = self.pop();
+        let operand = self.pop()?;
 
         match operand {
             TRUE => self.push(FALSE),
@@ -311,11 +535,14 @@ impl<'a> VM<'a> {
     }
 
     fn execute_minus_operator(&mut self) -> Result<(), String> {
-        let operand = self.pop();
-        if let Object::Integer(integer) = operand {
-            self.push(Object::Integer(-integer))
-        } else {
-            Err(format!("unsupported type for negation: {}", operand))
+        let operand = self.pop()?;
+        match operand {
+            Object::Integer(integer) => {
+                let result = arithmetic::integer_negate(self.integer_mode, integer)?;
+                self.push(Object::Integer(result))
+            }
+            Object::Float(float) => self.push(Object::Float(-float)),
+            _ => Err(arithmetic::prefix_error("-", &operand)),
         }
     }
 
@@ -327,13 +554,33 @@ impl<'a> VM<'a> {
     fn build_hash(&mut self, start_index: usize, end_index: usize) -> Result<Object, String> {
         let mut drain = self.stack.drain(start_index..end_index);
 
-        let mut pairs = HashMap::new();
+        let mut hash = object::Hash::default();
         while let (Some(key), Some(value)) = (drain.next(), drain.next()) {
             let hash_key = hash_key_of(&key)?;
             let pair = object::HashPair { key, value };
-            pairs.insert(hash_key, pair);
+            hash.insert(hash_key, pair);
         }
-        Ok(Object::Hash(object::Hash { pairs }))
+        Ok(Object::Hash(hash))
+    }
+
+    // container と index と値を下ろして、代入した値と書き換えた container を積む
+    fn execute_set_index(&mut self, num_indices: usize, op: u8) -> Result<(), String> {
+        let value = self.pop()?;
+        let indices = self.stack.split_off(self.stack_start(num_indices)?);
+        self.sp -= num_indices;
+        let container = self.pop()?;
+
+        let (container, value) = object::update_index(container, &indices, |current| {
+            if op == 0 {
+                return Ok(value);
+            }
+            self.push(current)?;
+            self.push(value)?;
+            self.execute_binary_operation(Opcode::from(op))?;
+            self.pop()
+        })?;
+        self.push(value)?;
+        self.push(container)
     }
 
     fn execute_index_expression(&mut self, left: Object, index: Object) -> Result<(), String> {
@@ -341,8 +588,11 @@ impl<'a> VM<'a> {
             (Object::Array(array), Object::Integer(integer)) => {
                 self.execute_array_index(array, integer)
             }
+            (Object::String(string), Object::Integer(integer)) => {
+                self.push(string::char_at(&string, integer))
+            }
             (Object::Hash(hash), i) => self.execute_hash_index(hash, i),
-            (l, _) => Err(format!("index operator not supported: {}", l)),
+            (l, _) => Err(format!("index operator not supported: {}", l.type_name())),
         }
     }
 
@@ -355,7 +605,7 @@ impl<'a> VM<'a> {
 
     fn execute_hash_index(&mut self, hash: object::Hash, index: Object) -> Result<(), String> {
         let key = hash_key_of(&index)?;
-        self.push(match hash.pairs.get(&key) {
+        self.push(match hash.get(&key) {
             Some(pair) => pair.value.clone(),
             None => NULL,
         })
@@ -381,6 +631,11 @@ impl<'a> VM<'a> {
         &mut self.frames[self.frame_index - 1]
     }
 
+    fn current_position(&self) -> Option<Position> {
+        let frame = &self.frames[self.frame_index - 1];
+        frame.cl.func.position_at(frame.ip)
+    }
+
     fn push_frame(&mut self, frame: Frame) {
         self.frames.push(frame);
         self.frame_index += 1;
@@ -400,6 +655,58 @@ impl<'a> VM<'a> {
     }
 }
 
+// エラーの文言を evaluator とそろえるため、opcode を元の演算子に戻す
+fn operator_of(op: Opcode) -> &'static str {
+    match op {
+        Opcode::OpAdd => "+",
+        Opcode::OpSub => "-",
+        Opcode::OpMul => "*",
+        Opcode::OpDiv => "/",
+        Opcode::OpMod => "%",
+        Opcode::OpEqual => "==",
+        Opcode::OpNotEqual => "!=",
+        Opcode::OpGreaterThan => ">",
+        Opcode::OpGreaterThanOrEqual => ">=",
+        Opcode::OpLessThan => "<",
+        Opcode::OpLessThanOrEqual => "<=",
+        _ => "?",
+    }
+}
+
+impl<'a> Caller for VM<'a> {
+    // 関数を積んで、その frame が返るまで実行する
+    fn call(&mut self, func: Object, args: Vec<Object>) -> Result<Object, String> {
+        match func {
+            Object::Closure(cl) => {
+                let sp = self.sp;
+                let depth = self.frame_index;
+                let num_args = args.len();
+                self.push(Object::Closure(cl.clone()))?;
+                for arg in args {
+                    self.push(arg)?;
+                }
+                let result = self
+                    .call_closure(cl, num_args)
+                    .and_then(|_| self.execute(depth));
+                if let Err(message) = result {
+                    // 途中で止まった frame と stack を呼び出し前に戻す
+                    self.frames.truncate(depth);
+                    self.frame_index = depth;
+                    self.sp = sp;
+                    self.stack.truncate(sp);
+                    return Err(message);
+                }
+                self.pop()
+            }
+            Object::Builtin(builtin) => match (builtin.func)(self, args) {
+                Object::Error(message) => Err(message),
+                obj => Ok(obj),
+            },
+            func => Err(format!("not a function: {}", func.type_name())),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::ast::Program;
@@ -430,8 +737,38 @@ mod tests {
             ("-10", -10),
             ("-50 + 100 + -50", 0),
             ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("7 % 3", 1),
+            ("-7 % 3", -1),
+            ("2 + 10 % 4 * 3", 8),
+        ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let tests = vec![
+            ("1.5", 1.5),
+            ("0.1 + 0.2", 0.1 + 0.2),
+            ("1 + 0.5", 1.5),
+            ("0.5 * 4", 2.0),
+            ("1 / 4.0", 0.25),
+            ("-2.5 - 1", -3.5),
+            ("float(3)", 3.0),
+            ("float(\"2.25\")", 2.25),
         ];
+        run_vm_tests(tests);
 
+        let tests = vec![
+            ("1 < 1.5", true),
+            ("2.5 > 2", true),
+            ("1 == 1.0", true),
+            ("1.0 != 1", false),
+            ("0.1 + 0.2 == 0.3", false),
+        ];
+        run_vm_tests(tests);
+
+        let tests = vec![("int(2.9)", 2), ("int(-2.9)", -2), ("int(\" 42 \")", 42)];
         run_vm_tests(tests);
     }
 
@@ -464,8 +801,33 @@ mod tests {
             ("!!false", false),
             ("!!5", true),
             ("!(if (false) { 5; })", true),
+            ("1 <= 2", true),
+            ("2 <= 2", true),
+            ("3 <= 2", false),
+            ("1 >= 2", false),
+            ("2 >= 2", true),
+            ("1.5 >= 1", true),
+            ("1 <= 0.5", false),
+            ("true && true", true),
+            ("true && false", false),
+            ("false && true", false),
+            ("false || true", true),
+            ("false || false", false),
+            ("1 < 2 && 2 < 3", true),
+            ("1 > 2 || 2 > 3", false),
+        ];
+        run_vm_tests(tests);
+
+        // 右辺は評価されない
+        let tests = vec![
+            ("true || 1 / 0", true),
+            ("false && 1 / 0", false),
+            ("let f = fn() { 0 / 0 }; 1 > 2 && f()", false),
         ];
         run_vm_tests(tests);
+
+        let tests = vec![("1 && 5", 5), ("false || 7", 7), ("false && 3 || 4", 4)];
+        run_vm_tests(tests);
     }
 
     #[test]
@@ -489,6 +851,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_loops() {
+        let tests = vec![
+            (
+                "let f = fn() { let s = 0; for (x in [1, 2, 3]) { let s = s + x; } s }; f()",
+                6,
+            ),
+            (
+                "let s = 0; for (x in [1, 2, 3, 4, 5]) { if (x == 2) { continue; } if (x == 4) { break; } let s = s + x; }; s",
+                4,
+            ),
+            (
+                "let s = 0; for (i, x in [10, 20]) { let s = s + i * x; }; s",
+                20,
+            ),
+            (
+                r#"let s = 0; for (k, v in {"b": 2, "a": 1}) { let s = s * 10 + v; }; s"#,
+                21,
+            ),
+            (
+                "let i = 0; while (i < 3) { let i = i + 1; }; i",
+                3,
+            ),
+            (
+                "let f = fn() { while (true) { return 5; } }; f()",
+                5,
+            ),
+            (
+                "let s = 0; for (x in [1, 2]) { for (y in [10, 20, 30]) { if (y == 20) { break; } let s = s + x * y; } }; s",
+                30,
+            ),
+            // 再帰しないので MAX_FRAMES を超える長さでも回せる
+            (
+                "let f = fn() { let i = 0; let xs = []; while (i < 2000) { let xs = push(xs, i); let i = i + 1; } let s = 0; for (x in xs) { let s = s + x; } s }; f()",
+                1999000,
+            ),
+        ];
+
+        run_vm_tests(tests);
+
+        let tests = vec![
+            ("let f = fn() { for (x in []) { x } }; f()", NULL),
+            ("if (true) { let x = 1; }", NULL),
+        ];
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn test_assignments() {
+        let tests = vec![
+            ("let x = 1; x = x + 1; x", 2),
+            ("let x = 1; x += 10; x -= 2; x *= 3; x /= 2; x %= 7; x", 6),
+            ("let x = 0; let y = 0; x = y = 3; x + y", 6),
+            ("let x = 1; (x = 5) + x", 10),
+            ("let f = fn() { let i = 0; i += 4; i }; f()", 4),
+            ("let xs = [1, 2]; xs[1] = 5; xs[0] + xs[1]", 6),
+            ("let xs = [1, [2, 3]]; xs[1][0] += 40; xs[1][0]", 42),
+            ("let xs = [1]; xs[0] += 1", 2),
+            // 配列とハッシュは値なので、代入しても他の変数からは見えない
+            ("let xs = [1]; let ys = xs; ys[0] = 9; xs[0]", 1),
+            (
+                "let xs = [1]; let f = fn(a) { a[0] = 9; a }; f(xs)[0] + xs[0]",
+                10,
+            ),
+            (
+                r#"let h = {"a": 1}; h["a"] += 1; h["b"] = 5; h["a"] + h["b"]"#,
+                7,
+            ),
+            (
+                r#"let h = {}; h["xs"] = [0]; h["xs"][0] = 3; h["xs"][0]"#,
+                3,
+            ),
+            ("let c = 0; let inc = fn() { c += 1 }; inc(); inc(); c", 2),
+        ];
+
+        run_vm_tests(tests);
+    }
+
     #[test]
     fn test_global_let_statements() {
         let tests = vec![
@@ -712,7 +1152,224 @@ mod tests {
         run_vm_tests(tests);
     }
 
-    fn run_vm_tests<T: Expectable>(tests: Vec<(&str, T)>) {
+    #[test]
+    fn test_builtin_functions() {
+        {
+            let tests = vec![
+                (r#"len("")"#, 0),
+                (r#"len("four")"#, 4),
+                (r#"len("hello world")"#, 11),
+                ("len([1, 2, 3])", 3),
+                ("len([])", 0),
+                ("first([1, 2, 3])", 1),
+                ("last([1, 2, 3])", 3),
+            ];
+            run_vm_tests(tests);
+        }
+        {
+            let tests = vec![
+                ("first([])", NULL),
+                ("last([])", NULL),
+                ("rest([])", NULL),
+                (r#"puts("hello", "world!")"#, NULL),
+                (
+                    "len(1)",
+                    Object::Error("argument to `len` not supported, got Integer(1)".to_string()),
+                ),
+                (
+                    r#"len("one", "two")"#,
+                    Object::Error("wrong number of arguments. got=2, want=1".to_string()),
+                ),
+                (
+                    "first(1)",
+                    Object::Error("argument to `first` must be ARRAY, got Integer(1)".to_string()),
+                ),
+                (
+                    "last(1)",
+                    Object::Error("argument to `last` must be ARRAY, got Integer(1)".to_string()),
+                ),
+                (
+                    "push(1, 1)",
+                    Object::Error("argument to `push` must be ARRAY, got Integer(1)".to_string()),
+                ),
+            ];
+            run_vm_tests(tests);
+        }
+        {
+            let tests = vec![("rest([1, 2, 3])", vec![2, 3]), ("push([], 1)", vec![1])];
+            run_vm_tests(tests);
+        }
+        {
+            let tests = vec![(
+                r#"
+                let lenOfEmpty = fn() { len([]) };
+                lenOfEmpty() + len([1, 2]);
+                "#,
+                2,
+            )];
+            run_vm_tests(tests);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let tests = vec![
+            (
+                r#"
+                let newClosure = fn() { let a = 99; fn() { a; }; };
+                let closure = newClosure();
+                closure();
+                "#,
+                99,
+            ),
+            (
+                r#"
+                let newAdder = fn() {
+                    let a = 1;
+                    let b = 2;
+                    fn() { let c = 3; fn() { a + b + c }; };
+                };
+                let adder = newAdder();
+                adder()();
+                "#,
+                6,
+            ),
+            (
+                r#"
+                let global = 10;
+                let outer = fn() {
+                    let a = 20;
+                    let middle = fn() {
+                        let b = 30;
+                        fn() { global + a + b; };
+                    };
+                    middle();
+                };
+                outer()();
+                "#,
+                60,
+            ),
+            (
+                r#"
+                let newClosure = fn() {
+                    let a = 1;
+                    let first = fn() { a };
+                    let second = fn() { first() + a };
+                    second;
+                };
+                newClosure()();
+                "#,
+                2,
+            ),
+        ];
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn test_calling_functions_with_arguments_and_bindings() {
+        let tests = vec![
+            (
+                r#"
+                let identity = fn(a) { a; };
+                identity(4);
+                "#,
+                4,
+            ),
+            (
+                r#"
+                let sum = fn(a, b) { a + b; };
+                sum(1, 2);
+                "#,
+                3,
+            ),
+            (
+                r#"
+                let sum = fn(a, b) {
+                    let c = a + b;
+                    c;
+                };
+                sum(1, 2);
+                "#,
+                3,
+            ),
+            (
+                r#"
+                let sum = fn(a, b) {
+                    let c = a + b;
+                    c;
+                };
+                sum(1, 2) + sum(3, 4);
+                "#,
+                10,
+            ),
+            (
+                r#"
+                let sum = fn(a, b) {
+                    let c = a + b;
+                    c;
+                };
+                let outer = fn() {
+                    sum(1, 2) + sum(3, 4);
+                };
+                outer();
+                "#,
+                10,
+            ),
+            (
+                r#"
+                let globalNum = 10;
+
+                let sum = fn(a, b) {
+                    let c = a + b;
+                    c + globalNum;
+                };
+
+                let outer = fn() {
+                    sum(1, 2) + sum(3, 4) + globalNum;
+                };
+
+                outer() + globalNum;
+                "#,
+                50,
+            ),
+            (
+                r#"
+                let newAdder = fn(a, b) {
+                    fn(c) { a + b + c };
+                };
+                let adder = newAdder(1, 2);
+                adder(8);
+                "#,
+                11,
+            ),
+            (
+                r#"
+                let length = fn(a) { let l = len(a); l };
+                length([1, 2, 3]);
+                "#,
+                3,
+            ),
+        ];
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn test_calling_functions_with_wrong_arguments() {
+        let tests = vec![
+            (
+                "fn() { 1; }(1);",
+                "wrong number of arguments: want=0, got=1",
+            ),
+            (
+                "fn(a) { a; }();",
+                "wrong number of arguments: want=1, got=0",
+            ),
+            (
+                "fn(a, b) { a + b; }(1);",
+                "wrong number of arguments: want=2, got=1",
+            ),
+        ];
+
         for (input, expected) in tests {
             let program = parse(input.to_string());
             let mut symbol_table_stack = new_symbol_table_stack();
@@ -724,18 +1381,185 @@ mod tests {
 
             let mut globals = new_globals_store();
             let mut vm = VM::new_with_globals_store(comp.bytecode(), &mut globals);
-            if let Err(err) = vm.run() {
-                assert!(false, "vm error: {}", err);
+            match vm.run() {
+                Ok(_) => assert!(false, "expected VM error but resulted in none."),
+                Err(err) => assert_eq!(err.message, expected),
             }
+        }
+    }
+
+    #[test]
+    fn test_recursive_functions() {
+        let tests = vec![
+            (
+                r#"
+                let countDown = fn(x) {
+                    if (x == 0) {
+                        return 0;
+                    } else {
+                        countDown(x - 1);
+                    }
+                };
+                countDown(1);
+                "#,
+                0,
+            ),
+            (
+                r#"
+                let countDown = fn(x) {
+                    if (x == 0) {
+                        return 0;
+                    } else {
+                        countDown(x - 1);
+                    }
+                };
+                let wrapper = fn() {
+                    countDown(1);
+                };
+                wrapper();
+                "#,
+                0,
+            ),
+            (
+                r#"
+                let wrapper = fn() {
+                    let countDown = fn(x) {
+                        if (x == 0) {
+                            return 0;
+                        } else {
+                            countDown(x - 1);
+                        }
+                    };
+                    countDown(1);
+                };
+                wrapper();
+                "#,
+                0,
+            ),
+            (
+                r#"
+                let fibonacci = fn(x) {
+                    if (x == 0) {
+                        return 0;
+                    } else {
+                        if (x == 1) {
+                            return 1;
+                        } else {
+                            fibonacci(x - 1) + fibonacci(x - 2);
+                        }
+                    }
+                };
+                fibonacci(15);
+                "#,
+                610,
+            ),
+        ];
+        run_vm_tests(tests);
+    }
 
-            if let Some(stack_elem) = vm.last_popped_stack_elem {
-                test_expected_object(&expected, &stack_elem);
-            } else {
-                assert!(false, "stack_elem is None.");
+    #[test]
+    fn test_mutually_recursive_functions() {
+        let tests = vec![
+            (
+                r#"
+                let isEven = fn(n) { if (n == 0) { true } else { isOdd(n - 1) } };
+                let isOdd = fn(n) { if (n == 0) { false } else { isEven(n - 1) } };
+                isEven(10);
+                "#,
+                true,
+            ),
+            (
+                r#"
+                let isEven = fn(n) { if (n == 0) { true } else { isOdd(n - 1) } };
+                let seed = 7;
+                let isOdd = fn(n) { if (n == 0) { false } else { isEven(n - 1) } };
+                isOdd(seed);
+                "#,
+                true,
+            ),
+        ];
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn test_runtime_error_positions() {
+        let tests = vec![
+            ("1 + true", Position::new(1, 3)),
+            ("let f = fn(a) { a };\nf(1, 2)", Position::new(2, 2)),
+            ("let f = fn() {\n  -true\n};\nf()", Position::new(2, 3)),
+            ("let x = 1;\nlet y = [1][0];\nx(y)", Position::new(3, 2)),
+            ("let x = 0;\n10 / x", Position::new(2, 4)),
+            ("9223372036854775807 + 1", Position::new(1, 21)),
+            ("for (x in 1) { x }", Position::new(1, 1)),
+            ("let xs = [1];\nxs[1] = 2", Position::new(2, 7)),
+            // callback の中のエラーは builtin を呼んだ位置で報告する
+            ("let f = fn(x) { x / 0 };\nmap([1], f)", Position::new(2, 4)),
+            ("len(1)", Position::new(1, 4)),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input.to_string());
+            let mut symbol_table_stack = new_symbol_table_stack();
+            let mut constants = new_constants();
+            let mut comp = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+            if let Err(err) = comp.compile(program) {
+                assert!(false, "compile error: {}", err);
+            }
+
+            let mut globals = new_globals_store();
+            let mut vm = VM::new_with_globals_store(comp.bytecode(), &mut globals);
+            match vm.run() {
+                Ok(_) => assert!(false, "expected VM error but resulted in none."),
+                Err(err) => assert_eq!(err.pos, Some(expected), "{}", input),
             }
         }
     }
 
+    #[test]
+    fn test_callback_errors() {
+        // callback の中の実行時エラーで、後ろの文を実行せずに止まる
+        let input = "let done = false;\nmap([1], fn(x) { x / 0 });\ndone = true;";
+        let program = parse(input.to_string());
+        let mut symbol_table_stack = new_symbol_table_stack();
+        let mut constants = new_constants();
+        let mut comp = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+        if let Err(err) = comp.compile(program) {
+            assert!(false, "compile error: {}", err);
+        }
+
+        let mut globals = new_globals_store();
+        let mut vm = VM::new_with_globals_store(comp.bytecode(), &mut globals);
+        assert_eq!(
+            vm.run(),
+            Err(RuntimeError {
+                message: "division by zero: 1 / 0".to_string(),
+                pos: Some(Position::new(2, 4)),
+            })
+        );
+        assert_eq!(globals[0], FALSE);
+    }
+
+    fn run_vm_tests<T: Expectable>(tests: Vec<(&str, T)>) {
+        for (input, expected) in tests {
+            let program = parse(input.to_string());
+            let mut symbol_table_stack = new_symbol_table_stack();
+            let mut constants = new_constants();
+            let mut comp = Compiler::new_with_state(&mut symbol_table_stack, &mut constants);
+            if let Err(err) = comp.compile(program) {
+                assert!(false, "compile error: {}", err);
+            }
+
+            let mut globals = new_globals_store();
+            let mut vm = VM::new_with_globals_store(comp.bytecode(), &mut globals);
+            // 実行時エラーは evaluator のテストに合わせて Error object として比べる
+            let result = match vm.run() {
+                Ok(_) => vm.last_popped_stack_elem.expect("stack_elem is None."),
+                Err(err) => Object::Error(err.message),
+            };
+            test_expected_object(&expected, &result);
+        }
+    }
+
     fn parse(input: String) -> Program {
         let l = Lexer::new(&input);
         let mut p = Parser::new(l);